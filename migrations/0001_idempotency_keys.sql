@@ -0,0 +1,10 @@
+-- Responses stored for replaying retried mutating requests
+-- customerId is 0 for requests that are not tied to a customer
+CREATE TABLE IdempotencyKeys (
+    idempotencyKey TEXT NOT NULL,
+    customerId INTEGER NOT NULL,
+    requestHash TEXT NOT NULL,
+    response TEXT NOT NULL,
+    createdAt INTEGER NOT NULL,
+    PRIMARY KEY (idempotencyKey, customerId)
+);
@@ -0,0 +1,6 @@
+-- Orders placed before this migration have no recorded price or timestamps
+ALTER TABLE PurchaseOrders ADD COLUMN pricePaid REAL;
+ALTER TABLE PurchaseOrders ADD COLUMN createdAt TEXT;
+ALTER TABLE PurchaseOrders ADD COLUMN shippedAt TEXT;
+
+CREATE INDEX PurchaseOrdersByCustomer ON PurchaseOrders (customerId, createdAt);
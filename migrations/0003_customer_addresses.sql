@@ -0,0 +1,23 @@
+-- Structured shipping addresses, a customer may have several but only one default
+-- Customers.shippingAddress is kept as a formatted copy of the default address
+CREATE TABLE CustomerAddresses (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    customerId INTEGER NOT NULL REFERENCES Customers(id),
+    line1 TEXT NOT NULL,
+    line2 TEXT,
+    city TEXT NOT NULL,
+    region TEXT,
+    postalCode TEXT,
+    country TEXT NOT NULL,
+    isDefault INTEGER NOT NULL
+);
+
+CREATE INDEX CustomerAddressesByCustomer ON CustomerAddresses (customerId);
+
+-- Free text addresses can't be split reliably, so they are carried over whole as line1
+INSERT INTO CustomerAddresses (customerId, line1, city, country, isDefault)
+    SELECT id, shippingAddress, '', '', 1 FROM Customers;
+
+-- Snapshot of where each order was sent, unaffected by later address changes
+ALTER TABLE PurchaseOrders ADD COLUMN addressId INTEGER;
+ALTER TABLE PurchaseOrders ADD COLUMN shippingAddress TEXT;
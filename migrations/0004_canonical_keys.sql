@@ -0,0 +1,8 @@
+-- Case-folded, compatibility-normalized forms used to look books and customers up,
+-- filled in for existing rows after this migration runs
+ALTER TABLE Books ADD COLUMN titleKey TEXT;
+ALTER TABLE Books ADD COLUMN authorKey TEXT;
+ALTER TABLE Customers ADD COLUMN nameKey TEXT;
+
+CREATE INDEX BooksByKey ON Books (titleKey, authorKey);
+CREATE INDEX CustomersByNameKey ON Customers (nameKey);
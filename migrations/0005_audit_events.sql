@@ -0,0 +1,18 @@
+-- Business events, each row's hash covers its fields and the previous row's hash,
+-- so editing or deleting a row breaks the chain from that row on. See db::audit::verify_chain
+CREATE TABLE AuditEvents (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    entityId INTEGER NOT NULL,
+    -- JSON, NULL when the entity didn't exist before or doesn't after
+    beforeValue TEXT,
+    afterValue TEXT,
+    requestId TEXT,
+    createdAt TEXT NOT NULL,
+    previousHash TEXT NOT NULL,
+    hash TEXT NOT NULL
+);
+
+CREATE INDEX AuditEventsByEntity ON AuditEvents (entity, entityId);
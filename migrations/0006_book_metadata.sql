@@ -0,0 +1,7 @@
+-- Metadata from publishers' ONIX feeds, see onix.rs. NULL for books added through the API.
+-- availability is one of available, not_yet_available, temporarily_unavailable or unavailable
+ALTER TABLE Books ADD COLUMN isbn TEXT;
+ALTER TABLE Books ADD COLUMN availability TEXT;
+ALTER TABLE Books ADD COLUMN description TEXT;
+
+CREATE UNIQUE INDEX BooksByIsbn ON Books (isbn);
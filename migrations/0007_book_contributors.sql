@@ -0,0 +1,20 @@
+-- People credited on books, matched by nameKey like Books.authorKey.
+-- Books.author is kept as the authors' names joined by ", ", and existing books' authors are split on commas after this migration runs
+CREATE TABLE Authors (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    nameKey TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX AuthorsByNameKey ON Authors (nameKey);
+
+-- role is one of author, editor, translator or illustrator, position orders the people in a role
+CREATE TABLE BookContributors (
+    bookId INTEGER NOT NULL REFERENCES Books(id),
+    authorId INTEGER NOT NULL REFERENCES Authors(id),
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (bookId, authorId, role)
+);
+
+CREATE INDEX BookContributorsByAuthor ON BookContributors (authorId);
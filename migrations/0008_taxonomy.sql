@@ -0,0 +1,34 @@
+-- A tree of categories, top-level ones have no parentId. Siblings can't share a name, matched by nameKey like Books.titleKey
+CREATE TABLE Categories (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    nameKey TEXT NOT NULL,
+    parentId INTEGER REFERENCES Categories(id)
+);
+
+CREATE UNIQUE INDEX CategoriesByParentAndName ON Categories (ifnull(parentId, 0), nameKey);
+
+CREATE TABLE BookCategories (
+    bookId INTEGER NOT NULL REFERENCES Books(id),
+    categoryId INTEGER NOT NULL REFERENCES Categories(id),
+    PRIMARY KEY (bookId, categoryId)
+);
+
+CREATE INDEX BookCategoriesByCategory ON BookCategories (categoryId);
+
+-- Free-form labels, a tag is removed once no book has it
+CREATE TABLE Tags (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    nameKey TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX TagsByNameKey ON Tags (nameKey);
+
+CREATE TABLE BookTags (
+    bookId INTEGER NOT NULL REFERENCES Books(id),
+    tagId INTEGER NOT NULL REFERENCES Tags(id),
+    PRIMARY KEY (bookId, tagId)
+);
+
+CREATE INDEX BookTagsByTag ON BookTags (tagId);
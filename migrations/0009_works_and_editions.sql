@@ -0,0 +1,37 @@
+-- Each row of Books is now an edition of a work: the same title and author in a given format, with its own price and stock.
+-- Books keeps its title and author, which are always those of its work, and existing books become the first edition of theirs.
+CREATE TABLE Series (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    nameKey TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX SeriesByNameKey ON Series (nameKey);
+
+CREATE TABLE Works (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    author TEXT NOT NULL,
+    titleKey TEXT NOT NULL,
+    authorKey TEXT NOT NULL,
+    seriesId INTEGER REFERENCES Series(id),
+    seriesPosition INTEGER
+);
+
+CREATE UNIQUE INDEX WorksByKey ON Works (titleKey, authorKey);
+CREATE INDEX WorksBySeries ON Works (seriesId, seriesPosition);
+
+-- format is one of hardcover, paperback, ebook or audiobook, publicationDate of form YYYY-MM-DD,
+-- and stock the copies on hand, NULL when it isn't counted, as for ebooks
+ALTER TABLE Books ADD COLUMN workId INTEGER REFERENCES Works(id);
+ALTER TABLE Books ADD COLUMN format TEXT;
+ALTER TABLE Books ADD COLUMN publisher TEXT;
+ALTER TABLE Books ADD COLUMN publicationDate TEXT;
+ALTER TABLE Books ADD COLUMN stock INTEGER;
+
+CREATE INDEX BooksByWork ON Books (workId);
+
+INSERT INTO Works (title, author, titleKey, authorKey)
+    SELECT title, author, titleKey, authorKey FROM Books WHERE id IN (SELECT MIN(id) FROM Books GROUP BY titleKey, authorKey) ORDER BY id;
+
+UPDATE Books SET workId = (SELECT id FROM Works WHERE Works.titleKey = Books.titleKey AND Works.authorKey = Books.authorKey);
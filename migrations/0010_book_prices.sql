@@ -0,0 +1,16 @@
+-- Each edition's prices over time. A price applies from effectiveFrom until effectiveTo, or on and on when that is NULL,
+-- and a later effectiveFrom is a scheduled change. Times are UTC of form YYYY-MM-DD HH:MM:SS, like PurchaseOrders.createdAt.
+-- Books.price keeps the price the book was last stored with, see prices::current_price for the one that applies now.
+CREATE TABLE BookPrices (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    bookId INTEGER NOT NULL REFERENCES Books(id),
+    price REAL NOT NULL,
+    effectiveFrom TEXT NOT NULL,
+    effectiveTo TEXT
+);
+
+CREATE UNIQUE INDEX BookPricesByBook ON BookPrices (bookId, effectiveFrom);
+
+-- What books cost before this migration is unknown, so their current price starts now.
+-- Orders placed before keep the price they were charged in PurchaseOrders.pricePaid.
+INSERT INTO BookPrices (bookId, price, effectiveFrom) SELECT id, price, datetime('now') FROM Books;
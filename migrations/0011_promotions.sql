@@ -0,0 +1,45 @@
+-- Discounts taken off orders as they are priced, see promotions.rs. kind is one of percentage, taking percent off,
+-- fixed, taking amount off, or bundle, making freeQuantity of every buyQuantity + freeQuantity copies free.
+-- Promotions with a code only apply to orders giving it, those without to every order they are eligible for.
+-- Codes are stored upper-case. bookId, authorId, categoryId and customerId each narrow down who and what is eligible when set.
+-- usageLimit counts every order that got the promotion, perCustomerLimit those of each customer. Times are UTC like BookPrices'.
+CREATE TABLE Promotions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    code TEXT,
+    kind TEXT NOT NULL,
+    percent REAL,
+    amount REAL,
+    buyQuantity INTEGER,
+    freeQuantity INTEGER,
+    minimumSubtotal REAL,
+    bookId INTEGER REFERENCES Books(id),
+    authorId INTEGER REFERENCES Authors(id),
+    categoryId INTEGER REFERENCES Categories(id),
+    customerId INTEGER REFERENCES Customers(id),
+    usageLimit INTEGER,
+    perCustomerLimit INTEGER,
+    startsAt TEXT,
+    endsAt TEXT
+);
+
+CREATE UNIQUE INDEX PromotionsByCode ON Promotions (code);
+
+-- An order is now of one or more copies of a book. subtotal is their price before discounts, and pricePaid what was charged.
+ALTER TABLE PurchaseOrders ADD COLUMN quantity INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE PurchaseOrders ADD COLUMN subtotal REAL;
+
+UPDATE PurchaseOrders SET subtotal = pricePaid;
+
+-- The discounts each order got, with the amount taken off. The promotion's name and code are copied as they were.
+CREATE TABLE OrderDiscounts (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    orderId INTEGER NOT NULL REFERENCES PurchaseOrders(id),
+    promotionId INTEGER NOT NULL REFERENCES Promotions(id),
+    name TEXT NOT NULL,
+    code TEXT,
+    amount REAL NOT NULL
+);
+
+CREATE INDEX OrderDiscountsByOrder ON OrderDiscounts (orderId);
+CREATE INDEX OrderDiscountsByPromotion ON OrderDiscounts (promotionId, orderId);
@@ -0,0 +1,16 @@
+-- The tax charged on each line of an order, see tax.rs. line is "book" for the book's copies. rate is a percentage of taxable,
+-- the line's price after discounts, and included is 1 when amount was part of the price rather than added to pricePaid.
+-- jurisdiction is the destination's country code, with its region code as in US-NY when the region's rate applied.
+-- Orders from before tax was charged have no rows.
+CREATE TABLE OrderTaxes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    orderId INTEGER NOT NULL REFERENCES PurchaseOrders(id),
+    line TEXT NOT NULL,
+    jurisdiction TEXT NOT NULL,
+    rate REAL NOT NULL,
+    taxable REAL NOT NULL,
+    amount REAL NOT NULL,
+    included INTEGER NOT NULL
+);
+
+CREATE INDEX OrderTaxesByOrder ON OrderTaxes (orderId);
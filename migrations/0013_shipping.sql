@@ -0,0 +1,9 @@
+-- How each order ships, see shipping.rs. shippingMethod is the method's key in the rules file and shippingName its name when
+-- ordered, shippingCost is part of pricePaid, and deliveryFrom and deliveryBy are the estimated delivery dates, of form YYYY-MM-DD.
+-- Orders from before shipping was charged, or placed without shipping rules, have them NULL.
+-- Tax on the shipping cost is kept in OrderTaxes with the line "shipping".
+ALTER TABLE PurchaseOrders ADD COLUMN shippingMethod TEXT;
+ALTER TABLE PurchaseOrders ADD COLUMN shippingName TEXT;
+ALTER TABLE PurchaseOrders ADD COLUMN shippingCost REAL;
+ALTER TABLE PurchaseOrders ADD COLUMN deliveryFrom TEXT;
+ALTER TABLE PurchaseOrders ADD COLUMN deliveryBy TEXT;
@@ -0,0 +1,19 @@
+-- Keys are reserved before the handler runs, so a concurrent retry sees the request in progress rather
+-- than running it again. response is NULL until the handler succeeds.
+-- Keys are scoped by customer, as customer:<id>, or for requests not tied to one by client, as ip:<address>
+CREATE TABLE IdempotencyReservations (
+    idempotencyKey TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    requestHash TEXT NOT NULL,
+    response TEXT,
+    createdAt INTEGER NOT NULL,
+    PRIMARY KEY (idempotencyKey, scope)
+);
+
+-- Earlier keys of requests not tied to a customer can't be traced to a client, so they're kept apart
+INSERT INTO IdempotencyReservations (idempotencyKey, scope, requestHash, response, createdAt)
+SELECT idempotencyKey, CASE customerId WHEN 0 THEN 'ip:unknown' ELSE 'customer:' || customerId END, requestHash, response, createdAt
+FROM IdempotencyKeys;
+
+DROP TABLE IdempotencyKeys;
+ALTER TABLE IdempotencyReservations RENAME TO IdempotencyKeys;
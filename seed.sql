@@ -0,0 +1,8 @@
+-- The series of the catalog init.sql starts with, added once a new database is migrated, see db::connect
+INSERT INTO Series (name, nameKey) VALUES ('Foundation', 'foundation');
+INSERT INTO Series (name, nameKey) VALUES ('Culture', 'culture');
+
+UPDATE Works SET seriesId = (SELECT id FROM Series WHERE nameKey = 'foundation'), seriesPosition = 1
+    WHERE titleKey = 'foundation' AND authorKey = 'isaac asimov';
+UPDATE Works SET seriesId = (SELECT id FROM Series WHERE nameKey = 'culture'), seriesPosition = 2
+    WHERE titleKey = 'the player of games' AND authorKey = 'iain m. banks';
@@ -0,0 +1,325 @@
+use regex::Regex;
+use log::error;
+
+use crate::db::addresses::AddressFields;
+use crate::redact::{field_value, pii};
+use crate::validation::FieldError;
+
+// Countries with their own postal code and region rules, anything else gets the generic ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Country {
+    US,
+    CA,
+    GB,
+    DE,
+    Other,
+}
+
+impl Country {
+    fn from_code(code: &str) -> Country {
+        match code {
+            "US" => Country::US,
+            "CA" => Country::CA,
+            "GB" => Country::GB,
+            "DE" => Country::DE,
+            _ => Country::Other,
+        }
+    }
+
+    // Postal codes are required where the rules are known and checked against the national format
+    fn postal_code_pattern(&self) -> &'static str {
+        match self {
+            Country::US => r"^\d{5}(-\d{4})?$",
+            Country::CA => r"^[ABCEGHJ-NPRSTVXY]\d[ABCEGHJ-NPRSTV-Z] \d[ABCEGHJ-NPRSTV-Z]\d$",
+            Country::GB => r"^(GIR 0AA|[A-Z]{1,2}\d[A-Z\d]? \d[A-Z]{2})$",
+            Country::DE => r"^\d{5}$",
+            Country::Other => r"^[A-Z\d][A-Z\d -]{0,9}$",
+        }
+    }
+
+    fn postal_code_example(&self) -> &'static str {
+        match self {
+            Country::US => "12345 or 12345-6789",
+            Country::CA => "K1A 0B1",
+            Country::GB => "SW1A 1AA",
+            Country::DE => "10115",
+            Country::Other => "up to 10 letters, digits, spaces or hyphens",
+        }
+    }
+
+    fn regions(&self) -> Option<&'static [&'static str]> {
+        match self {
+            Country::US => Some(US_STATES),
+            Country::CA => Some(CA_PROVINCES),
+            _ => None,
+        }
+    }
+
+    fn abbreviate(&self, line: &str) -> String {
+        match self {
+            Country::DE => abbreviate_suffixes(line, DE_ABBREVIATIONS),
+            _ => abbreviate_words(line, EN_ABBREVIATIONS),
+        }
+    }
+}
+
+const US_STATES: &[&str] = &[
+    "AL", "AK", "AZ", "AR", "CA", "CO", "CT", "DE", "DC", "FL", "GA", "HI", "ID", "IL", "IN", "IA", "KS",
+    "KY", "LA", "ME", "MD", "MA", "MI", "MN", "MS", "MO", "MT", "NE", "NV", "NH", "NJ", "NM", "NY", "NC",
+    "ND", "OH", "OK", "OR", "PA", "RI", "SC", "SD", "TN", "TX", "UT", "VT", "VA", "WA", "WV", "WI", "WY",
+    "AS", "GU", "MP", "PR", "VI", "AA", "AE", "AP",
+];
+
+const CA_PROVINCES: &[&str] = &["AB", "BC", "MB", "NB", "NL", "NS", "NT", "NU", "ON", "PE", "QC", "SK", "YT"];
+
+// Whole words in either form are replaced case-insensitively, following USPS street suffixes
+const EN_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("street", "St"), ("avenue", "Ave"), ("road", "Rd"), ("boulevard", "Blvd"), ("drive", "Dr"),
+    ("lane", "Ln"), ("court", "Ct"), ("place", "Pl"), ("terrace", "Ter"), ("highway", "Hwy"),
+    ("parkway", "Pkwy"), ("circle", "Cir"), ("square", "Sq"), ("apartment", "Apt"), ("suite", "Ste"),
+    ("building", "Bldg"), ("floor", "Fl"),
+];
+
+// German street names are compounds, so the suffix is replaced ("Hauptstraße" becomes "Hauptstr.")
+const DE_ABBREVIATIONS: &[(&str, &str)] = &[("straße", "str."), ("strasse", "str.")];
+
+// Names of the form "United Kingdom" are accepted alongside ISO 3166 alpha-2 codes
+const COUNTRY_NAMES: &[(&str, &str)] = &[
+    ("USA", "US"), ("UNITED STATES", "US"), ("UNITED STATES OF AMERICA", "US"), ("CANADA", "CA"),
+    ("UK", "GB"), ("UNITED KINGDOM", "GB"), ("GREAT BRITAIN", "GB"), ("GERMANY", "DE"), ("DEUTSCHLAND", "DE"),
+];
+
+// Street lines allow house numbers and unit markers like "Apt #4", "12-14", "1/2" and "O'Brien St"
+fn is_line_char(c: char) -> bool {
+    return c.is_alphanumeric() || c.is_whitespace() || ".,#-/'".contains(c);
+}
+
+fn is_place_char(c: char) -> bool {
+    return c.is_alphabetic() || c.is_whitespace() || ".-'".contains(c);
+}
+
+// Validates and normalizes a structured address, returning it in the stored form or every field that failed.
+// Field names in errors are given the prefix, such as "address." when nested in a customer.
+pub fn normalize_address(fields: AddressFields, prefix: &str, function: &str) -> Result<AddressFields, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let field = |name: &str| format!("{}{}", prefix, name);
+    let mut keep = |result: Result<String, FieldError>| match result {
+        Ok(value) => value,
+        Err(err) => {
+            errors.push(err);
+            String::new()
+        },
+    };
+
+    let country_code = keep(normalize_country(&fields.country, &field("country"), function));
+    let country = Country::from_code(&country_code);
+
+    let line1 = keep(normalize_line(&fields.line1, &field("line1"), country, function));
+    let line2 = fields.line2.as_ref().map(|l| keep(normalize_line(l, &field("line2"), country, function)));
+    let city = keep(check_chars(normalize_apostrophes(&fields.city), &field("city"), is_place_char, function));
+    let region = fields.region.as_ref()
+        .map(|r| keep(check_chars(normalize_apostrophes(r), &field("region"), is_place_char, function)));
+    let region = match (country.regions(), region) {
+        (Some(known), Some(r)) => Some(keep(normalize_region(&r, known, &country_code, &field("region"), function))),
+        (Some(_), None) => {
+            error!(target: "file", "Missing region in {} for {}", function, country_code);
+            keep(Err(FieldError::new(&field("region"), "required", &format!("A region is required for {}", country_code))));
+            None
+        },
+        (None, region) => region,
+    };
+    let postal_code = match &fields.postal_code {
+        Some(p) => Some(keep(normalize_postal_code(p, country, &field("postal_code"), function))),
+        None if country != Country::Other => {
+            error!(target: "file", "Missing postal_code in {} for {}", function, country_code);
+            keep(Err(FieldError::new(&field("postal_code"), "required", &format!("A postal code is required for {}", country_code))));
+            None
+        },
+        None => None,
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(AddressFields { line1, line2, city, region, postal_code, country: country_code })
+}
+
+// Free text addresses have no country, so only the street line rules apply
+pub fn normalize_free_text(address: &str, field: &str, function: &str) -> Result<String, FieldError> {
+    return normalize_line(address, field, Country::Other, function);
+}
+
+fn normalize_line(line: &str, field: &str, country: Country, function: &str) -> Result<String, FieldError> {
+    let line = check_chars(normalize_apostrophes(line), field, is_line_char, function)?;
+    if !line.chars().any(char::is_alphanumeric) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, pii(&line));
+        return Err(FieldError::new(field, "invalid_characters", "Please include letters or numbers"));
+    }
+    return Ok(country.abbreviate(&line));
+}
+
+fn normalize_country(country: &str, field: &str, function: &str) -> Result<String, FieldError> {
+    let upper = country.trim().to_uppercase();
+    if let Some((_, code)) = COUNTRY_NAMES.iter().find(|(name, _)| *name == upper) {
+        return Ok(code.to_string());
+    }
+    if upper.len() == 2 && upper.chars().all(|c| c.is_ascii_uppercase()) {
+        return Ok(upper);
+    }
+    error!(target: "file", "Invalid country in {}: {}", function, country);
+    return Err(FieldError::new(field, "invalid_country", "Please use a two letter country code such as US"));
+}
+
+fn normalize_region(region: &str, known: &[&str], country_code: &str, field: &str, function: &str) -> Result<String, FieldError> {
+    let code = region.to_uppercase();
+    if !known.contains(&code.as_str()) {
+        error!(target: "file", "Invalid region in {} for {}: {}", function, country_code, region);
+        return Err(FieldError::new(field, "invalid_region", &format!("Please use the two letter region code for {}", country_code)));
+    }
+    return Ok(code);
+}
+
+fn normalize_postal_code(postal_code: &str, country: Country, field: &str, function: &str) -> Result<String, FieldError> {
+    let mut code = postal_code.trim().to_uppercase();
+    // Canadian and UK codes are often written without the space before the last three characters
+    if matches!(country, Country::CA | Country::GB) && !code.contains(' ') {
+        // Found by characters, as codes that aren't ASCII are only rejected by the pattern below
+        if let Some((last_three, _)) = code.char_indices().nth_back(2).filter(|(i, _)| *i > 0) {
+            code.insert(last_three, ' ');
+        }
+    }
+    let re = Regex::new(country.postal_code_pattern()).unwrap();
+    if !re.is_match(&code) {
+        error!(target: "file", "Invalid postal_code in {}: {}", function, pii(postal_code));
+        return Err(FieldError::new(field, "invalid_postal_code", &format!("Please use the form {}", country.postal_code_example())));
+    }
+    return Ok(code);
+}
+
+fn normalize_apostrophes(input: &str) -> String {
+    return input.replace(['\u{2018}', '\u{2019}', '`'], "'");
+}
+
+fn check_chars(input: String, field: &str, allowed: fn(char) -> bool, function: &str) -> Result<String, FieldError> {
+    if input.trim().is_empty() {
+        error!(target: "file", "Empty input given in {}, field: {}", function, field);
+        return Err(FieldError::new(field, "empty", "Please do not input only empty space"));
+    }
+    if let Some(bad) = input.chars().find(|c| !allowed(*c)) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, field_value(field, &input));
+        return Err(FieldError::new(field, "invalid_characters", &format!("The character '{}' is not allowed here", bad)));
+    }
+    return Ok(input);
+}
+
+fn abbreviate_words(line: &str, abbreviations: &[(&str, &str)]) -> String {
+    return line
+        .split(' ')
+        .map(|word| {
+            let bare = word.trim_end_matches(['.', ',']);
+            let lower = bare.to_lowercase();
+            match abbreviations.iter().find(|(long, short)| lower == *long || lower == short.to_lowercase()) {
+                // Abbreviations are written without a period, a following comma is kept
+                Some((_, short)) => format!("{}{}", short, word[bare.len()..].trim_start_matches('.')),
+                None => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+fn abbreviate_suffixes(line: &str, abbreviations: &[(&str, &str)]) -> String {
+    return line
+        .split(' ')
+        .map(|word| {
+            for (long, short) in abbreviations {
+                let suffix_start = word.char_indices().map(|(i, _)| i).find(|i| word[*i..].to_lowercase() == *long);
+                match suffix_start {
+                    Some(0) => return short[..1].to_uppercase() + &short[1..],
+                    Some(i) => return format!("{}{}", &word[..i], short),
+                    None => (),
+                };
+            }
+            word.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::address;
+
+    fn codes(errors: Vec<FieldError>) -> Vec<(String, &'static str)> {
+        return errors.into_iter().map(|e| (e.field, e.code)).collect();
+    }
+
+    #[test]
+    fn us_address_is_abbreviated_and_upper_cased() {
+        let fields = AddressFields {
+            line2: Some("apartment 4".to_string()),
+            ..address("1 Fifth Avenue, floor 2", "New York", Some("ny"), Some("10001-1234"), "usa")
+        };
+        let normalized = normalize_address(fields, "", "test").unwrap();
+        assert_eq!(normalized.line1, "1 Fifth Ave, Fl 2");
+        assert_eq!(normalized.line2.as_deref(), Some("Apt 4"));
+        assert_eq!(normalized.region.as_deref(), Some("NY"));
+        assert_eq!(normalized.postal_code.as_deref(), Some("10001-1234"));
+        assert_eq!(normalized.country, "US");
+    }
+
+    #[test]
+    fn abbreviations_drop_their_period() {
+        assert_eq!(normalize_free_text("12 Baker St. north", "address", "test").unwrap(), "12 Baker St north");
+        assert_eq!(normalize_free_text("12 O\u{2019}Brien Road", "address", "test").unwrap(), "12 O'Brien Rd");
+    }
+
+    #[test]
+    fn uk_postal_code_gets_its_space() {
+        let normalized = normalize_address(address("10 Downing Street", "London", None, Some("sw1a2aa"), "United Kingdom"), "", "test").unwrap();
+        assert_eq!(normalized.country, "GB");
+        assert_eq!(normalized.postal_code.as_deref(), Some("SW1A 2AA"));
+        assert_eq!(normalized.region, None);
+    }
+
+    #[test]
+    fn german_street_suffix_is_abbreviated() {
+        let normalized = normalize_address(address("Hauptstraße 5", "Berlin", None, Some("10115"), "de"), "", "test").unwrap();
+        assert_eq!(normalized.line1, "Hauptstr. 5");
+        let normalized = normalize_address(address("Strasse des 17. Juni 135", "Berlin", None, Some("10623"), "DE"), "", "test").unwrap();
+        assert_eq!(normalized.line1, "Str. des 17. Juni 135");
+    }
+
+    #[test]
+    fn other_countries_need_no_region_or_postal_code() {
+        let normalized = normalize_address(address("Rua Augusta 20", "Lisboa", Some("Lisboa"), None, "PT"), "", "test").unwrap();
+        assert_eq!(normalized.region.as_deref(), Some("Lisboa"));
+        assert_eq!(normalized.postal_code, None);
+    }
+
+    #[test]
+    fn every_failing_field_is_reported_with_the_prefix() {
+        let errors = normalize_address(address("   ", "Toronto", None, None, "CA"), "address.", "test").unwrap_err();
+        assert_eq!(codes(errors), vec![
+            ("address.line1".to_string(), "empty"),
+            ("address.region".to_string(), "required"),
+            ("address.postal_code".to_string(), "required"),
+        ]);
+    }
+
+    #[test]
+    fn invalid_codes_are_rejected() {
+        let errors = normalize_address(address("1 Main St", "Springfield", Some("XX"), Some("1234"), "US"), "", "test").unwrap_err();
+        assert_eq!(codes(errors), vec![("region".to_string(), "invalid_region"), ("postal_code".to_string(), "invalid_postal_code")]);
+        let errors = normalize_address(address("1 Main St", "Springfield", None, None, "America"), "", "test").unwrap_err();
+        assert_eq!(codes(errors), vec![("country".to_string(), "invalid_country")]);
+    }
+
+    #[test]
+    fn lines_need_letters_or_numbers_and_allowed_characters() {
+        assert_eq!(normalize_free_text("#-/", "address", "test").unwrap_err().code, "invalid_characters");
+        assert_eq!(normalize_free_text("1 Main St; DROP", "address", "test").unwrap_err().code, "invalid_characters");
+        assert_eq!(normalize_free_text("Apt #4, 12-14 1/2 Main St", "address", "test").unwrap(), "Apt #4, 12-14 1/2 Main St");
+    }
+}
@@ -0,0 +1,507 @@
+// Maintenance commands run against dd.db in the working directory, so run it from where the server runs.
+// Changes are written to the audit log with the actor "cli", and logged to logs.txt.
+#![allow(clippy::needless_return)]
+
+mod output;
+
+use bookshop_rs::db::purchaseOrders::{self, CancelOrderError, PurchaseOrderFilter};
+use bookshop_rs::db::prices::{self, PriceError};
+use bookshop_rs::db::promotions;
+use bookshop_rs::db::audit::{self, Actor};
+use bookshop_rs::db::{books, customers, maintenance};
+use bookshop_rs::catalog::{self, Book, CatalogFormat, CreateBook};
+use bookshop_rs::onix;
+use bookshop_rs::redact::RedactingEncoder;
+use bookshop_rs::validation::{validate, validate_amount, validate_count, validate_timestamp, Rule, Rules, Validate, ValidationConfig};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use log4rs::append::file::FileAppender;
+use log4rs::config::{Appender, Config, Logger, Root};
+use log4rs::encode::json::JsonEncoder;
+use output::{print, Format};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const BOOK_COLUMNS: &[&str] = &["id", "title", "author", "format", "price", "stock", "isbn", "availability"];
+const PRICE_COLUMNS: &[&str] = &["id", "book_id", "price", "effective_from", "effective_to"];
+const SKIPPED_COLUMNS: &[&str] = &["record_reference", "isbn", "reason"];
+const CUSTOMER_COLUMNS: &[&str] = &["id", "name", "account_balance", "shipping_address"];
+const PROMOTION_COLUMNS: &[&str] = &["id", "name", "code", "kind", "uses", "usage_limit", "starts_at", "ends_at"];
+const ORDER_COLUMNS: &[&str] = &["order_id", "customer_id", "book_id", "book_title", "quantity", "price_paid", "shipped", "created_at", "shipped_at"];
+
+#[derive(Parser, Debug)]
+#[command(name = "bookshop-admin", about = "Manage the bookshop database")]
+struct Cli {
+    /// How records are printed
+    #[arg(long, value_enum, default_value = "table", global = true)]
+    format: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Add, list, update and delete books
+    #[command(subcommand)]
+    Books(Box<BooksCommand>),
+    /// List customers and adjust their balances
+    #[command(subcommand)]
+    Customers(CustomersCommand),
+    /// List, ship and cancel orders
+    #[command(subcommand)]
+    Orders(OrdersCommand),
+    /// List and end promotions
+    #[command(subcommand)]
+    Promotions(PromotionsCommand),
+    /// Migrate, back up and vacuum dd.db
+    #[command(subcommand)]
+    Database(DatabaseCommand),
+    /// Check the audit log
+    #[command(subcommand)]
+    Audit(AuditCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum BooksCommand {
+    /// Every book, by id
+    List,
+    /// Adds a book, unless one with the same title, author and format exists. Separate several people with commas.
+    Add {
+        #[arg(long)]
+        title: String,
+        #[arg(long)]
+        author: String,
+        #[arg(long)]
+        price: f64,
+        #[arg(long)]
+        editor: Option<String>,
+        #[arg(long)]
+        translator: Option<String>,
+        #[arg(long)]
+        illustrator: Option<String>,
+        /// hardcover, paperback, ebook or audiobook
+        #[arg(long = "edition-format")]
+        edition_format: Option<String>,
+        #[arg(long)]
+        publisher: Option<String>,
+        /// Of form YYYY-MM-DD
+        #[arg(long)]
+        publication_date: Option<String>,
+        #[arg(long)]
+        series: Option<String>,
+        #[arg(long)]
+        series_position: Option<i64>,
+        /// Copies on hand, not counted if left out
+        #[arg(long)]
+        stock: Option<i64>,
+    },
+    /// Changes only the given fields of a book
+    Update {
+        id: i64,
+        #[arg(long)]
+        title: Option<String>,
+        #[arg(long)]
+        author: Option<String>,
+        #[arg(long)]
+        price: Option<f64>,
+    },
+    /// Every price of a book, oldest first and scheduled ones last
+    Prices { id: i64 },
+    /// Sets a book's price from now, or schedules it
+    SetPrice {
+        id: i64,
+        price: f64,
+        /// A later UTC time of form YYYY-MM-DD HH:MM:SS, or a date for its start
+        #[arg(long)]
+        from: Option<String>,
+    },
+    /// Cancels a price that hasn't started yet
+    CancelPrice { id: i64, price_id: i64 },
+    /// Sets the copies on hand of a book, leave out the number to stop counting them
+    SetStock { id: i64, stock: Option<i64> },
+    /// Deletes a book, unless it was ordered
+    Delete { id: i64 },
+    /// Adds every book in a .csv or .jsonl file, or none if any row fails
+    Import {
+        path: PathBuf,
+        /// Only checks the rows
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Writes every book to a new .csv or .jsonl file
+    Export { path: PathBuf },
+    /// Adds or updates books by ISBN from a publisher's ONIX 3.0 file
+    ImportOnix { path: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+enum CustomersCommand {
+    /// Every customer, by id
+    List,
+    /// One customer with their balance
+    Show { id: i64 },
+    /// Adds the amount to the balance, negative to deduct
+    AdjustBalance {
+        id: i64,
+        #[arg(allow_negative_numbers = true)]
+        amount: f64,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum OrderStatus {
+    Shipped,
+    NotShipped,
+}
+
+#[derive(Subcommand, Debug)]
+enum OrdersCommand {
+    /// Orders, newest first
+    List {
+        #[arg(long)]
+        customer: Option<i64>,
+        #[arg(long, value_enum)]
+        status: Option<OrderStatus>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Marks orders as shipped, leaving those already shipped as they are
+    Ship {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+    },
+    /// Deletes an unshipped order and refunds its price to the customer's balance
+    Cancel { id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+enum PromotionsCommand {
+    /// Every promotion, by id, with how many orders used it
+    List,
+    /// Ends a promotion now, orders already placed keep their discounts
+    End { id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+enum DatabaseCommand {
+    /// Creates dd.db if needed and applies pending migrations
+    Migrate,
+    /// Copies dd.db to a new file, safe while the server is running
+    Backup { path: PathBuf },
+    /// Reclaims the space left by deleted rows
+    Vacuum,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCommand {
+    /// Checks the audit log's hash chain, exits with 1 if it's broken
+    Verify,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logging();
+    return match run(cli.command, cli.format) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        },
+    };
+}
+
+// Only the file target, to logs.txt, so stdout stays parseable. The server rolls the file, see log4rs.yml.
+fn init_logging() {
+    let file = FileAppender::builder()
+        .encoder(Box::new(RedactingEncoder::new(Box::new(JsonEncoder::new()))))
+        .build("logs.txt")
+        .expect("expected to be able to open logs.txt");
+    let config = Config::builder()
+        .appender(Appender::builder().build("file", Box::new(file)))
+        .logger(Logger::builder().appender("file").additive(false).build("file", LevelFilter::Info))
+        .build(Root::builder().build(LevelFilter::Off))
+        .expect("logging config should be valid");
+    log4rs::init_config(config).expect("Should initialize");
+}
+
+fn run(command: Command, format: Format) -> Result<(), String> {
+    // Brings the database up to date once, as the server does at startup, unless that's the command
+    if !matches!(command, Command::Database(DatabaseCommand::Migrate)) {
+        maintenance::migrate();
+    }
+    match command {
+        Command::Books(command) => books_command(*command, format),
+        Command::Customers(command) => customers_command(command, format),
+        Command::Orders(command) => orders_command(command, format),
+        Command::Promotions(command) => promotions_command(command, format),
+        Command::Database(command) => database_command(command),
+        Command::Audit(AuditCommand::Verify) => {
+            let verification = audit::verify_chain();
+            if format == Format::Json {
+                print(format, &[&verification], &[]);
+            } else if verification.valid {
+                println!("Audit chain intact, {} events", verification.events);
+            }
+            if !verification.valid {
+                return Err(format!("Audit chain broken at event {}: {}",
+                                   verification.first_invalid_id.unwrap_or_default(), verification.reason.unwrap_or_default()));
+            }
+            return Ok(());
+        },
+    }
+}
+
+fn books_command(command: BooksCommand, format: Format) -> Result<(), String> {
+    match command {
+        BooksCommand::List => print(format, &books::list_books(), BOOK_COLUMNS),
+        BooksCommand::Add { title, author, price, editor, translator, illustrator, edition_format, publisher, publication_date,
+                            series, series_position, stock } => {
+            let book = Book {
+                id: None,
+                title: Some(title),
+                author: Some(author),
+                editor,
+                translator,
+                illustrator,
+                price: Some(price),
+                isbn: None,
+                availability: None,
+                description: None,
+                series,
+                series_position,
+                format: edition_format
+                    .map(|f| books::EditionFormat::parse(&f).ok_or_else(|| format!("Unknown edition format {}, please use hardcover, paperback, ebook or audiobook", f)))
+                    .transpose()?,
+                publisher,
+                publication_date,
+                stock,
+            };
+            let book = validated::<CreateBook>(book)?;
+            let book = book.to_record();
+            let (title, author) = (book.title.clone(), book.author.clone());
+            let bid = books::create_book(&Actor::cli(), book).map_err(|e| match e {
+                books::BookConflict::SameEdition(bid) => format!("Book {} by {} already exists in this format with id {}", title, author, bid),
+                books::BookConflict::SameIsbn(bid) => format!("Another book has this ISBN with id {}", bid),
+            })?;
+            print(format, &[books::get_book(bid)], BOOK_COLUMNS);
+        },
+        BooksCommand::Update { id, title, author, price } => {
+            let Book { title, author, price, .. } = validated::<UpdateBook>(Book { title, author, price, ..Book::default() })?;
+            let book = books::update_book(&Actor::cli(), id, title, author, price).ok_or_else(|| format!("No book with id {}", id))?;
+            print(format, &[book], BOOK_COLUMNS);
+        },
+        BooksCommand::Prices { id } => {
+            let history = prices::price_history(id).ok_or_else(|| format!("No book with id {}", id))?;
+            print(format, &history, PRICE_COLUMNS);
+        },
+        BooksCommand::SetPrice { id, price, from } => {
+            let price = valid_price(price)?;
+            if let Some(from) = &from {
+                validate_timestamp(from, "from", "cli").map_err(|e| e.message)?;
+            }
+            let record = prices::schedule_price(&Actor::cli(), id, price, from.as_deref()).map_err(|e| price_error(id, e))?;
+            print(format, &[record], PRICE_COLUMNS);
+        },
+        BooksCommand::CancelPrice { id, price_id } => {
+            let record = prices::cancel_price(&Actor::cli(), id, price_id).map_err(|e| price_error(id, e))?;
+            print(format, &[record], PRICE_COLUMNS);
+        },
+        BooksCommand::SetStock { id, stock } => {
+            let stock = stock.map(|s| valid_count("stock", s)).transpose()?;
+            let book = books::set_stock(&Actor::cli(), id, stock).ok_or_else(|| format!("No book with id {}", id))?;
+            print(format, &[book], BOOK_COLUMNS);
+        },
+        BooksCommand::Delete { id } => match books::delete_book(&Actor::cli(), id) {
+            Ok(book) => print(format, &[book], BOOK_COLUMNS),
+            Err(books::DeleteBookError::NotFound) => return Err(format!("No book with id {}", id)),
+            Err(books::DeleteBookError::HasOrders(orders)) => return Err(format!("Book {} has {} orders and can't be deleted", id, orders)),
+            Err(books::DeleteBookError::HasPromotions(promotions)) => {
+                return Err(format!("Book {} has {} promotions and can't be deleted", id, promotions))
+            },
+        },
+        BooksCommand::Import { path, dry_run } => {
+            let input = fs::read(&path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+            let report = catalog::import(&Actor::cli(), &input, catalog_format(&path)?, dry_run, &validation_config()?);
+            if format == Format::Json {
+                print(format, &[&report], &[]);
+            } else {
+                for row in &report.errors {
+                    for err in &row.errors {
+                        println!("Line {}: {}: {}", row.line, err.field, err.message);
+                    }
+                }
+            }
+            if !report.errors.is_empty() {
+                return Err(format!("{} of {} rows have errors, no books were imported", report.errors.len(), report.rows));
+            }
+            if format == Format::Table {
+                println!("{} {} books", if dry_run { "Would import" } else { "Imported" }, report.imported);
+            }
+        },
+        BooksCommand::Export { path } => {
+            let format = catalog_format(&path)?;
+            let mut file = File::options().write(true).create_new(true).open(&path)
+                .map_err(|e| format!("Can't create {}: {}", path.display(), e))?;
+            for page in catalog::export(format) {
+                file.write_all(&page).map_err(|e| format!("Can't write {}: {}", path.display(), e))?;
+            }
+            println!("Exported to {}", path.display());
+        },
+        BooksCommand::ImportOnix { path } => {
+            let xml = fs::read_to_string(&path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+            let report = onix::ingest(&Actor::cli(), &xml, &validation_config()?)?;
+            if format == Format::Json {
+                print(format, &[&report], &[]);
+                return Ok(());
+            }
+            if !report.skipped.is_empty() {
+                print(format, &report.skipped, SKIPPED_COLUMNS);
+            }
+            println!("{} products: {} created, {} updated, {} unchanged, {} skipped",
+                     report.products, report.created, report.updated, report.unchanged, report.skipped.len());
+        },
+    };
+    return Ok(());
+}
+
+fn customers_command(command: CustomersCommand, format: Format) -> Result<(), String> {
+    match command {
+        CustomersCommand::List => print(format, &customers::list_customers(), CUSTOMER_COLUMNS),
+        CustomersCommand::Show { id } => {
+            let customer = customers::get_customer(id).ok_or_else(|| format!("No customer with id {}", id))?;
+            print(format, &[customer], CUSTOMER_COLUMNS);
+        },
+        CustomersCommand::AdjustBalance { id, amount } => {
+            if !amount.is_finite() {
+                return Err(format!("Invalid amount {}", amount));
+            }
+            customers::adjust_customer_balance(&Actor::cli(), id, amount).ok_or_else(|| format!("No customer with id {}", id))?;
+            print(format, &[customers::get_customer(id)], CUSTOMER_COLUMNS);
+        },
+    };
+    return Ok(());
+}
+
+fn orders_command(command: OrdersCommand, format: Format) -> Result<(), String> {
+    match command {
+        OrdersCommand::List { customer, status, limit } => {
+            let filter = PurchaseOrderFilter {
+                shipped: status.map(|s| match s { OrderStatus::Shipped => 1, OrderStatus::NotShipped => 0 }),
+                since: None,
+                until: None,
+            };
+            let (orders, _) = match customer {
+                Some(cid) => purchaseOrders::get_customer_purchase_orders(cid, &filter, limit, 0),
+                None => purchaseOrders::get_purchase_orders(&filter, limit, 0),
+            };
+            print(format, &orders, ORDER_COLUMNS);
+        },
+        OrdersCommand::Ship { ids } => {
+            let mut shipped = Vec::new();
+            let mut missing = Vec::new();
+            for id in ids {
+                match purchaseOrders::get_purchase_order(id) {
+                    Some(order) => {
+                        if order.shipped == 0 {
+                            purchaseOrders::ship_po(&Actor::cli(), id);
+                        }
+                        shipped.extend(purchaseOrders::get_purchase_order(id));
+                    },
+                    None => missing.push(id.to_string()),
+                };
+            }
+            print(format, &shipped, ORDER_COLUMNS);
+            if !missing.is_empty() {
+                return Err(format!("No orders with ids {}", missing.join(", ")));
+            }
+        },
+        OrdersCommand::Cancel { id } => match purchaseOrders::cancel_po(&Actor::cli(), id) {
+            Ok(order) => print(format, &[order], ORDER_COLUMNS),
+            Err(CancelOrderError::NotFound) => return Err(format!("No order with id {}", id)),
+            Err(CancelOrderError::AlreadyShipped) => return Err(format!("Order {} has already shipped", id)),
+        },
+    };
+    return Ok(());
+}
+
+fn promotions_command(command: PromotionsCommand, format: Format) -> Result<(), String> {
+    match command {
+        PromotionsCommand::List => print(format, &promotions::list_promotions(), PROMOTION_COLUMNS),
+        PromotionsCommand::End { id } => {
+            let promotion = promotions::end_promotion(&Actor::cli(), id).ok_or_else(|| format!("No promotion with id {}", id))?;
+            print(format, &[promotion], PROMOTION_COLUMNS);
+        },
+    };
+    return Ok(());
+}
+
+fn database_command(command: DatabaseCommand) -> Result<(), String> {
+    match command {
+        DatabaseCommand::Migrate => {
+            let (before, after) = maintenance::migrate();
+            match before {
+                Some(before) if before == after => println!("Already at migration {}", after),
+                Some(before) => println!("Migrated from {} to {}", before, after),
+                None => println!("Created the database at migration {}", after),
+            };
+        },
+        DatabaseCommand::Backup { path } => {
+            let size = maintenance::backup(&path)?;
+            println!("Backed up to {}, {} bytes", path.display(), size);
+        },
+        DatabaseCommand::Vacuum => {
+            let (before, after) = maintenance::vacuum()?;
+            println!("Vacuumed from {} to {} bytes", before, after);
+        },
+    };
+    return Ok(());
+}
+
+fn catalog_format(path: &Path) -> Result<CatalogFormat, String> {
+    return CatalogFormat::of_path(path).ok_or_else(|| format!("Can't tell the format of {}, please use a .csv or .jsonl file", path.display()));
+}
+
+// reject_confusables as the server has it, from Rocket.toml and ROCKET_ environment variables
+fn validation_config() -> Result<ValidationConfig, String> {
+    return rocket::Config::figment().extract::<ValidationConfig>().map_err(|e| format!("Invalid Rocket.toml: {}", e));
+}
+
+// Normalized and checked with the API's rules, as onix::read_product does
+fn validated<R: Rules<Book>>(mut book: Book) -> Result<Book, String> {
+    book.normalize();
+    let errors = validate::<Book, R>(&book, &validation_config()?);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join(", "));
+    }
+    return Ok(book);
+}
+
+// The fields `books update` can change, each optional
+struct UpdateBook;
+impl Rules<Book> for UpdateBook {
+    const FUNCTION: &'static str = "cli_update_book";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("title", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("author", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("price", &[Rule::Amount]),
+    ];
+}
+
+fn valid_price(price: f64) -> Result<f64, String> {
+    return validate_amount(price, "price", "cli").map(|()| price).map_err(|e| e.message);
+}
+
+fn price_error(bid: i64, err: PriceError) -> String {
+    return match err {
+        PriceError::BookNotFound(_) => format!("No book with id {}", bid),
+        PriceError::NotFound(pid) => format!("Book {} has no price with id {}", bid, pid),
+        PriceError::InPast => "Prices can only be set from now or later".to_string(),
+        PriceError::Started(pid) => format!("Price {} has already started and can't be cancelled", pid),
+    };
+}
+
+fn valid_count(field: &str, count: i64) -> Result<i64, String> {
+    return validate_count(count, field, "cli").map(|()| count).map_err(|e| e.message);
+}
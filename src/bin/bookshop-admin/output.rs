@@ -0,0 +1,48 @@
+use clap::ValueEnum;
+use rocket::serde::json::serde_json::{self, Value};
+use serde::Serialize;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+// Records as an aligned table of the given columns, or as JSON with every field
+pub fn print<T: Serialize>(format: Format, records: &[T], columns: &[&str]) {
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(records).expect("records should always serialize"));
+        return;
+    }
+    let rows = records
+        .iter()
+        .map(|record| {
+            let value = serde_json::to_value(record).expect("records should always serialize");
+            columns.iter().map(|column| cell(&value[column])).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| rows.iter().map(|row| row[i].chars().count()).chain([column.len()]).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let line = |cells: Vec<String>| {
+        let padded = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect::<Vec<_>>();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(columns.iter().map(|c| c.to_string()).collect());
+    line(widths.iter().map(|w| "-".repeat(*w)).collect());
+    for row in rows {
+        line(row);
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => s.clone(),
+        // Prices and balances are shown as money
+        Value::Number(n) if n.is_f64() => format!("{:.2}", n.as_f64().unwrap_or_default()),
+        other => other.to_string(),
+    }
+}
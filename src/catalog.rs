@@ -0,0 +1,354 @@
+use csv::{ReaderBuilder, StringRecord, Trim, WriterBuilder};
+use rocket::serde::json::serde_json;
+use rocket::FromFormField;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use log::{error, info};
+
+use crate::db::audit::Actor;
+use crate::db::books::{self, BookConflict, BookRecord, EditionFormat};
+use crate::text::canonical_key;
+use crate::validation::{normalize_text_in, required, validate, FieldError, Rule, Rules, Validate, ValidationConfig, Value};
+
+// Books read from the catalog at a time while exporting
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+// Book's fields in order, which is what import reads back
+const CSV_HEADER: [&str; 16] = ["id", "title", "author", "editor", "translator", "illustrator", "price", "isbn", "availability", "description",
+                                 "series", "series_position", "format", "publisher", "publication_date", "stock"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Book {
+    pub id: Option<i64>,
+    pub title: Option<String>,
+    // Several people are separated by commas, see authors::split_names
+    pub author: Option<String>,
+    pub editor: Option<String>,
+    pub translator: Option<String>,
+    pub illustrator: Option<String>,
+    pub price: Option<f64>,
+    // An ISBN-13, hyphens are dropped
+    pub isbn: Option<String>,
+    // One of books::AVAILABILITY
+    pub availability: Option<String>,
+    pub description: Option<String>,
+    // The work's series, see works::set_series
+    pub series: Option<String>,
+    pub series_position: Option<i64>,
+    pub format: Option<EditionFormat>,
+    pub publisher: Option<String>,
+    pub publication_date: Option<String>,
+    pub stock: Option<i64>,
+}
+
+impl Book {
+    // What create_book stores for a book that passed CreateBook
+    pub fn to_record(&self) -> BookRecord {
+        return BookRecord {
+            id: 0,
+            title: required(&self.title),
+            author: required(&self.author),
+            price: required(&self.price),
+            editor: self.editor.clone(),
+            translator: self.translator.clone(),
+            illustrator: self.illustrator.clone(),
+            isbn: self.isbn.clone(),
+            availability: self.availability.clone(),
+            description: self.description.clone(),
+            work_id: 0,
+            series: self.series.clone(),
+            series_position: self.series_position,
+            format: self.format,
+            publisher: self.publisher.clone(),
+            publication_date: self.publication_date.clone(),
+            stock: self.stock,
+        };
+    }
+
+    // What export writes, every field import reads back
+    pub fn from_record(book: &BookRecord) -> Book {
+        return Book {
+            id: Some(book.id),
+            title: Some(book.title.clone()),
+            author: Some(book.author.clone()),
+            editor: book.editor.clone(),
+            translator: book.translator.clone(),
+            illustrator: book.illustrator.clone(),
+            price: Some(book.price),
+            isbn: book.isbn.clone(),
+            availability: book.availability.clone(),
+            description: book.description.clone(),
+            series: book.series.clone(),
+            series_position: book.series_position,
+            format: book.format,
+            publisher: book.publisher.clone(),
+            publication_date: book.publication_date.clone(),
+            stock: book.stock,
+        };
+    }
+}
+
+impl Validate for Book {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "id" => Some(Value::Id(self.id)),
+            "title" => Some(Value::Text(&self.title)),
+            "author" => Some(Value::Text(&self.author)),
+            "editor" => Some(Value::Text(&self.editor)),
+            "translator" => Some(Value::Text(&self.translator)),
+            "illustrator" => Some(Value::Text(&self.illustrator)),
+            "price" => Some(Value::Amount(self.price)),
+            "isbn" => Some(Value::Text(&self.isbn)),
+            "availability" => Some(Value::Text(&self.availability)),
+            "description" => Some(Value::Text(&self.description)),
+            "series" => Some(Value::Text(&self.series)),
+            "series_position" => Some(Value::Count(self.series_position)),
+            "publisher" => Some(Value::Text(&self.publisher)),
+            "publication_date" => Some(Value::Text(&self.publication_date)),
+            "stock" => Some(Value::Count(self.stock)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        normalize_text_in(&mut self.title);
+        normalize_text_in(&mut self.author);
+        normalize_text_in(&mut self.editor);
+        normalize_text_in(&mut self.translator);
+        normalize_text_in(&mut self.illustrator);
+        if let Some(isbn) = &mut self.isbn {
+            *isbn = isbn.trim().replace('-', "");
+        }
+        if let Some(availability) = &mut self.availability {
+            *availability = availability.trim().to_string();
+        }
+        normalize_text_in(&mut self.description);
+        normalize_text_in(&mut self.series);
+        normalize_text_in(&mut self.publisher);
+        normalize_text_in(&mut self.publication_date);
+    }
+}
+
+pub struct CreateBook;
+impl Rules<Book> for CreateBook {
+    const FUNCTION: &'static str = "create_book";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("title", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("author", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("editor", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("translator", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("illustrator", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("price", &[Rule::Required, Rule::Amount]),
+        ("isbn", &[Rule::Isbn]),
+        ("availability", &[Rule::Availability]),
+        ("series", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("series_position", &[Rule::Count]),
+        ("publisher", &[Rule::Alphanumeric, Rule::SingleScript]),
+        ("publication_date", &[Rule::Date]),
+        ("stock", &[Rule::Count]),
+    ];
+}
+
+pub struct FindBook;
+impl Rules<Book> for FindBook {
+    const FUNCTION: &'static str = "get_price";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("title", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("author", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+    ];
+}
+
+// CSV has a header row naming the columns, JSON Lines has one Book object per line
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq)]
+pub enum CatalogFormat {
+    Csv,
+    Jsonl,
+}
+
+impl CatalogFormat {
+    // By the file's extension: .csv, or .jsonl or .ndjson
+    pub fn of_path(path: &Path) -> Option<CatalogFormat> {
+        return match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(CatalogFormat::Csv),
+            "jsonl" | "ndjson" => Some(CatalogFormat::Jsonl),
+            _ => None,
+        };
+    }
+}
+
+// Every error of one row, by the line it starts on
+#[derive(Serialize, Debug, Clone)]
+pub struct RowErrors {
+    pub line: u64,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    // Books added, or that a dry run would add. Nothing is added when any row has errors.
+    pub imported: usize,
+    pub errors: Vec<RowErrors>,
+}
+
+// Checks every row with the same rules as create_book, and adds them all in one transaction if none failed.
+// Rows with the same title, author and format, or the same ISBN, as an existing book or an earlier row fail too. Any id is ignored.
+pub fn import(actor: &Actor, input: &[u8], format: CatalogFormat, dry_run: bool, config: &ValidationConfig) -> ImportReport {
+    let rows = match format {
+        CatalogFormat::Csv => read_csv(input),
+        CatalogFormat::Jsonl => read_jsonl(input),
+    };
+    let mut errors = Vec::new();
+    let mut valid = Vec::new();
+    let mut seen = HashMap::new();
+    let mut seen_isbns = HashMap::new();
+    for (line, row) in &rows {
+        let mut book = match row {
+            Ok(book) => book.clone(),
+            Err(err) => {
+                errors.push(RowErrors { line: *line, errors: vec![err.clone()] });
+                continue;
+            },
+        };
+        book.normalize();
+        let mut row_errors = validate::<Book, CreateBook>(&book, config);
+        if row_errors.is_empty() {
+            let key = (canonical_key(&required(&book.title)), canonical_key(&required(&book.author)), book.format);
+            match (seen.get(&key), book.isbn.as_ref().and_then(|isbn| seen_isbns.get(isbn))) {
+                (Some(first), _) => row_errors.push(duplicate(&format!("Same title, author and format as line {}", first))),
+                (None, Some(first)) => row_errors.push(duplicate_isbn(&format!("Same ISBN as line {}", first))),
+                (None, None) => {
+                    seen.insert(key, *line);
+                    if let Some(isbn) = &book.isbn {
+                        seen_isbns.insert(isbn.clone(), *line);
+                    }
+                    valid.push((*line, book));
+                },
+            };
+        }
+        if !row_errors.is_empty() {
+            errors.push(RowErrors { line: *line, errors: row_errors });
+        }
+    }
+
+    let lines = valid.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+    let new_books = valid.iter().map(|(_, book)| book.to_record()).collect();
+    let imported = match books::import_books(actor, new_books, dry_run || !errors.is_empty()) {
+        Ok(count) if errors.is_empty() => count,
+        Ok(_) => 0,
+        Err(existing) => {
+            for (i, conflict) in existing {
+                let error = match conflict {
+                    BookConflict::SameEdition(bid) => duplicate(&format!("A book with this title, author and format exists with id {}", bid)),
+                    BookConflict::SameIsbn(bid) => duplicate_isbn(&format!("A book with this ISBN exists with id {}", bid)),
+                };
+                errors.push(RowErrors { line: lines[i], errors: vec![error] });
+            }
+            errors.sort_by_key(|row| row.line);
+            0
+        },
+    };
+    if errors.is_empty() {
+        info!(target: "file", "Catalog import of {} rows {}", rows.len(), if dry_run { "checked" } else { "imported" });
+    } else {
+        error!(target: "file", "Catalog import of {} rows rejected, {} rows have errors", rows.len(), errors.len());
+    }
+    return ImportReport { dry_run, rows: rows.len(), imported, errors };
+}
+
+fn duplicate(message: &str) -> FieldError {
+    return FieldError::new("title", "duplicate", message);
+}
+
+fn duplicate_isbn(message: &str) -> FieldError {
+    return FieldError::new("isbn", "duplicate", message);
+}
+
+fn malformed(field: &str, message: &str) -> FieldError {
+    return FieldError::new(field, "malformed", message);
+}
+
+fn read_csv(input: &[u8]) -> Vec<(u64, Result<Book, FieldError>)> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(input);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return vec![(1, Err(malformed("row", &e.to_string())))],
+    };
+    return reader
+        .records()
+        .map(|record| match record {
+            Ok(record) => (record.position().map_or(0, |p| p.line()), read_csv_record(&record, &headers)),
+            Err(e) => (e.position().map_or(0, |p| p.line()), Err(malformed("row", &e.to_string()))),
+        })
+        .collect();
+}
+
+fn read_csv_record(record: &StringRecord, headers: &StringRecord) -> Result<Book, FieldError> {
+    return record.deserialize::<Book>(Some(headers)).map_err(|e| match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => {
+            let field = err.field().and_then(|i| headers.get(i as usize)).unwrap_or("row");
+            malformed(field, &err.kind().to_string())
+        },
+        _ => malformed("row", &e.to_string()),
+    });
+}
+
+fn read_jsonl(input: &[u8]) -> Vec<(u64, Result<Book, FieldError>)> {
+    return input
+        .split(|b| *b == b'\n')
+        .enumerate()
+        .map(|(i, line)| (i as u64 + 1, line.strip_suffix(b"\r").unwrap_or(line)))
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(line, json)| (line, serde_json::from_slice::<Book>(json).map_err(|e| malformed("row", &e.to_string()))))
+        .collect();
+}
+
+// The whole catalog by id, a page of books at a time, CSV with its header first
+pub fn export(format: CatalogFormat) -> Export {
+    return Export { format, after: Some(0) };
+}
+
+pub struct Export {
+    format: CatalogFormat,
+    // The last id exported, None once every book was
+    after: Option<i64>,
+}
+
+impl Iterator for Export {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let after = self.after?;
+        let page = books::list_books_after(after, EXPORT_PAGE_SIZE);
+        self.after = match page.last() {
+            Some(book) if page.len() as i64 == EXPORT_PAGE_SIZE => Some(book.id),
+            _ => None,
+        };
+        return Some(match self.format {
+            CatalogFormat::Csv => write_csv(&page, after == 0),
+            CatalogFormat::Jsonl => write_jsonl(&page),
+        });
+    }
+}
+
+fn write_csv(page: &[BookRecord], header: bool) -> Vec<u8> {
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    if header {
+        writer.write_record(CSV_HEADER).expect("writing to memory should not fail");
+    }
+    for book in page {
+        writer.serialize(Book::from_record(book)).expect("books should always serialize");
+    }
+    return writer.into_inner().expect("writing to memory should not fail");
+}
+
+fn write_jsonl(page: &[BookRecord]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for book in page {
+        serde_json::to_writer(&mut output, &Book::from_record(book)).expect("books should always serialize");
+        output.push(b'\n');
+    }
+    return output;
+}
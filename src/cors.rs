@@ -0,0 +1,131 @@
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Build, Request, Response, Rocket};
+use serde::Deserialize;
+use std::io::Cursor;
+use std::sync::OnceLock;
+use log::{error, info, warn};
+
+// Read from the `cors` table of the profile in Rocket.toml
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+struct CorsConfig {
+    // Such as "https://shop.example.com", or "*" for any. Empty, the default, allows none.
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<String>,
+    // Request headers a browser may send, matched without regard to case
+    allowed_headers: Vec<String>,
+    // Response headers a browser lets the calling page read
+    exposed_headers: Vec<String>,
+    // Seconds a browser may cache a preflight response
+    max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&["GET", "POST", "PUT", "DELETE"]),
+            allowed_headers: strings(&["Content-Type", "Idempotency-Key", "X-Request-Id"]),
+            exposed_headers: strings(&["X-Request-Id", "Idempotent-Replayed", "Retry-After", "Deprecation", "Sunset", "Link"]),
+            max_age: 3600,
+        }
+    }
+}
+
+// Lets browser pages on the allowed origins call the API, and answers their OPTIONS preflight requests.
+// There are no OPTIONS routes, so a preflight is answered here in place of its 404.
+pub struct Cors {
+    // Set from Rocket.toml on ignite
+    config: OnceLock<CorsConfig>,
+}
+
+impl Cors {
+    pub fn fairing() -> Cors {
+        Cors { config: OnceLock::new() }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info { name: "CORS", kind: Kind::Ignite | Kind::Response }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = match rocket.figment().extract_inner::<CorsConfig>("cors") {
+            Ok(config) => config,
+            Err(e) if e.missing() => CorsConfig::default(),
+            Err(e) => {
+                error!(target: "file", "Invalid cors in Rocket.toml: {}", e);
+                return Err(rocket);
+            },
+        };
+        if let Some(method) = config.allowed_methods.iter().find(|m| m.parse::<Method>().is_err()) {
+            error!(target: "file", "Invalid cors.allowed_methods in Rocket.toml, not an HTTP method: {}", method);
+            return Err(rocket);
+        }
+        info!(target: "file", "CORS allowed for origins: {}", config.allowed_origins.join(", "));
+        let _ = self.config.set(config);
+        Ok(rocket)
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let config = match self.config.get() {
+            Some(config) => config,
+            None => return,
+        };
+        let origin = match req.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+        // Responses differ by Origin, so caches must keep them apart
+        res.adjoin_header(Header::new("Vary", "Origin"));
+        let requested_method = req.headers().get_one("Access-Control-Request-Method");
+        let preflight = req.method() == Method::Options && requested_method.is_some();
+
+        if !config.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin) {
+            warn!(target: "file", "CORS request from origin not in cors.allowed_origins: {}", origin);
+            if preflight {
+                forbid(res);
+            }
+            return;
+        }
+        res.set_header(Header::new("Access-Control-Allow-Origin", origin.to_string()));
+        if !preflight {
+            res.set_header(Header::new("Access-Control-Expose-Headers", config.exposed_headers.join(", ")));
+            return;
+        }
+
+        // A limited preflight keeps its 429, see rate_limit
+        if res.status() != Status::NotFound {
+            return;
+        }
+        let method_allowed = requested_method.is_some_and(|m| config.allowed_methods.iter().any(|a| a.eq_ignore_ascii_case(m)));
+        let headers = req.headers().get_one("Access-Control-Request-Headers").unwrap_or_default();
+        let disallowed_header = headers
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .find(|h| !config.allowed_headers.iter().any(|a| a.eq_ignore_ascii_case(h)));
+        if !method_allowed || disallowed_header.is_some() {
+            warn!(target: "file", "CORS preflight from {} refused, method: {}, headers: {}", origin, requested_method.unwrap_or_default(), headers);
+            forbid(res);
+            return;
+        }
+        res.set_status(Status::NoContent);
+        res.set_sized_body(0, Cursor::new(""));
+        res.remove_header("Content-Type");
+        res.set_header(Header::new("Access-Control-Allow-Methods", config.allowed_methods.join(", ")));
+        res.set_header(Header::new("Access-Control-Allow-Headers", config.allowed_headers.join(", ")));
+        res.set_header(Header::new("Access-Control-Max-Age", config.max_age.to_string()));
+    }
+}
+
+fn forbid(res: &mut Response<'_>) {
+    let body = "Cross-origin request not allowed";
+    res.set_status(Status::Forbidden);
+    res.set_sized_body(body.len(), Cursor::new(body));
+    res.set_header(rocket::http::ContentType::Plain);
+}
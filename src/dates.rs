@@ -0,0 +1,69 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Dates are counted in days from 1970-01-01, after Howard Hinnant's days_from_civil and civil_from_days.
+// That was a Thursday, so days.rem_euclid(7) is 0 on Thursdays and 2 and 3 at weekends.
+
+// The UTC date today
+pub fn today() -> i64 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).expect("system clock is before 1970").as_secs();
+    return (seconds / 86_400) as i64;
+}
+
+// The days of a date of form YYYY-MM-DD
+pub fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return Some(era * 146_097 + day_of_era - 719_468);
+}
+
+// The year, month from 1 and day of the month of the days
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+// The date of the days as YYYY-MM-DD
+pub fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    return format!("{:04}-{:02}-{:02}", year, month, day);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_civil_utc_dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(-1), "1969-12-31");
+        assert_eq!(format_date(19_782), "2024-02-29");
+        assert_eq!(format_date(20_745), "2026-10-19");
+        assert_eq!(format_date(47_541), "2100-03-01");
+    }
+
+    #[test]
+    fn parsing_is_the_inverse() {
+        for days in [-1, 0, 59, 10_956, 19_782, 20_745, 47_541] {
+            assert_eq!(parse_date(&format_date(days)), Some(days));
+        }
+        assert_eq!(parse_date("2027-13-01"), None);
+        assert_eq!(parse_date("2027-04"), None);
+        assert_eq!(parse_date("soon"), None);
+    }
+}
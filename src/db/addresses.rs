@@ -0,0 +1,231 @@
+use super::db::connect;
+use super::audit::{self, Action, Actor};
+use super::books::LogErrResult;
+use crate::redact::pii;
+use rusqlite::{named_params, Connection, Row};
+use serde::Serialize;
+use log::info;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AddressFields {
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CustomerAddress {
+    pub id: i64,
+    pub customer_id: i64,
+    #[serde(flatten)]
+    pub fields: AddressFields,
+    pub is_default: bool,
+}
+
+impl AddressFields {
+    // Addresses given as free text, such as the old shipping_address field, are stored whole as line1
+    pub fn free_text(address: String) -> AddressFields {
+        AddressFields {
+            line1: address,
+            line2: None,
+            city: String::new(),
+            region: None,
+            postal_code: None,
+            country: String::new(),
+        }
+    }
+
+    // Addresses carried over from the old free text column only have line1 set
+    pub fn formatted(&self) -> String {
+        let region_and_code = [self.region.as_deref(), self.postal_code.as_deref()]
+            .iter()
+            .flatten()
+            .filter(|s| !s.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let parts = [
+            Some(self.line1.as_str()),
+            self.line2.as_deref(),
+            Some(self.city.as_str()),
+            Some(region_and_code.as_str()),
+            Some(self.country.as_str()),
+        ];
+        return parts.iter().flatten().filter(|s| !s.is_empty()).copied().collect::<Vec<_>>().join(", ");
+    }
+}
+
+const COLUMNS: &str = "id, customerId, line1, line2, city, region, postalCode, country, isDefault";
+
+fn from_row(row: &Row) -> rusqlite::Result<CustomerAddress> {
+    Ok(CustomerAddress {
+        id: row.get(0)?,
+        customer_id: row.get(1)?,
+        fields: AddressFields {
+            line1: row.get(2)?,
+            line2: row.get(3)?,
+            city: row.get(4)?,
+            region: row.get(5)?,
+            postal_code: row.get(6)?,
+            country: row.get(7)?,
+        },
+        is_default: row.get(8)?,
+    })
+}
+
+// Keeps the legacy Customers.shippingAddress column in step with the default address
+fn sync_customer_address(db: &Connection, cid: i64) {
+    let query = "UPDATE customers SET shippingAddress = :address WHERE id = :cid";
+    if let Some(address) = query_default_address(db, cid) {
+        db.execute(query, named_params! {":address": address.fields.formatted(), ":cid": cid})
+            .log_expect("expected to be able to update Customers table in execute");
+    }
+}
+
+fn query_default_address(db: &Connection, cid: i64) -> Option<CustomerAddress> {
+    let query = format!("SELECT {} FROM CustomerAddresses WHERE customerId = :cid AND isDefault = 1", COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to be able to select from CustomerAddresses table in prepare");
+    let mut rows = stmt
+        .query_map(named_params! {":cid": cid}, from_row)
+        .log_expect("expected to be able to get default address from CustomerAddresses table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read CustomerAddresses row"));
+}
+
+pub fn create_address(actor: &Actor, cid: i64, fields: &AddressFields, is_default: bool) -> i64 {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
+    let aid = insert_address(&tx, actor, cid, fields, is_default);
+    tx.commit().log_expect("expected to be able to commit transaction");
+
+    info!(target: "file", "Successfully created address id: {} for customer id: {}", aid, cid);
+    return aid;
+}
+
+// Saves the address within the caller's transaction, such as the one creating the customer
+pub fn insert_address(db: &Connection, actor: &Actor, cid: i64, fields: &AddressFields, is_default: bool) -> i64 {
+    // The first address a customer saves is always their default
+    let has_default = query_default_address(db, cid).is_some();
+    let is_default = is_default || !has_default;
+    if is_default {
+        db.execute("UPDATE CustomerAddresses SET isDefault = 0 WHERE customerId = :cid", named_params! {":cid": cid})
+            .log_expect("expected to be able to update CustomerAddresses table in execute");
+    }
+    let query = "INSERT INTO CustomerAddresses (customerId, line1, line2, city, region, postalCode, country, isDefault) \
+                 VALUES (:cid, :line1, :line2, :city, :region, :postal_code, :country, :is_default)";
+    db.execute(query, named_params! {
+        ":cid": cid, ":line1": fields.line1, ":line2": fields.line2, ":city": fields.city,
+        ":region": fields.region, ":postal_code": fields.postal_code, ":country": fields.country,
+        ":is_default": is_default,
+    }).log_expect("expected to be able to insert into CustomerAddresses table in execute");
+    let aid = db.last_insert_rowid();
+    sync_customer_address(db, cid);
+    audit::record(db, actor, Action::AddressChanged, "customer_address", aid, None, query_address(db, cid, aid).as_ref());
+    return aid;
+}
+
+pub fn get_addresses(cid: i64) -> Vec<CustomerAddress> {
+    let db = connect();
+    let query = format!("SELECT {} FROM CustomerAddresses WHERE customerId = :cid ORDER BY isDefault DESC, id", COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to be able to select from CustomerAddresses table in prepare");
+    let addresses = stmt
+        .query_map(named_params! {":cid": cid}, from_row)
+        .log_expect("expected to be able to get addresses from CustomerAddresses table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read CustomerAddresses rows");
+    info!(target: "file", "Successfully retrieved {} addresses for customer id: {}", addresses.len(), cid);
+    return addresses;
+}
+
+pub fn get_address(cid: i64, aid: i64) -> Option<CustomerAddress> {
+    let db = connect();
+    return query_address(&db, cid, aid);
+}
+
+fn query_address(db: &Connection, cid: i64, aid: i64) -> Option<CustomerAddress> {
+    let query = format!("SELECT {} FROM CustomerAddresses WHERE customerId = :cid AND id = :aid", COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to be able to select from CustomerAddresses table in prepare");
+    let mut rows = stmt
+        .query_map(named_params! {":cid": cid, ":aid": aid}, from_row)
+        .log_expect("expected to be able to get address from CustomerAddresses table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read CustomerAddresses row"));
+}
+
+pub fn get_default_address(cid: i64) -> Option<CustomerAddress> {
+    let db = connect();
+    return query_default_address(&db, cid);
+}
+
+// Returns false if the customer has no such address
+pub fn update_address(actor: &Actor, cid: i64, aid: i64, fields: &AddressFields) -> bool {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
+    let before = query_address(&tx, cid, aid);
+    let query = "UPDATE CustomerAddresses SET line1 = :line1, line2 = :line2, city = :city, region = :region, \
+                 postalCode = :postal_code, country = :country WHERE customerId = :cid AND id = :aid";
+    let updated = tx.execute(query, named_params! {
+        ":cid": cid, ":aid": aid, ":line1": fields.line1, ":line2": fields.line2, ":city": fields.city,
+        ":region": fields.region, ":postal_code": fields.postal_code, ":country": fields.country,
+    }).log_expect("expected to be able to update CustomerAddresses table in execute");
+    sync_customer_address(&tx, cid);
+    if updated > 0 {
+        audit::record(&tx, actor, Action::AddressChanged, "customer_address", aid, before.as_ref(), query_address(&tx, cid, aid).as_ref());
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+
+    if updated > 0 {
+        info!(target: "file", "Successfully updated address id: {} of customer id: {} to {}", aid, cid, pii(fields.formatted()));
+    }
+    return updated > 0;
+}
+
+// Returns false if the customer has no such address
+pub fn set_default_address(actor: &Actor, cid: i64, aid: i64) -> bool {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
+    let before = query_address(&tx, cid, aid);
+    let query = "UPDATE CustomerAddresses SET isDefault = (id = :aid) WHERE customerId = :cid \
+                 AND EXISTS (SELECT 1 FROM CustomerAddresses WHERE customerId = :cid AND id = :aid)";
+    let updated = tx.execute(query, named_params! {":cid": cid, ":aid": aid})
+        .log_expect("expected to be able to update CustomerAddresses table in execute");
+    sync_customer_address(&tx, cid);
+    if updated > 0 {
+        audit::record(&tx, actor, Action::AddressChanged, "customer_address", aid, before.as_ref(), query_address(&tx, cid, aid).as_ref());
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+
+    if updated > 0 {
+        info!(target: "file", "Successfully set address id: {} as default for customer id: {}", aid, cid);
+    }
+    return updated > 0;
+}
+
+// Returns false if the customer has no such address. Orders keep their own copy of the address,
+// so deleting one never changes where a past order was sent.
+pub fn delete_address(actor: &Actor, cid: i64, aid: i64) -> bool {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
+    let before = query_address(&tx, cid, aid);
+    let deleted = tx.execute("DELETE FROM CustomerAddresses WHERE customerId = :cid AND id = :aid",
+                             named_params! {":cid": cid, ":aid": aid})
+        .log_expect("expected to be able to delete from CustomerAddresses table in execute");
+    // Promote the oldest remaining address if the default was removed
+    if query_default_address(&tx, cid).is_none() {
+        let query = "UPDATE CustomerAddresses SET isDefault = 1 WHERE id = \
+                     (SELECT MIN(id) FROM CustomerAddresses WHERE customerId = :cid)";
+        tx.execute(query, named_params! {":cid": cid})
+            .log_expect("expected to be able to update CustomerAddresses table in execute");
+    }
+    sync_customer_address(&tx, cid);
+    if deleted > 0 {
+        audit::record(&tx, actor, Action::AddressChanged, "customer_address", aid, before.as_ref(), None);
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+
+    if deleted > 0 {
+        info!(target: "file", "Successfully deleted address id: {} of customer id: {}", aid, cid);
+    }
+    return deleted > 0;
+}
@@ -0,0 +1,186 @@
+use super::db::connect;
+use super::books::LogErrResult;
+use rocket::serde::json;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use log::{error, info};
+
+// The previousHash of the first event
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    BookCreated,
+    BookUpdated,
+    BookDeleted,
+    BalanceChanged,
+    OrderShipped,
+    OrderCancelled,
+    AddressChanged,
+    PriceScheduled,
+    PriceCancelled,
+    PromotionChanged,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::BookCreated => "book_created",
+            Action::BookUpdated => "book_updated",
+            Action::BookDeleted => "book_deleted",
+            Action::BalanceChanged => "balance_changed",
+            Action::OrderShipped => "order_shipped",
+            Action::OrderCancelled => "order_cancelled",
+            Action::AddressChanged => "address_changed",
+            Action::PriceScheduled => "price_scheduled",
+            Action::PriceCancelled => "price_cancelled",
+            Action::PromotionChanged => "promotion_changed",
+        }
+    }
+}
+
+// A row of AuditEvents, with before and after as the JSON text that was hashed
+struct AuditEvent {
+    id: i64,
+    actor: String,
+    action: String,
+    entity: String,
+    entity_id: i64,
+    before: Option<String>,
+    after: Option<String>,
+    request_id: Option<String>,
+    created_at: String,
+    previous_hash: String,
+    hash: String,
+}
+
+// Who made a change, and the request it was made in. There are no logins yet, so a request acts for the
+// customer it names (see request_id::RequestActor), and the command line tools for themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub name: String,
+    pub request_id: Option<String>,
+}
+
+impl Actor {
+    pub fn cli() -> Actor {
+        return Actor { name: "cli".to_string(), request_id: None };
+    }
+
+    pub fn request(customer: Option<i64>, request_id: &str) -> Actor {
+        let name = match customer {
+            Some(cid) => format!("customer:{}", cid),
+            None => "anonymous".to_string(),
+        };
+        return Actor { name, request_id: Some(request_id.to_string()) };
+    }
+
+    // The same request acting for the customer, for requests that name them in the body rather than the path
+    pub fn for_customer(&self, cid: Option<i64>) -> Actor {
+        return match (cid, &self.request_id) {
+            (Some(cid), Some(request_id)) => Actor::request(Some(cid), request_id),
+            _ => self.clone(),
+        };
+    }
+}
+
+// Appends an event to the chain. The connection must be in a transaction that has already written,
+// or was begun IMMEDIATE, so no other event can be appended between reading the last hash and this insert.
+pub fn record<T: Serialize>(db: &Connection, actor: &Actor, action: Action, entity: &str, entity_id: i64, before: Option<&T>, after: Option<&T>) {
+    let previous_hash: String = db
+        .query_row("SELECT hash FROM AuditEvents ORDER BY id DESC LIMIT 1", (), |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to select from AuditEvents table in query_row")
+        .unwrap_or_else(|| GENESIS_HASH.to_string());
+    let created_at: String = db.query_row("SELECT strftime('%Y-%m-%d %H:%M:%f', 'now')", (), |row| row.get(0))
+        .log_expect("expected to be able to read the time in query_row");
+    let to_json = |value: Option<&T>| value.map(|v| json::to_string(v).expect("audited values should always serialize"));
+
+    let mut event = AuditEvent {
+        id: 0,
+        actor: actor.name.clone(),
+        action: action.name().to_string(),
+        entity: entity.to_string(),
+        entity_id,
+        before: to_json(before),
+        after: to_json(after),
+        request_id: actor.request_id.clone(),
+        created_at,
+        previous_hash,
+        hash: String::new(),
+    };
+    event.hash = hash_event(&event);
+
+    let query = "INSERT INTO AuditEvents (actor, action, entity, entityId, beforeValue, afterValue, requestId, createdAt, previousHash, hash) \
+                 VALUES (:actor, :action, :entity, :entity_id, :before, :after, :request_id, :created_at, :previous_hash, :hash)";
+    db.execute(query, named_params! {
+        ":actor": event.actor, ":action": event.action, ":entity": event.entity, ":entity_id": event.entity_id,
+        ":before": event.before, ":after": event.after, ":request_id": event.request_id, ":created_at": event.created_at,
+        ":previous_hash": event.previous_hash, ":hash": event.hash,
+    }).log_expect("expected to be able to insert into AuditEvents table in execute");
+    info!(target: "file", "Audited {} of {} id: {}", event.action, event.entity, event.entity_id);
+}
+
+// Covers every stored field but the id, in a fixed order, with before and after as stored
+fn hash_event(event: &AuditEvent) -> String {
+    let fields = (
+        &event.previous_hash, &event.actor, &event.action, &event.entity, event.entity_id,
+        &event.before, &event.after, &event.request_id, &event.created_at,
+    );
+    let mut hasher = Sha256::new();
+    hasher.update(json::to_string(&fields).expect("audit fields should always serialize").as_bytes());
+    return format!("{:x}", hasher.finalize());
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub events: i64,
+    // The first event whose hash or link doesn't match, everything after it is suspect too
+    pub first_invalid_id: Option<i64>,
+    pub reason: Option<String>,
+}
+
+pub fn verify_chain() -> ChainVerification {
+    let db = connect();
+    let query = "SELECT id, actor, action, entity, entityId, beforeValue, afterValue, requestId, createdAt, previousHash, hash \
+                 FROM AuditEvents ORDER BY id";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from AuditEvents table in prepare");
+    let rows = stmt
+        .query_map((), |row| Ok(AuditEvent {
+            id: row.get(0)?,
+            actor: row.get(1)?,
+            action: row.get(2)?,
+            entity: row.get(3)?,
+            entity_id: row.get(4)?,
+            before: row.get(5)?,
+            after: row.get(6)?,
+            request_id: row.get(7)?,
+            created_at: row.get(8)?,
+            previous_hash: row.get(9)?,
+            hash: row.get(10)?,
+        }))
+        .log_expect("expected to be able to get events from AuditEvents table in query_map");
+
+    let mut expected_previous = GENESIS_HASH.to_string();
+    let mut events = 0;
+    for row in rows {
+        let event = row.log_expect("expected to be able to read AuditEvents row");
+        events += 1;
+        let reason = if event.previous_hash != expected_previous {
+            Some("previous_hash does not match the hash of the event before it, an event was removed or reordered")
+        } else if hash_event(&event) != event.hash {
+            Some("hash does not match the event's contents, it was edited")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            error!(target: "file", "Audit chain broken at event id: {}: {}", event.id, reason);
+            return ChainVerification { valid: false, events, first_invalid_id: Some(event.id), reason: Some(reason.to_string()) };
+        }
+        expected_previous = event.hash;
+    }
+    info!(target: "file", "Audit chain verified, {} events", events);
+    return ChainVerification { valid: true, events, first_invalid_id: None, reason: None };
+}
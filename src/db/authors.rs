@@ -0,0 +1,132 @@
+use super::books::{book_from_row, BookRecord, LogErrResult, BOOK_COLUMNS};
+use super::db::connect;
+use crate::text::canonical_key;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use log::info;
+
+// What a person did on a book, stored in BookContributors.role
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Author,
+    Editor,
+    Translator,
+    Illustrator,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Author, Role::Editor, Role::Translator, Role::Illustrator];
+
+    pub fn parse(role: &str) -> Option<Role> {
+        return Role::ALL.into_iter().find(|r| r.as_str() == role);
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Editor => "editor",
+            Role::Translator => "translator",
+            Role::Illustrator => "illustrator",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AuthorRecord {
+    pub id: i64,
+    pub name: String,
+    // Books they contributed to in any role
+    pub books: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContributedBook {
+    pub role: Role,
+    pub book: BookRecord,
+}
+
+// Names given together in one field, such as "Terry Pratchett, Neil Gaiman"
+pub fn split_names(names: &str) -> Vec<String> {
+    return names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect();
+}
+
+// Replaces a book's contributors in one role with the names in the order given, adding authors not seen before.
+// Authors are matched regardless of case and Unicode normalization form, see text::canonical_key.
+pub fn set_contributors(db: &Connection, bid: i64, role: Role, names: &[String]) {
+    db.execute("DELETE FROM BookContributors WHERE bookId = :bid AND role = :role", named_params! {":bid": bid, ":role": role.as_str()})
+        .log_expect("expected to be able to delete from BookContributors table in execute");
+    for (position, name) in names.iter().enumerate() {
+        let aid = author_id(db, name);
+        db.execute("INSERT OR IGNORE INTO BookContributors (bookId, authorId, role, position) VALUES (:bid, :aid, :role, :position)",
+                   named_params! {":bid": bid, ":aid": aid, ":role": role.as_str(), ":position": position as i64})
+            .log_expect("expected to be able to insert into BookContributors table in execute");
+    }
+}
+
+fn author_id(db: &Connection, name: &str) -> i64 {
+    let key = canonical_key(name);
+    let existing = db
+        .query_row("SELECT id FROM Authors WHERE nameKey = :name_key", named_params! {":name_key": key}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Authors table in query_row");
+    if let Some(aid) = existing {
+        return aid;
+    }
+    db.execute("INSERT INTO Authors (name, nameKey) VALUES (:name, :name_key)", named_params! {":name": name, ":name_key": key})
+        .log_expect("expected to be able to insert into Authors table in execute");
+    info!(target: "file", "Successfully created author id: {}", db.last_insert_rowid());
+    return db.last_insert_rowid();
+}
+
+fn author_from_row(row: &Row) -> rusqlite::Result<AuthorRecord> {
+    Ok(AuthorRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        books: row.get(2)?,
+    })
+}
+
+const AUTHOR_COLUMNS: &str = "id, name, (SELECT COUNT(DISTINCT bookId) FROM BookContributors WHERE authorId = Authors.id)";
+
+// Authors by name, those whose names contain the given text if any, and the count of all that match
+pub fn list_authors(name: Option<&str>, limit: i64, offset: i64) -> (Vec<AuthorRecord>, i64) {
+    let db = connect();
+    let pattern = format!("%{}%", canonical_key(name.unwrap_or_default()).replace(['%', '_'], ""));
+    let query = format!("SELECT {} FROM Authors WHERE nameKey LIKE :pattern ORDER BY nameKey LIMIT :limit OFFSET :offset", AUTHOR_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let authors = stmt
+        .query_map(named_params! {":pattern": pattern, ":limit": limit, ":offset": offset}, author_from_row)
+        .log_expect("expected to be able to get authors from Authors table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Authors rows");
+    let total = db
+        .query_row("SELECT COUNT(*) FROM Authors WHERE nameKey LIKE :pattern", named_params! {":pattern": pattern}, |row| row.get(0))
+        .log_expect("expected to be able to count Authors table in query_row");
+    return (authors, total);
+}
+
+pub fn get_author(aid: i64) -> Option<AuthorRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Authors WHERE id = :aid", AUTHOR_COLUMNS);
+    return db
+        .query_row(&query, named_params! {":aid": aid}, author_from_row)
+        .optional()
+        .log_expect("expected to be able to get author from Authors table in query_row");
+}
+
+// Every book an author contributed to, by id, once for each of their roles on it
+pub fn author_books(aid: i64) -> Vec<ContributedBook> {
+    let db = connect();
+    let query = format!("SELECT {}, BookContributors.role AS role FROM Books JOIN BookContributors ON BookContributors.bookId = Books.id \
+                         WHERE BookContributors.authorId = :aid ORDER BY Books.id, BookContributors.role", BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map(named_params! {":aid": aid}, |row| {
+            let role = Role::parse(&row.get::<_, String>("role")?).unwrap_or(Role::Author);
+            Ok(ContributedBook { role, book: book_from_row(row)? })
+        })
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+}
@@ -1,4 +1,12 @@
+use super::audit::{self, Action, Actor};
+use super::authors::{self, split_names, Role};
 use super::db::connect;
+use super::prices;
+use super::taxonomy;
+use super::works;
+use crate::text::canonical_key;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::{Deserialize, Serialize};
 use log::{info, error};
 use std::fmt::Debug;
 
@@ -13,37 +21,442 @@ impl<T, E: Debug> LogErrResult<T, E> for Result<T, E> {
     }
 }
 
-pub fn create_book(title: String, author: String, price: f64) {
-    let db = connect();
-    let query = "INSERT INTO books (title, author, price) VALUES (:title, :author, :price)";
-    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
-    stmt.execute(&[(":title", &title), (":author", &author), (":price", &format!("{}",price))])
-        .log_expect("expected to be able to insert into Books table in execute");
-    info!(target: "file", "Successfully created book: Author: {}, Title: {}, Price: {:.2}", author, title, price);
+// How an edition is made, stored in Books.format
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EditionFormat {
+    Hardcover,
+    Paperback,
+    Ebook,
+    Audiobook,
+}
+
+impl EditionFormat {
+    pub const ALL: [EditionFormat; 4] = [EditionFormat::Hardcover, EditionFormat::Paperback, EditionFormat::Ebook, EditionFormat::Audiobook];
+
+    pub fn parse(format: &str) -> Option<EditionFormat> {
+        return EditionFormat::ALL.into_iter().find(|f| f.as_str() == format);
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EditionFormat::Hardcover => "hardcover",
+            EditionFormat::Paperback => "paperback",
+            EditionFormat::Ebook => "ebook",
+            EditionFormat::Audiobook => "audiobook",
+        }
+    }
+}
+
+// Stored in Books.availability, ONIX's code list 65 grouped, see onix::availability
+pub const AVAILABILITY: [&str; 4] = ["available", "not_yet_available", "temporarily_unavailable", "unavailable"];
+
+// One edition of a work, see works.rs. Orders, prices and stock are by edition.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BookRecord {
+    pub id: i64,
+    pub title: String,
+    pub author: String,
+    pub price: f64,
+    // Names joined by ", " from BookContributors, see authors::set_contributors. The authors are in author.
+    pub editor: Option<String>,
+    pub translator: Option<String>,
+    pub illustrator: Option<String>,
+    // From ONIX feeds, see onix.rs
+    pub isbn: Option<String>,
+    pub availability: Option<String>,
+    pub description: Option<String>,
+    // The work's, shared by all of its editions
+    pub work_id: i64,
+    pub series: Option<String>,
+    pub series_position: Option<i64>,
+    pub format: Option<EditionFormat>,
+    pub publisher: Option<String>,
+    // Of form YYYY-MM-DD
+    pub publication_date: Option<String>,
+    // Copies on hand, None when they aren't counted
+    pub stock: Option<i64>,
+}
+
+// The columns read by book_from_row, with the price that applies now, see prices::current_price,
+// and the contributors in each role other than author
+pub const BOOK_COLUMNS: &str = "Books.id, Books.title, Books.author, \
+    (SELECT BookPrices.price FROM BookPrices WHERE BookPrices.bookId = Books.id AND BookPrices.effectiveFrom <= datetime('now') \
+     ORDER BY BookPrices.effectiveFrom DESC LIMIT 1), \
+    (SELECT group_concat(name, ', ') FROM (SELECT Authors.name FROM BookContributors JOIN Authors ON Authors.id = BookContributors.authorId \
+     WHERE BookContributors.bookId = Books.id AND BookContributors.role = 'editor' ORDER BY BookContributors.position)), \
+    (SELECT group_concat(name, ', ') FROM (SELECT Authors.name FROM BookContributors JOIN Authors ON Authors.id = BookContributors.authorId \
+     WHERE BookContributors.bookId = Books.id AND BookContributors.role = 'translator' ORDER BY BookContributors.position)), \
+    (SELECT group_concat(name, ', ') FROM (SELECT Authors.name FROM BookContributors JOIN Authors ON Authors.id = BookContributors.authorId \
+     WHERE BookContributors.bookId = Books.id AND BookContributors.role = 'illustrator' ORDER BY BookContributors.position)), \
+    Books.isbn, Books.availability, Books.description, Books.workId, \
+    (SELECT Series.name FROM Works JOIN Series ON Series.id = Works.seriesId WHERE Works.id = Books.workId), \
+    (SELECT Works.seriesPosition FROM Works WHERE Works.id = Books.workId), \
+    Books.format, Books.publisher, Books.publicationDate, Books.stock";
+
+// An existing book that a new one would duplicate, by its id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookConflict {
+    // The same title, author and format
+    SameEdition(i64),
+    SameIsbn(i64),
+}
+
+// Stores every field but the id, which the database gives, unless another book has its ISBN
+pub fn create_book(actor: &Actor, book: BookRecord) -> Result<i64, BookConflict> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    if let Some(bid) = query_edition_id(&tx, &book.title, &book.author, book.format) {
+        error!(target: "file", "Book {} by {} not created, book id {} is the same edition", book.title, book.author, bid);
+        return Err(BookConflict::SameEdition(bid));
+    }
+    if let Some(bid) = book.isbn.as_ref().and_then(|isbn| query_isbn_id(&tx, isbn)) {
+        error!(target: "file", "Book with ISBN {} not created, book id {} has it", book.isbn.unwrap_or_default(), bid);
+        return Err(BookConflict::SameIsbn(bid));
+    }
+    let book = insert_book(&tx, actor, book);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully created book: Author: {}, Title: {}, Price: {:.2}", book.author, book.title, book.price);
+    return Ok(book.id);
+}
+
+// Inserts every field but the id, which the database gives, as an edition of the work with its title and author
+fn insert_book(db: &Connection, actor: &Actor, book: BookRecord) -> BookRecord {
+    let wid = set_work(db, &book);
+    let query = "INSERT INTO books (title, author, price, isbn, availability, description, titleKey, authorKey, \
+                 workId, format, publisher, publicationDate, stock) \
+                 VALUES (:title, :author, :price, :isbn, :availability, :description, :title_key, :author_key, \
+                 :wid, :format, :publisher, :publication_date, :stock)";
+    db.execute(query, named_params! {
+        ":title": book.title, ":author": book.author, ":price": book.price, ":isbn": book.isbn,
+        ":availability": book.availability, ":description": book.description,
+        ":title_key": canonical_key(&book.title), ":author_key": canonical_key(&book.author),
+        ":wid": wid, ":format": book.format.map(|f| f.as_str()), ":publisher": book.publisher,
+        ":publication_date": book.publication_date, ":stock": book.stock,
+    }).log_expect("expected to be able to insert into Books table in execute");
+    let bid = db.last_insert_rowid();
+    prices::record_price(db, bid, book.price, None);
+    set_book_contributors(db, &BookRecord { id: bid, ..book });
+    let book = query_book(db, bid).expect("the inserted book should be readable in its transaction");
+    audit::record(db, actor, Action::BookCreated, "book", bid, None, Some(&book));
+    return book;
 }
 
-pub fn get_book_id(title: String, author: String) -> i64 {
+// Keeps BookContributors in step with the names in a book's author, editor, translator and illustrator
+fn set_book_contributors(db: &Connection, book: &BookRecord) {
+    let names = [(Role::Author, Some(&book.author)), (Role::Editor, book.editor.as_ref()),
+                 (Role::Translator, book.translator.as_ref()), (Role::Illustrator, book.illustrator.as_ref())];
+    for (role, names) in names {
+        authors::set_contributors(db, book.id, role, &names.map(|n| split_names(n)).unwrap_or_default());
+    }
+}
+
+// The work the book is an edition of, by its title and author, in the book's series if it has one.
+// Books without a series leave their work's as it is.
+fn set_work(db: &Connection, book: &BookRecord) -> i64 {
+    let wid = works::work_id(db, &book.title, &book.author);
+    if let Some(series) = &book.series {
+        works::set_series(db, wid, series, book.series_position);
+    }
+    return wid;
+}
+
+// Stores every field of an existing book, returning it as read back. A new title or author makes it an edition of another work,
+// and a new price applies from now.
+fn write_book(db: &Connection, book: &BookRecord) -> BookRecord {
+    if prices::current_price(db, book.id) != Some(book.price) {
+        prices::record_price(db, book.id, book.price, None);
+    }
+    let wid = set_work(db, book);
+    let query = "UPDATE books SET title = :title, author = :author, price = :price, isbn = :isbn, availability = :availability, \
+                 description = :description, titleKey = :title_key, authorKey = :author_key, workId = :wid, format = :format, \
+                 publisher = :publisher, publicationDate = :publication_date, stock = :stock WHERE id = :bid";
+    db.execute(query, named_params! {
+        ":title": book.title, ":author": book.author, ":price": book.price, ":isbn": book.isbn,
+        ":availability": book.availability, ":description": book.description,
+        ":title_key": canonical_key(&book.title), ":author_key": canonical_key(&book.author),
+        ":wid": wid, ":format": book.format.map(|f| f.as_str()), ":publisher": book.publisher,
+        ":publication_date": book.publication_date, ":stock": book.stock, ":bid": book.id,
+    }).log_expect("expected to be able to update Books table in execute");
+    set_book_contributors(db, book);
+    works::remove_unused(db);
+    return query_book(db, book.id).expect("the updated book should be readable in its transaction");
+}
+
+// Adds every book in one transaction, or none of them if any has the same title, author
+// and format as an existing book, giving their indexes with the existing ids. A dry run rolls back either way.
+pub fn import_books(actor: &Actor, new_books: Vec<BookRecord>, dry_run: bool) -> Result<usize, Vec<(usize, BookConflict)>> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let existing = new_books
+        .iter()
+        .enumerate()
+        .filter_map(|(i, book)| {
+            let edition = query_edition_id(&tx, &book.title, &book.author, book.format).map(BookConflict::SameEdition);
+            let isbn = || book.isbn.as_ref().and_then(|isbn| query_isbn_id(&tx, isbn)).map(BookConflict::SameIsbn);
+            edition.or_else(isbn).map(|conflict| (i, conflict))
+        })
+        .collect::<Vec<_>>();
+    if !existing.is_empty() {
+        return Err(existing);
+    }
+    let count = new_books.len();
+    for book in new_books {
+        insert_book(&tx, actor, book);
+    }
+    if dry_run {
+        return Ok(count);
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully imported {} books", count);
+    return Ok(count);
+}
+
+// Finds books by the whole author, such as "Terry Pratchett, Neil Gaiman", or by any one contributor in any role.
+// Matches regardless of case and Unicode normalization form, see text::canonical_key
+const BOOK_ID_QUERY: &str = "SELECT id FROM books WHERE titleKey = :title_key AND (authorKey = :author_key OR id IN \
+    (SELECT bookId FROM BookContributors JOIN Authors ON Authors.id = BookContributors.authorId WHERE Authors.nameKey = :author_key)) \
+    ORDER BY id";
+
+// The book's id by its title and author, None when there is no such book
+pub fn find_book_id(title: &str, author: &str) -> Option<i64> {
     let db = connect();
-    let query = "SELECT id FROM books WHERE title = :title AND author = :author";
-    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
+    return query_book_id(&db, title, author);
+}
+
+fn query_isbn_id(db: &Connection, isbn: &str) -> Option<i64> {
+    return db
+        .query_row("SELECT id FROM books WHERE isbn = :isbn", named_params! { ":isbn": isbn }, |row| row.get::<_, i64>(0))
+        .optional()
+        .log_expect("expected to be able to get id from Books table in query_row");
+}
+
+fn query_edition_id(db: &Connection, title: &str, author: &str, format: Option<EditionFormat>) -> Option<i64> {
+    let query = "SELECT id FROM books WHERE titleKey = :title_key AND authorKey = :author_key AND format IS :format ORDER BY id";
+    return db
+        .query_row(query, named_params! {
+            ":title_key": canonical_key(title), ":author_key": canonical_key(author), ":format": format.map(|f| f.as_str()),
+        }, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Books table in query_row");
+}
+
+fn query_book_id(db: &Connection, title: &str, author: &str) -> Option<i64> {
+    let mut stmt = db.prepare(BOOK_ID_QUERY).log_expect("expected to prepare statement correctly in prepare");
 
     let mut rows = stmt
-        .query_map(&[(":title", &title), (":author", &author)], |row| row.get(0))
+        .query_map(named_params! {":title_key": canonical_key(title), ":author_key": canonical_key(author)}, |row| row.get(0))
         .log_expect("expected to be able to get id from Books table in query_map");
-    let id = rows.next().unwrap().unwrap();
-    return id;
+    return rows.next().map(|r| r.log_expect("expected to be able to read Books row"));
 }
 
-pub fn get_book_price(bid: i64) -> f64 {
+pub fn book_from_row(row: &Row) -> rusqlite::Result<BookRecord> {
+    Ok(BookRecord {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        author: row.get(2)?,
+        price: row.get(3)?,
+        editor: row.get(4)?,
+        translator: row.get(5)?,
+        illustrator: row.get(6)?,
+        isbn: row.get(7)?,
+        availability: row.get(8)?,
+        description: row.get(9)?,
+        work_id: row.get(10)?,
+        series: row.get(11)?,
+        series_position: row.get(12)?,
+        format: row.get::<_, Option<String>>(13)?.and_then(|f| EditionFormat::parse(&f)),
+        publisher: row.get(14)?,
+        publication_date: row.get(15)?,
+        stock: row.get(16)?,
+    })
+}
+
+pub fn get_book(bid: i64) -> Option<BookRecord> {
     let db = connect();
-    let query = "SELECT price FROM books WHERE id = :bid";
-    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
-    
+    return query_book(&db, bid);
+}
+
+fn query_book(db: &Connection, bid: i64) -> Option<BookRecord> {
+    let query = format!("SELECT {} FROM books WHERE Books.id = :bid", BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+
     let mut rows = stmt
-        .query_map(&[(":bid", &format!("{}", bid))], |row| row.get(0))
-        .log_expect("expected to be able to get price from Books table in query_map");
-    let price = rows.next().unwrap().unwrap();
+        .query_map(named_params! {":bid": bid}, book_from_row)
+        .log_expect("expected to be able to get book from Books table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read Books row"));
+}
+
+pub fn list_books() -> Vec<BookRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM books ORDER BY Books.id", BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let books = stmt
+        .query_map((), book_from_row)
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+    info!(target: "file", "Successfully retrieved {} books", books.len());
+    return books;
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Upserted {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+// In one transaction, replaces the book with each book's ISBN, or else the one with the same title and author and no ISBN yet,
+// in the same format or none, or adds it. Returns each book's id and what was done.
+pub fn upsert_books_by_isbn(actor: &Actor, new_books: Vec<BookRecord>) -> Vec<(i64, Upserted)> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let mut results = Vec::new();
+    for book in new_books {
+        let query = "SELECT id FROM books WHERE isbn = :isbn OR (isbn IS NULL AND titleKey = :title_key AND authorKey = :author_key \
+                     AND (format IS NULL OR format IS :format)) ORDER BY isbn IS NULL, format IS NULL LIMIT 1";
+        let existing = tx
+            .query_row(query, named_params! {
+                ":isbn": book.isbn, ":title_key": canonical_key(&book.title), ":author_key": canonical_key(&book.author),
+                ":format": book.format.map(|f| f.as_str()),
+            }, |row| row.get::<_, i64>(0))
+            .optional()
+            .log_expect("expected to be able to get id from Books table in query_row");
+        let before = match existing.and_then(|bid| query_book(&tx, bid)) {
+            Some(before) => before,
+            None => {
+                results.push((insert_book(&tx, actor, book).id, Upserted::Created));
+                continue;
+            },
+        };
+        // Stock is counted by the shop, not the publisher
+        let after = BookRecord {
+            id: before.id,
+            work_id: before.work_id,
+            series: book.series.or(before.series.clone()),
+            series_position: book.series_position.or(before.series_position),
+            stock: before.stock,
+            ..book
+        };
+        if after == before {
+            results.push((before.id, Upserted::Unchanged));
+            continue;
+        }
+        let after = write_book(&tx, &after);
+        audit::record(&tx, actor, Action::BookUpdated, "book", after.id, Some(&before), Some(&after));
+        results.push((after.id, Upserted::Updated));
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully upserted {} books by ISBN", results.len());
+    return results;
+}
+
+// Up to limit books with ids above after, by id, to go through the catalog a page at a time
+pub fn list_books_after(after: i64, limit: i64) -> Vec<BookRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM books WHERE Books.id > :after ORDER BY Books.id LIMIT :limit", BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map(named_params! {":after": after, ":limit": limit}, book_from_row)
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+}
+
+// Fields left as None keep their value. Returns the updated book, or None if there is no such book.
+pub fn update_book(actor: &Actor, bid: i64, title: Option<String>, author: Option<String>, price: Option<f64>) -> Option<BookRecord> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let before = query_book(&tx, bid)?;
+    let after = BookRecord {
+        id: bid,
+        title: title.unwrap_or_else(|| before.title.clone()),
+        author: author.unwrap_or_else(|| before.author.clone()),
+        price: price.unwrap_or(before.price),
+        ..before.clone()
+    };
+    let after = write_book(&tx, &after);
+    audit::record(&tx, actor, Action::BookUpdated, "book", bid, Some(&before), Some(&after));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully updated book id: {} to Author: {}, Title: {}, Price: {:.2}", bid, after.author, after.title, after.price);
+    return Some(after);
+}
+
+pub enum DeleteBookError {
+    NotFound,
+    // Orders keep pointing at their book, so a book that was ordered stays
+    HasOrders(i64),
+    // Promotions for the book are kept with the orders they discounted, by their count
+    HasPromotions(i64),
+}
+
+pub fn delete_book(actor: &Actor, bid: i64) -> Result<BookRecord, DeleteBookError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let book = query_book(&tx, bid).ok_or(DeleteBookError::NotFound)?;
+    let orders: i64 = tx.query_row("SELECT COUNT(*) FROM PurchaseOrders WHERE bookId = :bid", named_params! {":bid": bid}, |row| row.get(0))
+        .log_expect("expected to be able to count PurchaseOrders table in query_row");
+    if orders > 0 {
+        return Err(DeleteBookError::HasOrders(orders));
+    }
+    let promotions: i64 = tx.query_row("SELECT COUNT(*) FROM Promotions WHERE bookId = :bid", named_params! {":bid": bid}, |row| row.get(0))
+        .log_expect("expected to be able to count Promotions table in query_row");
+    if promotions > 0 {
+        return Err(DeleteBookError::HasPromotions(promotions));
+    }
+    tx.execute("DELETE FROM BookContributors WHERE bookId = :bid", named_params! {":bid": bid})
+        .log_expect("expected to be able to delete from BookContributors table in execute");
+    taxonomy::remove_book(&tx, bid);
+    prices::remove_book(&tx, bid);
+    tx.execute("DELETE FROM books WHERE id = :bid", named_params! {":bid": bid})
+        .log_expect("expected to be able to delete from Books table in execute");
+    works::remove_unused(&tx);
+    audit::record(&tx, actor, Action::BookDeleted, "book", bid, Some(&book), None);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully deleted book id: {}", bid);
+    return Ok(book);
+}
+
+// The price that applies now, see prices.rs. None if there is no such book.
+pub fn get_book_price(bid: i64) -> Option<f64> {
+    let db = connect();
+    let price = prices::current_price(&db, bid)?;
 
     info!(target: "file", "Successfully got book id: {}'s price of {:.2}", bid, price);
-    return price;
-}
\ No newline at end of file
+    return Some(price);
+}
+
+// Sets the copies on hand, None to stop counting them. Returns the updated book, or None if there is no such book.
+pub fn set_stock(actor: &Actor, bid: i64, stock: Option<i64>) -> Option<BookRecord> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let before = query_book(&tx, bid)?;
+    tx.execute("UPDATE books SET stock = :stock WHERE id = :bid", named_params! {":stock": stock, ":bid": bid})
+        .log_expect("expected to be able to update Books table in execute");
+    let after = query_book(&tx, bid).expect("the updated book should be readable in its transaction");
+    audit::record(&tx, actor, Action::BookUpdated, "book", bid, Some(&before), Some(&after));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully set book id: {}'s stock to {:?}", bid, stock);
+    return Some(after);
+}
+
+// Takes the copies for an order within its transaction, false when fewer are left. Books whose stock isn't counted never run out.
+pub fn take_from_stock(db: &Connection, bid: i64, quantity: i64) -> bool {
+    let query = "UPDATE books SET stock = stock - :quantity WHERE id = :bid AND stock >= :quantity";
+    let taken = db.execute(query, named_params! {":bid": bid, ":quantity": quantity})
+        .log_expect("expected to be able to update Books table in execute");
+    if taken > 0 {
+        return true;
+    }
+    let stock: Option<i64> = db.query_row("SELECT stock FROM books WHERE id = :bid", named_params! {":bid": bid}, |row| row.get(0))
+        .log_expect("expected to be able to get stock from Books table in query_row");
+    return stock.is_none();
+}
+
+// Puts back the copies a cancelled order took, within its transaction
+pub fn return_to_stock(db: &Connection, bid: i64, quantity: i64) {
+    db.execute("UPDATE books SET stock = stock + :quantity WHERE id = :bid AND stock IS NOT NULL", named_params! {":bid": bid, ":quantity": quantity})
+        .log_expect("expected to be able to update Books table in execute");
+}
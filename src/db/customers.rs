@@ -1,4 +1,11 @@
 use super::db::connect;
+use crate::text::canonical_key;
+use crate::redact::pii;
+use super::addresses::{self, AddressFields};
+use super::audit::{self, Action, Actor};
+use rocket::serde::json::serde_json::json;
+use rusqlite::{named_params, OptionalExtension, Row, TransactionBehavior};
+use serde::Serialize;
 use log::{info, error};
 use std::fmt::Debug;
 pub trait LogErrResult<T, E : Debug> {
@@ -11,47 +18,118 @@ impl<T, E: Debug> LogErrResult<T, E> for Result<T, E> {
     }
 }
 
-pub fn create_customer(name: String, address: String) {
-    let db = connect();
+// The customer and their default address are saved in one transaction, so no customer is left without an address
+pub fn create_customer(actor: &Actor, name: String, address: &AddressFields) -> i64 {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
     // Default balance of 5 dollars is added
-    let query = "INSERT INTO customers (name, shippingAddress, accountBalance) VALUES (:name, :address, 5.00)";
-    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
-    stmt.execute(&[(":name", &name), (":address", &address)])
+    let query = "INSERT INTO customers (name, shippingAddress, accountBalance, nameKey) VALUES (:name, :address, 5.00, :name_key)";
+    tx.execute(query, &[(":name", &name), (":address", &address.formatted()), (":name_key", &canonical_key(&name))])
         .log_expect("expected to be able to insert into Customers table in execute");
-    info!(target: "file", "Successfully created customer: {}, Address: {}", name, address);
+    let cid = tx.last_insert_rowid();
+    addresses::insert_address(&tx, actor, cid, address, true);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully created customer: {}, Address: {}", pii(&name), pii(address.formatted()));
+    return cid;
 }
 
-pub fn get_customer_id(name: String, address: String) -> i64 {
+// The address may be any of the customer's saved addresses, as the free text line1 or fully formatted.
+// Names match regardless of case and Unicode normalization form, see text::canonical_key
+pub fn get_customer_id(name: String, address: String) -> Option<i64> {
     let db = connect();
-    let query = "SELECT id FROM customers WHERE name = :name AND shippingAddress = :address";
+    let query = "SELECT id FROM customers c WHERE nameKey = :name_key AND (shippingAddress = :address \
+                 OR EXISTS (SELECT 1 FROM CustomerAddresses a WHERE a.customerId = c.id AND a.line1 = :address))";
     let mut stmt = db.prepare(query).log_expect("expected to be able to select from Customers table in prepare");
 
     let mut rows = stmt
-        .query_map(&[(":name", &name), (":address", &address)], |row| row.get(0))
+        .query_map(&[(":name_key", &canonical_key(&name)), (":address", &address)], |row| row.get(0))
         .log_expect("expected to be able to get id from Customers table in query_map");
-    let id = rows.next().unwrap().unwrap();
-    return id;
+    return rows.next().map(|r| r.log_expect("expected to be able to read Customers row"));
+}
+
+pub fn get_customer_name(cid: i64) -> Option<String> {
+    let db = connect();
+    let query = "SELECT name FROM customers WHERE id = :cid";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from Customers table in prepare");
+
+    let mut rows = stmt
+        .query_map(&[(":cid", &cid)], |row| row.get(0))
+        .log_expect("expected to be able to get name from Customers table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read Customers row"));
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CustomerRecord {
+    pub id: i64,
+    pub name: String,
+    pub account_balance: f64,
+    // The default address, formatted
+    pub shipping_address: String,
+}
+
+fn customer_from_row(row: &Row) -> rusqlite::Result<CustomerRecord> {
+    Ok(CustomerRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        account_balance: row.get(2)?,
+        shipping_address: row.get(3)?,
+    })
+}
+
+pub fn get_customer(cid: i64) -> Option<CustomerRecord> {
+    let db = connect();
+    let query = "SELECT id, name, accountBalance, shippingAddress FROM customers WHERE id = :cid";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from Customers table in prepare");
+
+    let mut rows = stmt
+        .query_map(named_params! {":cid": cid}, customer_from_row)
+        .log_expect("expected to be able to get customer from Customers table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read Customers row"));
 }
 
-pub fn get_customer_address(cid: i64) -> String {
+pub fn list_customers() -> Vec<CustomerRecord> {
+    let db = connect();
+    let query = "SELECT id, name, accountBalance, shippingAddress FROM customers ORDER BY id";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from Customers table in prepare");
+    let customers = stmt
+        .query_map((), customer_from_row)
+        .log_expect("expected to be able to get customers from Customers table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Customers rows");
+    info!(target: "file", "Successfully retrieved {} customers", customers.len());
+    return customers;
+}
+
+pub fn customer_exists(cid: i64) -> bool {
+    let db = connect();
+    let query = "SELECT EXISTS (SELECT 1 FROM customers WHERE id = :cid)";
+    let exists: bool = db.query_row(query, &[(":cid", &cid)], |row| row.get(0))
+        .log_expect("expected to be able to select from Customers table in query_row");
+    return exists;
+}
+
+// None for a customer that doesn't exist
+pub fn get_customer_address(cid: i64) -> Option<String> {
     let db = connect();
     let query = "SELECT shippingAddress FROM customers WHERE id = :cid";
     let mut stmt = db.prepare(query).log_expect("expected to be able to select from Customers table in prepare");
 
     let mut rows = stmt
-        .query_map(&[(":cid", &format!("{}",cid))], |row| row.get(0))
+        .query_map(&[(":cid", &cid)], |row| row.get(0))
         .log_expect("expected to be able to get shippingAddress from Customers table in query_map");
-    let address = rows.next().unwrap().unwrap();
-    info!(target: "file", "Successfully retrieved cid {}'s address: {}", cid, address);
-    return address;
+    let address: String = rows.next()?.log_expect("expected to be able to read Customers row");
+    info!(target: "file", "Successfully retrieved cid {}'s address: {}", cid, pii(&address));
+    return Some(address);
 }
 
-pub fn update_customer_address(cid: i64, address: String) {
-    let db = connect();
-    let query = "UPDATE customers SET shippingAddress = :address WHERE id = :cid";
-    let mut stmt = db.prepare(query).log_expect("expected to be able to update Customers table in prepare");
-    stmt.execute(&[(":address", &address), (":cid", &format!("{}",cid))]).log_expect("expected to be able to update Customers table in execute");
-    info!(target: "file", "Successfully updated address of cid {} to {}", cid, address);
+// Replaces the default address with a free text one, kept for the updateAddress endpoint
+pub fn update_customer_address(actor: &Actor, cid: i64, address: String) {
+    let fields = AddressFields::free_text(address.clone());
+    match addresses::get_default_address(cid) {
+        Some(default) => { addresses::update_address(actor, cid, default.id, &fields); },
+        None => { addresses::create_address(actor, cid, &fields, true); },
+    };
+    info!(target: "file", "Successfully updated address of cid {} to {}", cid, pii(&address));
 }
 
 pub fn get_customer_balance(cid: i64) -> f64 {
@@ -67,11 +145,32 @@ pub fn get_customer_balance(cid: i64) -> f64 {
     return balance;
 }
 
-pub fn update_customer_balance(cid : i64, balance : f64) {
-    let db = connect();
-    let query = "UPDATE customers SET accountBalance = :balance WHERE id = :cid";
-    // let mut stmt = db.prepare(query).expect("expected to be able to update Customers table");
-    let mut stmt = db.prepare(query).log_expect("expected to be able to update Customers table in prepare");
-    stmt.execute(&[(":balance", &balance), (":cid", &(cid as f64))]).log_expect("expected to be able to update Customers table in execute");
+pub fn update_customer_balance(actor: &Actor, cid : i64, balance : f64) {
+    change_customer_balance(actor, cid, |_| balance);
     info!(target: "file", "Successfully updated balance of cid {} to {}", cid, balance);
 }
+
+// Adds amount, negative to deduct, in one transaction. Returns the new balance, or None if there is no such customer.
+pub fn adjust_customer_balance(actor: &Actor, cid: i64, amount: f64) -> Option<f64> {
+    let balance = change_customer_balance(actor, cid, |before| before + amount)?;
+    info!(target: "file", "Successfully adjusted balance of cid {} by {:.2} to {:.2}", cid, amount, balance);
+    return Some(balance);
+}
+
+fn change_customer_balance(actor: &Actor, cid: i64, change: impl FnOnce(f64) -> f64) -> Option<f64> {
+    let mut db = connect();
+    // Immediate, so the balance read is the one being replaced
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let before: Option<f64> = tx
+        .query_row("SELECT accountBalance FROM customers WHERE id = :cid", named_params! {":cid": cid}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to select from Customers table in query_row");
+    let before = before?;
+    let balance = change(before);
+    let query = "UPDATE customers SET accountBalance = :balance WHERE id = :cid";
+    tx.execute(query, named_params! {":balance": balance, ":cid": cid}).log_expect("expected to be able to update Customers table in execute");
+    audit::record(&tx, actor, Action::BalanceChanged, "customer", cid,
+                  Some(&json!({"account_balance": before})), Some(&json!({"account_balance": balance})));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    return Some(balance);
+}
@@ -1,22 +1,33 @@
 use rusqlite::{Connection};
 use std::{fs, path::Path};
+use super::migrations;
+
+pub const DATABASE_PATH: &str = "dd.db";
+pub const SCHEMA_PATH: &str = "init.sql";
+// Catalog data for a new database that needs the tables of later migrations
+pub const SEED_PATH: &str = "seed.sql";
 
 pub fn connect() -> Connection {
     let mut must_initialize_db = false;
-    if !Path::new("dd.db").exists() {
+    if !Path::new(DATABASE_PATH).exists() {
         must_initialize_db = true;
     }
 
-    let connection = Connection::open("dd.db").unwrap();
+    let connection = Connection::open(DATABASE_PATH).unwrap();
     
     if must_initialize_db {
-        let query = fs::read_to_string("init.sql").expect("initial schema does not exist");
+        let query = fs::read_to_string(SCHEMA_PATH).expect("initial schema does not exist");
         let commands = query.split(";\n");
 
         for command in commands {
             connection.execute(command, ()).unwrap();
         }
+        // A new database is migrated before it's seeded, existing ones at startup, see health::initialize
+        migrations::run(&connection);
+        if let Ok(seed) = fs::read_to_string(SEED_PATH) {
+            connection.execute_batch(&seed).unwrap();
+        }
     }
-    
+
     return connection;
 }
\ No newline at end of file
@@ -0,0 +1,59 @@
+use super::db::{connect, DATABASE_PATH, SCHEMA_PATH};
+use super::migrations;
+use regex::Regex;
+use rusqlite::{Connection, OpenFlags};
+use std::fs;
+use log::info;
+
+// Creates and migrates the database at startup, the only time the server migrates it
+pub fn initialize() {
+    migrations::run(&connect());
+    info!(target: "file", "Database {} is initialized", DATABASE_PATH);
+}
+
+// Opens the existing database without creating or migrating it, unlike connect
+pub fn open_existing() -> Result<Connection, String> {
+    let connection = Connection::open_with_flags(DATABASE_PATH, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .map_err(|e| format!("Unable to open {}: {}", DATABASE_PATH, e))?;
+    connection.query_row("SELECT 1", (), |_| Ok(()))
+        .map_err(|e| format!("Unable to query {}: {}", DATABASE_PATH, e))?;
+    return Ok(connection);
+}
+
+pub fn check_migrations(connection: &Connection) -> Result<(), String> {
+    let version: usize = connection.query_row("PRAGMA user_version", (), |row| row.get(0))
+        .map_err(|e| format!("Unable to read user_version: {}", e))?;
+    let latest = migrations::latest_version();
+    if version != latest {
+        return Err(format!("At migration {} of {}", version, latest));
+    }
+    return Ok(());
+}
+
+// Every table and column created by init.sql still exists
+pub fn check_schema(connection: &Connection) -> Result<(), String> {
+    let schema = fs::read_to_string(SCHEMA_PATH).map_err(|e| format!("Unable to read {}: {}", SCHEMA_PATH, e))?;
+    let create_table = Regex::new(r"(?s)CREATE TABLE (\w+) \((.*?)\n\);").unwrap();
+    let mut missing = Vec::new();
+    for table in create_table.captures_iter(&schema) {
+        let mut stmt = connection.prepare(&format!("SELECT name FROM pragma_table_info('{}')", &table[1]))
+            .map_err(|e| format!("Unable to read table {}: {}", &table[1], e))?;
+        let columns = stmt.query_map((), |row| row.get::<_, String>(0))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Unable to read table {}: {}", &table[1], e))?;
+        if columns.is_empty() {
+            missing.push(table[1].to_string());
+            continue;
+        }
+        for definition in table[2].split(",\n") {
+            let column = definition.split_whitespace().next().unwrap_or("");
+            if !columns.iter().any(|c| c == column) {
+                missing.push(format!("{}.{}", &table[1], column));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!("Missing {}", missing.join(", ")));
+    }
+    return Ok(());
+}
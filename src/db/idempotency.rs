@@ -0,0 +1,64 @@
+use super::db::connect;
+use super::books::LogErrResult;
+use log::info;
+
+pub struct StoredResponse {
+    pub request_hash: String,
+    // None while the request that reserved the key is still being handled
+    pub response: Option<String>,
+}
+
+// Drops every stored response older than the cutoff so expired keys can be reused
+pub fn purge_expired(cutoff: i64) {
+    let db = connect();
+    let query = "DELETE FROM IdempotencyKeys WHERE createdAt < :cutoff";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to delete from IdempotencyKeys table in prepare");
+    let purged = stmt.execute(&[(":cutoff", &cutoff)])
+        .log_expect("expected to be able to delete from IdempotencyKeys table in execute");
+    if purged > 0 {
+        info!(target: "file", "Purged {} expired idempotency keys", purged);
+    }
+}
+
+// Claims the key for a request about to be handled. If it was already claimed, gives what the earlier
+// request stored instead. SQLite runs one insert at a time, so only one of concurrent retries claims it.
+pub fn reserve(key: &str, scope: &str, request_hash: &str, created_at: i64) -> Option<StoredResponse> {
+    let db = connect();
+    let query = "INSERT OR IGNORE INTO IdempotencyKeys (idempotencyKey, scope, requestHash, response, createdAt) \
+                 VALUES (:key, :scope, :hash, NULL, :created_at)";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to insert into IdempotencyKeys table in prepare");
+    let inserted = stmt.execute(rusqlite::named_params! {
+        ":key": key, ":scope": scope, ":hash": request_hash, ":created_at": created_at,
+    }).log_expect("expected to be able to insert into IdempotencyKeys table in execute");
+    if inserted == 1 {
+        info!(target: "file", "Reserved idempotency key {} of {}", key, scope);
+        return None;
+    }
+
+    let query = "SELECT requestHash, response FROM IdempotencyKeys WHERE idempotencyKey = :key AND scope = :scope";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from IdempotencyKeys table in prepare");
+    let mut rows = stmt
+        .query_map(rusqlite::named_params! {":key": key, ":scope": scope}, |row| {
+            Ok(StoredResponse { request_hash: row.get(0)?, response: row.get(1)? })
+        })
+        .log_expect("expected to be able to get response from IdempotencyKeys table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read IdempotencyKeys row"));
+}
+
+pub fn store_response(key: &str, scope: &str, response: &str) {
+    let db = connect();
+    let query = "UPDATE IdempotencyKeys SET response = :response WHERE idempotencyKey = :key AND scope = :scope";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to update IdempotencyKeys table in prepare");
+    stmt.execute(rusqlite::named_params! {":key": key, ":scope": scope, ":response": response})
+        .log_expect("expected to be able to update IdempotencyKeys table in execute");
+    info!(target: "file", "Stored response for idempotency key {} of {}", key, scope);
+}
+
+// Frees a reserved key whose request failed, so it can be retried with a corrected body
+pub fn release(key: &str, scope: &str) {
+    let db = connect();
+    let query = "DELETE FROM IdempotencyKeys WHERE idempotencyKey = :key AND scope = :scope AND response IS NULL";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to delete from IdempotencyKeys table in prepare");
+    stmt.execute(rusqlite::named_params! {":key": key, ":scope": scope})
+        .log_expect("expected to be able to delete from IdempotencyKeys table in execute");
+}
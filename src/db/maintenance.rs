@@ -0,0 +1,48 @@
+use super::db::{connect, DATABASE_PATH};
+use super::health;
+use super::migrations;
+use super::books::LogErrResult;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+use log::info;
+
+fn user_version(connection: &Connection) -> usize {
+    return connection.query_row("PRAGMA user_version", (), |row| row.get(0))
+        .log_expect("expected to be able to read user_version in query_row");
+}
+
+// Creates the database from init.sql if there is none and applies any pending migrations.
+// Returns the migration the database was at before, if it existed, and the one it's at now.
+pub fn migrate() -> (Option<usize>, usize) {
+    let before = health::open_existing().ok().map(|connection| user_version(&connection));
+    let connection = connect();
+    migrations::run(&connection);
+    let after = user_version(&connection);
+    info!(target: "file", "Database {} migrated from {:?} to {}", DATABASE_PATH, before, after);
+    return (before, after);
+}
+
+// A consistent copy of the database, taken while the server may be writing to it
+pub fn backup(path: &Path) -> Result<u64, String> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let connection = health::open_existing()?;
+    connection.execute("VACUUM INTO :path", &[(":path", &path.to_string_lossy())])
+        .map_err(|e| format!("Unable to back up to {}: {}", path.display(), e))?;
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    info!(target: "file", "Database {} backed up to {}, {} bytes", DATABASE_PATH, path.display(), size);
+    return Ok(size);
+}
+
+// Rebuilds the database file without the space left by deleted rows. Returns its size before and after.
+pub fn vacuum() -> Result<(u64, u64), String> {
+    let size = || fs::metadata(DATABASE_PATH).map(|m| m.len()).unwrap_or(0);
+    let connection = health::open_existing()?;
+    let before = size();
+    connection.execute("VACUUM", ()).map_err(|e| format!("Unable to vacuum {}: {}", DATABASE_PATH, e))?;
+    let after = size();
+    info!(target: "file", "Database {} vacuumed from {} to {} bytes", DATABASE_PATH, before, after);
+    return Ok((before, after));
+}
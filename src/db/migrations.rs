@@ -0,0 +1,270 @@
+use rusqlite::{named_params, Connection, ToSql, Transaction, TransactionBehavior};
+use log::{info, warn};
+use super::addresses::AddressFields;
+use super::authors::{self, split_names, Role};
+use super::books::LogErrResult;
+use crate::address::{normalize_address, normalize_free_text};
+use crate::text::canonical_key;
+
+struct Migration {
+    name: &'static str,
+    // None for migrations that only change data
+    sql: Option<&'static str>,
+    // Data changes SQLite can't express, run in the same transaction after the sql
+    backfill: Option<fn(&Connection)>,
+}
+
+// Schema changes applied on top of init.sql, in order.
+// The index + 1 of the last applied migration is stored in PRAGMA user_version.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "0001_idempotency_keys",
+        sql: Some(include_str!("../../migrations/0001_idempotency_keys.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0002_purchase_order_history",
+        sql: Some(include_str!("../../migrations/0002_purchase_order_history.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0003_customer_addresses",
+        sql: Some(include_str!("../../migrations/0003_customer_addresses.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0004_canonical_keys",
+        sql: Some(include_str!("../../migrations/0004_canonical_keys.sql")),
+        backfill: Some(backfill_canonical_keys),
+    },
+    Migration {
+        name: "0005_audit_events",
+        sql: Some(include_str!("../../migrations/0005_audit_events.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0006_book_metadata",
+        sql: Some(include_str!("../../migrations/0006_book_metadata.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0007_book_contributors",
+        sql: Some(include_str!("../../migrations/0007_book_contributors.sql")),
+        backfill: Some(backfill_book_contributors),
+    },
+    Migration {
+        name: "0008_taxonomy",
+        sql: Some(include_str!("../../migrations/0008_taxonomy.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0009_works_and_editions",
+        sql: Some(include_str!("../../migrations/0009_works_and_editions.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0010_book_prices",
+        sql: Some(include_str!("../../migrations/0010_book_prices.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0011_promotions",
+        sql: Some(include_str!("../../migrations/0011_promotions.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0012_order_taxes",
+        sql: Some(include_str!("../../migrations/0012_order_taxes.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0013_shipping",
+        sql: Some(include_str!("../../migrations/0013_shipping.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0014_idempotency_reservations",
+        sql: Some(include_str!("../../migrations/0014_idempotency_reservations.sql")),
+        backfill: None,
+    },
+    Migration {
+        name: "0015_normalized_addresses",
+        sql: None,
+        backfill: Some(backfill_normalized_addresses),
+    },
+    Migration {
+        name: "0016_case_folded_keys",
+        sql: None,
+        backfill: Some(backfill_case_folded_keys),
+    },
+];
+
+// The user_version of a fully migrated database
+pub fn latest_version() -> usize {
+    return MIGRATIONS.len();
+}
+
+// Applies the pending migrations, each in an immediate transaction that reads user_version first,
+// so a second process migrating at the same time waits and then finds it applied
+pub fn run(connection: &Connection) {
+    loop {
+        let tx = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)
+            .log_expect("expected to be able to begin transaction");
+        let version: usize = tx
+            .query_row("PRAGMA user_version", (), |row| row.get(0))
+            .log_expect("expected to be able to read user_version in query_row");
+        let Some(migration) = MIGRATIONS.get(version) else {
+            return;
+        };
+        if let Some(sql) = migration.sql {
+            tx.execute_batch(sql).log_expect("expected to be able to apply migration in execute_batch");
+        }
+        if let Some(backfill) = migration.backfill {
+            backfill(&tx);
+        }
+        tx.pragma_update(None, "user_version", version + 1)
+            .log_expect("expected to be able to set user_version in pragma_update");
+        tx.commit().log_expect("expected to be able to commit transaction");
+        info!(target: "file", "Applied migration {}", migration.name);
+    }
+}
+
+fn backfill_canonical_keys(connection: &Connection) {
+    let mut stmt = connection.prepare("SELECT id, title, author FROM Books")
+        .log_expect("expected to be able to select from Books table in prepare");
+    let books = stmt
+        .query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+    for (bid, title, author) in books {
+        connection.execute("UPDATE Books SET titleKey = :title_key, authorKey = :author_key WHERE id = :bid",
+                           named_params! {":title_key": canonical_key(&title), ":author_key": canonical_key(&author), ":bid": bid})
+            .log_expect("expected to be able to update Books table in execute");
+    }
+
+    let mut stmt = connection.prepare("SELECT id, name FROM Customers")
+        .log_expect("expected to be able to select from Customers table in prepare");
+    let customers = stmt
+        .query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .log_expect("expected to be able to get customers from Customers table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Customers rows");
+    for (cid, name) in customers {
+        connection.execute("UPDATE Customers SET nameKey = :name_key WHERE id = :cid",
+                           named_params! {":name_key": canonical_key(&name), ":cid": cid})
+            .log_expect("expected to be able to update Customers table in execute");
+    }
+}
+
+fn backfill_book_contributors(connection: &Connection) {
+    let mut stmt = connection.prepare("SELECT id, author FROM Books")
+        .log_expect("expected to be able to select from Books table in prepare");
+    let books = stmt
+        .query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+    for (bid, author) in books {
+        authors::set_contributors(connection, bid, Role::Author, &split_names(&author));
+    }
+}
+
+// Addresses stored before they were normalized are rewritten in the normalized form lookups use.
+// Those that fail the current rules are left as they were.
+fn backfill_normalized_addresses(connection: &Connection) {
+    const FUNCTION: &str = "0015_normalized_addresses";
+    let mut stmt = connection.prepare("SELECT id, line1, line2, city, region, postalCode, country FROM CustomerAddresses")
+        .log_expect("expected to be able to select from CustomerAddresses table in prepare");
+    let addresses = stmt
+        .query_map((), |row| {
+            Ok((row.get::<_, i64>(0)?, AddressFields {
+                line1: row.get(1)?,
+                line2: row.get(2)?,
+                city: row.get(3)?,
+                region: row.get(4)?,
+                postal_code: row.get(5)?,
+                country: row.get(6)?,
+            }))
+        })
+        .log_expect("expected to be able to get addresses from CustomerAddresses table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read CustomerAddresses rows");
+    for (aid, fields) in addresses {
+        // Addresses carried over from the free text column have no country and only line1
+        let normalized = match fields.country.is_empty() {
+            true => normalize_free_text(&fields.line1, "line1", FUNCTION).ok().map(|line1| AddressFields { line1, ..fields }),
+            false => normalize_address(fields, "", FUNCTION).ok(),
+        };
+        let Some(address) = normalized else { continue };
+        connection.execute("UPDATE CustomerAddresses SET line1 = :line1, line2 = :line2, city = :city, region = :region, \
+                            postalCode = :postal_code, country = :country WHERE id = :aid",
+                           named_params! {
+                               ":line1": address.line1, ":line2": address.line2, ":city": address.city, ":region": address.region,
+                               ":postal_code": address.postal_code, ":country": address.country, ":aid": aid,
+                           })
+            .log_expect("expected to be able to update CustomerAddresses table in execute");
+    }
+
+    let mut stmt = connection.prepare("SELECT id, shippingAddress FROM Customers")
+        .log_expect("expected to be able to select from Customers table in prepare");
+    let customers = stmt
+        .query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .log_expect("expected to be able to get customers from Customers table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Customers rows");
+    for (cid, address) in customers {
+        let Ok(address) = normalize_free_text(&address, "shipping_address", FUNCTION) else { continue };
+        connection.execute("UPDATE Customers SET shippingAddress = :address WHERE id = :cid",
+                           named_params! {":address": address, ":cid": cid})
+            .log_expect("expected to be able to update Customers table in execute");
+    }
+}
+
+// The key columns of each table by the column they are the canonical_key of
+const CANONICAL_KEYS: &[(&str, &[(&str, &str)])] = &[
+    ("Books", &[("titleKey", "title"), ("authorKey", "author")]),
+    ("Works", &[("titleKey", "title"), ("authorKey", "author")]),
+    ("Customers", &[("nameKey", "name")]),
+    ("Authors", &[("nameKey", "name")]),
+    ("Series", &[("nameKey", "name")]),
+    ("Categories", &[("nameKey", "name")]),
+    ("Tags", &[("nameKey", "name")]),
+];
+
+// Keys stored before full case folding, such as "strasse" for "Straße", are rewritten with text::canonical_key.
+// A key that another row of a unique index already has is left as it was.
+fn backfill_case_folded_keys(connection: &Connection) {
+    for (table, keys) in CANONICAL_KEYS {
+        let columns = keys.iter().map(|(key, column)| format!("{}, {}", key, column)).collect::<Vec<_>>().join(", ");
+        let mut stmt = connection.prepare(&format!("SELECT id, {} FROM {}", columns, table))
+            .log_expect("expected to be able to select keys in prepare");
+        // Each key as stored and as it is now
+        let rows = stmt
+            .query_map((), |row| {
+                let keys = (0..keys.len())
+                    .map(|i| Ok((row.get::<_, Option<String>>(2 * i + 1)?, canonical_key(&row.get::<_, String>(2 * i + 2)?))))
+                    .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+                Ok((row.get::<_, i64>(0)?, keys))
+            })
+            .log_expect("expected to be able to get keys in query_map")
+            .collect::<Result<Vec<_>, _>>()
+            .log_expect("expected to be able to read key rows");
+        let names = keys.iter().map(|(key, _)| format!(":{}", key)).collect::<Vec<_>>();
+        let assignments = keys.iter().zip(&names).map(|((key, _), name)| format!("{} = {}", key, name)).collect::<Vec<_>>().join(", ");
+        let query = format!("UPDATE OR IGNORE {} SET {} WHERE id = :id", table, assignments);
+        for (id, row_keys) in rows {
+            if row_keys.iter().all(|(stored, folded)| stored.as_ref() == Some(folded)) {
+                continue;
+            }
+            let mut params = names.iter().zip(&row_keys).map(|(name, (_, folded))| (name.as_str(), folded as &dyn ToSql)).collect::<Vec<_>>();
+            params.push((":id", &id));
+            let updated = connection.execute(&query, params.as_slice())
+                .log_expect("expected to be able to update keys in execute");
+            // Such as two authors whose names differ only by folding, in a unique index
+            if updated == 0 {
+                warn!(target: "file", "{} id {} keeps its key, another row has the case-folded one", table, id);
+            }
+        }
+    }
+}
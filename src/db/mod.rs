@@ -1,4 +1,17 @@
+pub mod addresses;
+pub mod audit;
+pub mod authors;
 pub mod books;
 pub mod customers;
+#[allow(clippy::module_inception)]
 mod db;
+pub mod health;
+pub mod idempotency;
+pub mod maintenance;
+mod migrations;
+pub mod prices;
+pub mod promotions;
+#[allow(non_snake_case)]
 pub mod purchaseOrders;
+pub mod taxonomy;
+pub mod works;
@@ -0,0 +1,155 @@
+use super::audit::{self, Action, Actor};
+use super::books::LogErrResult;
+use super::db::connect;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::Serialize;
+use log::{error, info};
+
+// One of an edition's prices and when it applies, see migrations/0010_book_prices.sql
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PriceRecord {
+    pub id: i64,
+    pub book_id: i64,
+    pub price: f64,
+    // UTC of form YYYY-MM-DD HH:MM:SS
+    pub effective_from: String,
+    // None for the latest price, which applies until another one is set
+    pub effective_to: Option<String>,
+}
+
+pub enum PriceError {
+    BookNotFound(i64),
+    NotFound(i64),
+    // Prices that already applied stay as they were, orders may have been charged them
+    InPast,
+    Started(i64),
+}
+
+const PRICE_COLUMNS: &str = "id, bookId, price, effectiveFrom, effectiveTo";
+
+fn price_from_row(row: &Row) -> rusqlite::Result<PriceRecord> {
+    Ok(PriceRecord {
+        id: row.get(0)?,
+        book_id: row.get(1)?,
+        price: row.get(2)?,
+        effective_from: row.get(3)?,
+        effective_to: row.get(4)?,
+    })
+}
+
+// The price that applies now, None when there is no such book
+pub fn current_price(db: &Connection, bid: i64) -> Option<f64> {
+    let query = "SELECT price FROM BookPrices WHERE bookId = :bid AND effectiveFrom <= datetime('now') ORDER BY effectiveFrom DESC LIMIT 1";
+    return db
+        .query_row(query, named_params! {":bid": bid}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get price from BookPrices table in query_row");
+}
+
+// Sets the price from the time on, or from now, within the caller's transaction. The price before it ends then,
+// and one scheduled for later still starts when it was meant to. A price from the very same time is replaced.
+pub fn record_price(db: &Connection, bid: i64, price: f64, from: Option<&str>) -> PriceRecord {
+    let from: String = db
+        .query_row("SELECT datetime(ifnull(:from, 'now'))", named_params! {":from": from}, |row| row.get(0))
+        .log_expect("expected to be able to read the time in query_row");
+    let replaced = db
+        .execute("UPDATE BookPrices SET price = :price WHERE bookId = :bid AND effectiveFrom = :from",
+                 named_params! {":price": price, ":bid": bid, ":from": from})
+        .log_expect("expected to be able to update BookPrices table in execute");
+    if replaced == 0 {
+        let next: Option<String> = db
+            .query_row("SELECT min(effectiveFrom) FROM BookPrices WHERE bookId = :bid AND effectiveFrom > :from",
+                       named_params! {":bid": bid, ":from": from}, |row| row.get(0))
+            .log_expect("expected to be able to get effectiveFrom from BookPrices table in query_row");
+        db.execute("UPDATE BookPrices SET effectiveTo = :from WHERE bookId = :bid AND effectiveFrom = \
+                    (SELECT max(effectiveFrom) FROM BookPrices WHERE bookId = :bid AND effectiveFrom < :from)",
+                   named_params! {":bid": bid, ":from": from})
+            .log_expect("expected to be able to update BookPrices table in execute");
+        db.execute("INSERT INTO BookPrices (bookId, price, effectiveFrom, effectiveTo) VALUES (:bid, :price, :from, :next)",
+                   named_params! {":bid": bid, ":price": price, ":from": from, ":next": next})
+            .log_expect("expected to be able to insert into BookPrices table in execute");
+    }
+    let query = format!("SELECT {} FROM BookPrices WHERE bookId = :bid AND effectiveFrom = :from", PRICE_COLUMNS);
+    return db
+        .query_row(&query, named_params! {":bid": bid, ":from": from}, price_from_row)
+        .log_expect("expected to be able to get price from BookPrices table in query_row");
+}
+
+// Sets the book's price from the time given, of form YYYY-MM-DD or YYYY-MM-DD HH:MM:SS in UTC, or from now
+pub fn schedule_price(actor: &Actor, bid: i64, price: f64, from: Option<&str>) -> Result<PriceRecord, PriceError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    if !book_exists(&tx, bid) {
+        return Err(PriceError::BookNotFound(bid));
+    }
+    let in_past: bool = tx
+        .query_row("SELECT ifnull(datetime(:from) < datetime('now'), 0)", named_params! {":from": from}, |row| row.get(0))
+        .log_expect("expected to be able to read the time in query_row");
+    if in_past {
+        error!(target: "file", "Price for book id: {} scheduled in the past: {:?}", bid, from);
+        return Err(PriceError::InPast);
+    }
+    let record = record_price(&tx, bid, price, from);
+    audit::record(&tx, actor, Action::PriceScheduled, "price", record.id, None, Some(&record));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully set book id: {}'s price to {:.2} from {}", bid, price, record.effective_from);
+    return Ok(record);
+}
+
+// Removes a price that hasn't started yet, so the one before it applies for longer
+pub fn cancel_price(actor: &Actor, bid: i64, pid: i64) -> Result<PriceRecord, PriceError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    if !book_exists(&tx, bid) {
+        return Err(PriceError::BookNotFound(bid));
+    }
+    let query = format!("SELECT {} FROM BookPrices WHERE id = :pid AND bookId = :bid", PRICE_COLUMNS);
+    let record = tx
+        .query_row(&query, named_params! {":pid": pid, ":bid": bid}, price_from_row)
+        .optional()
+        .log_expect("expected to be able to get price from BookPrices table in query_row")
+        .ok_or(PriceError::NotFound(pid))?;
+    let started: bool = tx
+        .query_row("SELECT :from <= datetime('now')", named_params! {":from": record.effective_from}, |row| row.get(0))
+        .log_expect("expected to be able to read the time in query_row");
+    if started {
+        return Err(PriceError::Started(pid));
+    }
+    tx.execute("DELETE FROM BookPrices WHERE id = :pid", named_params! {":pid": pid})
+        .log_expect("expected to be able to delete from BookPrices table in execute");
+    tx.execute("UPDATE BookPrices SET effectiveTo = :to WHERE bookId = :bid AND effectiveTo = :from",
+               named_params! {":to": record.effective_to, ":bid": bid, ":from": record.effective_from})
+        .log_expect("expected to be able to update BookPrices table in execute");
+    audit::record(&tx, actor, Action::PriceCancelled, "price", pid, Some(&record), None);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully cancelled price id: {} of book id: {}", pid, bid);
+    return Ok(record);
+}
+
+// Every price of the book, oldest first and scheduled ones last, or None if there is no such book
+pub fn price_history(bid: i64) -> Option<Vec<PriceRecord>> {
+    let db = connect();
+    if !book_exists(&db, bid) {
+        return None;
+    }
+    let query = format!("SELECT {} FROM BookPrices WHERE bookId = :bid ORDER BY effectiveFrom", PRICE_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let prices = stmt
+        .query_map(named_params! {":bid": bid}, price_from_row)
+        .log_expect("expected to be able to get prices from BookPrices table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read BookPrices rows");
+    return Some(prices);
+}
+
+// Removes the prices of a book being deleted, within its transaction
+pub fn remove_book(db: &Connection, bid: i64) {
+    db.execute("DELETE FROM BookPrices WHERE bookId = :bid", named_params! {":bid": bid})
+        .log_expect("expected to be able to delete from BookPrices table in execute");
+}
+
+fn book_exists(db: &Connection, bid: i64) -> bool {
+    return db
+        .query_row("SELECT COUNT(*) FROM Books WHERE id = :bid", named_params! {":bid": bid}, |row| row.get::<_, i64>(0))
+        .log_expect("expected to be able to count Books table in query_row") > 0;
+}
@@ -0,0 +1,558 @@
+use super::audit::{self, Action, Actor};
+use super::books::LogErrResult;
+use super::db::connect;
+use crate::shipping::ShippingQuote;
+use crate::tax::TaxLine;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use log::{info, warn};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromotionKind {
+    // percent off the order
+    Percentage,
+    // amount off the order
+    Fixed,
+    // free_quantity of every buy_quantity + free_quantity copies free
+    Bundle,
+}
+
+impl PromotionKind {
+    pub const ALL: [PromotionKind; 3] = [PromotionKind::Percentage, PromotionKind::Fixed, PromotionKind::Bundle];
+
+    pub fn parse(kind: &str) -> Option<PromotionKind> {
+        return PromotionKind::ALL.into_iter().find(|k| k.as_str() == kind);
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromotionKind::Percentage => "percentage",
+            PromotionKind::Fixed => "fixed",
+            PromotionKind::Bundle => "bundle",
+        }
+    }
+}
+
+// See migrations/0011_promotions.sql. New promotions have an id and uses of 0.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PromotionRecord {
+    pub id: i64,
+    pub name: String,
+    // Upper-case, None for promotions that apply without one
+    pub code: Option<String>,
+    pub kind: PromotionKind,
+    pub percent: Option<f64>,
+    pub amount: Option<f64>,
+    pub buy_quantity: Option<i64>,
+    pub free_quantity: Option<i64>,
+    pub minimum_subtotal: Option<f64>,
+    pub book_id: Option<i64>,
+    pub author_id: Option<i64>,
+    pub category_id: Option<i64>,
+    pub customer_id: Option<i64>,
+    pub usage_limit: Option<i64>,
+    pub per_customer_limit: Option<i64>,
+    // UTC of form YYYY-MM-DD HH:MM:SS, open-ended when None
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    // Orders that got it
+    pub uses: i64,
+}
+
+// One discount of an order, with the promotion's name and code as they were when it was ordered
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AppliedDiscount {
+    pub promotion_id: i64,
+    pub name: String,
+    pub code: Option<String>,
+    pub amount: f64,
+}
+
+// What an order of quantity copies of a book comes to, see price_order. Shipping is added by ShippingRules::apply,
+// then tax by TaxRules::apply.
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderPricing {
+    pub book_id: i64,
+    pub quantity: i64,
+    pub unit_price: f64,
+    pub subtotal: f64,
+    pub discounts: Vec<AppliedDiscount>,
+    pub shipping: Option<ShippingQuote>,
+    pub taxes: Vec<TaxLine>,
+    pub total: f64,
+}
+
+#[derive(Debug)]
+pub enum PromotionError {
+    NotFound(i64),
+    // A book, author, category or customer that doesn't exist, by field and id
+    Unknown(&'static str, i64),
+    // Another promotion has the code, by its id
+    DuplicateCode(i64),
+    // ends_at isn't after starts_at
+    EmptyWindow,
+    // Promotions that orders got can't be deleted, by their uses
+    Used(i64),
+}
+
+// Why the coupon code given with an order can't be used, by the code
+#[derive(Debug, Clone, PartialEq)]
+pub enum CouponRejected {
+    Unknown(String),
+    NotStarted(String),
+    Ended(String),
+    UsedUp(String),
+    NotEligible(String),
+    BelowMinimum(String, f64),
+    // Bundles need at least buy_quantity + free_quantity copies
+    TooFewCopies(String, i64),
+    // By the promotion's name, used up by other orders between pricing the order and placing it
+    UsedUpSincePriced(String),
+}
+
+impl fmt::Display for CouponRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CouponRejected::Unknown(code) => write!(f, "No promotion with code {}", code),
+            CouponRejected::NotStarted(code) => write!(f, "Code {} can't be used yet", code),
+            CouponRejected::Ended(code) => write!(f, "Code {} has expired", code),
+            CouponRejected::UsedUp(code) => write!(f, "Code {} has been used up", code),
+            CouponRejected::NotEligible(code) => write!(f, "Code {} doesn't apply to this order", code),
+            CouponRejected::BelowMinimum(code, minimum) => write!(f, "Code {} only applies to orders of ${:.2} or more", code, minimum),
+            CouponRejected::TooFewCopies(code, copies) => write!(f, "Code {} only applies to orders of {} or more copies", code, copies),
+            CouponRejected::UsedUpSincePriced(name) => write!(f, "{} was just used up by other orders, please order again", name),
+        }
+    }
+}
+
+const PROMOTION_COLUMNS: &str = "Promotions.id, Promotions.name, Promotions.code, Promotions.kind, Promotions.percent, \
+    Promotions.amount, Promotions.buyQuantity, Promotions.freeQuantity, Promotions.minimumSubtotal, Promotions.bookId, \
+    Promotions.authorId, Promotions.categoryId, Promotions.customerId, Promotions.usageLimit, Promotions.perCustomerLimit, \
+    Promotions.startsAt, Promotions.endsAt, (SELECT COUNT(*) FROM OrderDiscounts WHERE OrderDiscounts.promotionId = Promotions.id)";
+
+// Whether the promotion :pid is for the customer :cid and the book :bid, by the book itself, any of its authors,
+// or a category it is in or below
+const ELIGIBLE: &str = "SELECT COUNT(*) FROM Promotions WHERE Promotions.id = :pid \
+    AND (Promotions.bookId IS NULL OR Promotions.bookId = :bid) \
+    AND (Promotions.customerId IS NULL OR Promotions.customerId = :cid) \
+    AND (Promotions.authorId IS NULL OR Promotions.authorId IN \
+         (SELECT authorId FROM BookContributors WHERE bookId = :bid AND role = 'author')) \
+    AND (Promotions.categoryId IS NULL OR Promotions.categoryId IN \
+         (WITH RECURSIVE Ancestors(id) AS (SELECT categoryId FROM BookCategories WHERE bookId = :bid \
+          UNION SELECT Categories.parentId FROM Categories JOIN Ancestors ON Categories.id = Ancestors.id \
+          WHERE Categories.parentId IS NOT NULL) SELECT id FROM Ancestors))";
+
+fn promotion_from_row(row: &Row) -> rusqlite::Result<PromotionRecord> {
+    Ok(PromotionRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        code: row.get(2)?,
+        kind: PromotionKind::parse(&row.get::<_, String>(3)?).expect("Promotions.kind should be a PromotionKind"),
+        percent: row.get(4)?,
+        amount: row.get(5)?,
+        buy_quantity: row.get(6)?,
+        free_quantity: row.get(7)?,
+        minimum_subtotal: row.get(8)?,
+        book_id: row.get(9)?,
+        author_id: row.get(10)?,
+        category_id: row.get(11)?,
+        customer_id: row.get(12)?,
+        usage_limit: row.get(13)?,
+        per_customer_limit: row.get(14)?,
+        starts_at: row.get(15)?,
+        ends_at: row.get(16)?,
+        uses: row.get(17)?,
+    })
+}
+
+fn query_promotion(db: &Connection, pid: i64) -> Option<PromotionRecord> {
+    let query = format!("SELECT {} FROM Promotions WHERE Promotions.id = :pid", PROMOTION_COLUMNS);
+    return db
+        .query_row(&query, named_params! {":pid": pid}, promotion_from_row)
+        .optional()
+        .log_expect("expected to be able to get promotion from Promotions table in query_row");
+}
+
+pub fn get_promotion(pid: i64) -> Option<PromotionRecord> {
+    return query_promotion(&connect(), pid);
+}
+
+pub fn list_promotions() -> Vec<PromotionRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Promotions ORDER BY Promotions.id", PROMOTION_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map((), promotion_from_row)
+        .log_expect("expected to be able to get promotions from Promotions table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Promotions rows");
+}
+
+// Stores every field but the id, which the database gives, and uses
+pub fn create_promotion(actor: &Actor, promotion: PromotionRecord) -> Result<PromotionRecord, PromotionError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    check_promotion(&tx, &promotion)?;
+    tx.execute("INSERT INTO Promotions (name, kind) VALUES (:name, :kind)",
+               named_params! {":name": promotion.name, ":kind": promotion.kind.as_str()})
+        .log_expect("expected to be able to insert into Promotions table in execute");
+    let pid = tx.last_insert_rowid();
+    let after = write_promotion(&tx, &PromotionRecord { id: pid, ..promotion });
+    audit::record(&tx, actor, Action::PromotionChanged, "promotion", pid, None, Some(&after));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully created promotion id: {} named {}", pid, after.name);
+    return Ok(after);
+}
+
+// Replaces every field of the promotion. Orders that already got it keep their discount.
+pub fn update_promotion(actor: &Actor, pid: i64, promotion: PromotionRecord) -> Result<PromotionRecord, PromotionError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let before = query_promotion(&tx, pid).ok_or(PromotionError::NotFound(pid))?;
+    let promotion = PromotionRecord { id: pid, ..promotion };
+    check_promotion(&tx, &promotion)?;
+    let after = write_promotion(&tx, &promotion);
+    audit::record(&tx, actor, Action::PromotionChanged, "promotion", pid, Some(&before), Some(&after));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully updated promotion id: {}", pid);
+    return Ok(after);
+}
+
+// Only promotions no order got, others can be ended by setting ends_at
+pub fn delete_promotion(actor: &Actor, pid: i64) -> Result<PromotionRecord, PromotionError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let promotion = query_promotion(&tx, pid).ok_or(PromotionError::NotFound(pid))?;
+    if promotion.uses > 0 {
+        return Err(PromotionError::Used(promotion.uses));
+    }
+    tx.execute("DELETE FROM Promotions WHERE id = :pid", named_params! {":pid": pid})
+        .log_expect("expected to be able to delete from Promotions table in execute");
+    audit::record(&tx, actor, Action::PromotionChanged, "promotion", pid, Some(&promotion), None);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully deleted promotion id: {}", pid);
+    return Ok(promotion);
+}
+
+// Ends the promotion now, unless it already has. Returns None if there is no such promotion.
+pub fn end_promotion(actor: &Actor, pid: i64) -> Option<PromotionRecord> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let before = query_promotion(&tx, pid)?;
+    tx.execute("UPDATE Promotions SET endsAt = datetime('now') WHERE id = :pid AND (endsAt IS NULL OR endsAt > datetime('now'))",
+               named_params! {":pid": pid})
+        .log_expect("expected to be able to update Promotions table in execute");
+    let after = query_promotion(&tx, pid).expect("the updated promotion should be readable in its transaction");
+    if after != before {
+        audit::record(&tx, actor, Action::PromotionChanged, "promotion", pid, Some(&before), Some(&after));
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully ended promotion id: {} at {:?}", pid, after.ends_at);
+    return Some(after);
+}
+
+// What the promotion refers to must exist, its code must be its own, and its window mustn't be empty
+fn check_promotion(db: &Connection, promotion: &PromotionRecord) -> Result<(), PromotionError> {
+    let references = [("book_id", "Books", promotion.book_id), ("author_id", "Authors", promotion.author_id),
+                      ("category_id", "Categories", promotion.category_id), ("customer_id", "Customers", promotion.customer_id)];
+    for (field, table, id) in references {
+        let Some(id) = id else { continue };
+        let found: i64 = db
+            .query_row(&format!("SELECT COUNT(*) FROM {} WHERE id = :id", table), named_params! {":id": id}, |row| row.get(0))
+            .log_expect("expected to be able to count rows in query_row");
+        if found == 0 {
+            return Err(PromotionError::Unknown(field, id));
+        }
+    }
+    let other: Option<i64> = db
+        .query_row("SELECT id FROM Promotions WHERE code = :code AND id != :pid",
+                   named_params! {":code": promotion.code, ":pid": promotion.id}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Promotions table in query_row");
+    if let Some(other) = other {
+        return Err(PromotionError::DuplicateCode(other));
+    }
+    let empty: bool = db
+        .query_row("SELECT ifnull(datetime(:ends_at) <= datetime(:starts_at), 0)",
+                   named_params! {":starts_at": promotion.starts_at, ":ends_at": promotion.ends_at}, |row| row.get(0))
+        .log_expect("expected to be able to compare times in query_row");
+    if empty {
+        return Err(PromotionError::EmptyWindow);
+    }
+    return Ok(());
+}
+
+fn write_promotion(db: &Connection, promotion: &PromotionRecord) -> PromotionRecord {
+    let query = "UPDATE Promotions SET name = :name, code = :code, kind = :kind, percent = :percent, amount = :amount, \
+                 buyQuantity = :buy_quantity, freeQuantity = :free_quantity, minimumSubtotal = :minimum_subtotal, \
+                 bookId = :book_id, authorId = :author_id, categoryId = :category_id, customerId = :customer_id, \
+                 usageLimit = :usage_limit, perCustomerLimit = :per_customer_limit, \
+                 startsAt = datetime(:starts_at), endsAt = datetime(:ends_at) WHERE id = :pid";
+    db.execute(query, named_params! {
+        ":name": promotion.name, ":code": promotion.code, ":kind": promotion.kind.as_str(), ":percent": promotion.percent,
+        ":amount": promotion.amount, ":buy_quantity": promotion.buy_quantity, ":free_quantity": promotion.free_quantity,
+        ":minimum_subtotal": promotion.minimum_subtotal, ":book_id": promotion.book_id, ":author_id": promotion.author_id,
+        ":category_id": promotion.category_id, ":customer_id": promotion.customer_id, ":usage_limit": promotion.usage_limit,
+        ":per_customer_limit": promotion.per_customer_limit, ":starts_at": promotion.starts_at, ":ends_at": promotion.ends_at,
+        ":pid": promotion.id,
+    }).log_expect("expected to be able to update Promotions table in execute");
+    return query_promotion(db, promotion.id).expect("the written promotion should be readable in its transaction");
+}
+
+// Prices quantity copies of the book for the customer with every promotion without a code that applies, and the one with
+// the code if given. Free copies come off first, then percentages of what is left, then fixed amounts, never below 0.
+// A code that doesn't apply rejects the order, other promotions that don't are left out.
+pub fn price_order(cid: i64, bid: i64, quantity: i64, unit_price: f64, code: Option<&str>) -> Result<OrderPricing, CouponRejected> {
+    let db = connect();
+    let subtotal = cents(unit_price * quantity as f64);
+    let now: String = db.query_row("SELECT datetime('now')", (), |row| row.get(0))
+        .log_expect("expected to be able to read the time in query_row");
+
+    let query = format!("SELECT {} FROM Promotions WHERE Promotions.code IS NULL OR Promotions.code = :code ORDER BY Promotions.id",
+                        PROMOTION_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let candidates = stmt
+        .query_map(named_params! {":code": code}, promotion_from_row)
+        .log_expect("expected to be able to get promotions from Promotions table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Promotions rows");
+    if let Some(code) = code {
+        if !candidates.iter().any(|p| p.code.as_deref() == Some(code)) {
+            warn!(target: "file", "Unknown coupon code given by customer id: {}: {}", cid, code);
+            return Err(CouponRejected::Unknown(code.to_string()));
+        }
+    }
+
+    let mut applicable = Vec::new();
+    for promotion in candidates {
+        match applies(&db, &promotion, cid, bid, quantity, subtotal, &now) {
+            Ok(()) => applicable.push(promotion),
+            Err(rejected) if promotion.code.is_some() => {
+                warn!(target: "file", "Coupon rejected for customer id: {}: {}", cid, rejected);
+                return Err(rejected);
+            },
+            Err(_) => (),
+        };
+    }
+    let (discounts, total) = apply_discounts(applicable, quantity, unit_price, subtotal);
+    return Ok(OrderPricing { book_id: bid, quantity, unit_price, subtotal, discounts, shipping: None, taxes: Vec::new(), total });
+}
+
+// The discounts of the applicable promotions in order, free copies first, then percentages, then fixed amounts,
+// and the total they leave of the subtotal
+fn apply_discounts(mut applicable: Vec<PromotionRecord>, quantity: i64, unit_price: f64, subtotal: f64) -> (Vec<AppliedDiscount>, f64) {
+    applicable.sort_by_key(|p| match p.kind {
+        PromotionKind::Bundle => 0,
+        PromotionKind::Percentage => 1,
+        PromotionKind::Fixed => 2,
+    });
+
+    let mut total = subtotal;
+    let mut discounts = Vec::new();
+    for promotion in applicable {
+        let off = match promotion.kind {
+            PromotionKind::Bundle => {
+                let (buy, free) = (promotion.buy_quantity.unwrap_or(0), promotion.free_quantity.unwrap_or(0));
+                cents(unit_price * (quantity / (buy + free).max(1) * free) as f64)
+            },
+            PromotionKind::Percentage => cents(total * promotion.percent.unwrap_or(0.0) / 100.0),
+            PromotionKind::Fixed => promotion.amount.unwrap_or(0.0),
+        }.min(total);
+        if off <= 0.0 {
+            continue;
+        }
+        total = cents(total - off);
+        discounts.push(AppliedDiscount { promotion_id: promotion.id, name: promotion.name, code: promotion.code, amount: off });
+    }
+    return (discounts, total);
+}
+
+fn applies(db: &Connection, promotion: &PromotionRecord, cid: i64, bid: i64, quantity: i64, subtotal: f64, now: &str)
+           -> Result<(), CouponRejected> {
+    let code = promotion.code.clone().unwrap_or_default();
+    if promotion.starts_at.as_deref().is_some_and(|starts| starts > now) {
+        return Err(CouponRejected::NotStarted(code));
+    }
+    if promotion.ends_at.as_deref().is_some_and(|ends| ends <= now) {
+        return Err(CouponRejected::Ended(code));
+    }
+    if !within_limits(db, promotion, cid) {
+        return Err(CouponRejected::UsedUp(code));
+    }
+    let eligible: i64 = db
+        .query_row(ELIGIBLE, named_params! {":pid": promotion.id, ":cid": cid, ":bid": bid}, |row| row.get(0))
+        .log_expect("expected to be able to count Promotions table in query_row");
+    if eligible == 0 {
+        return Err(CouponRejected::NotEligible(code));
+    }
+    if let Some(minimum) = promotion.minimum_subtotal.filter(|minimum| subtotal < *minimum) {
+        return Err(CouponRejected::BelowMinimum(code, minimum));
+    }
+    if promotion.kind == PromotionKind::Bundle {
+        let copies = promotion.buy_quantity.unwrap_or(0) + promotion.free_quantity.unwrap_or(0);
+        if quantity < copies {
+            return Err(CouponRejected::TooFewCopies(code, copies));
+        }
+    }
+    return Ok(());
+}
+
+// Whether the promotion's usage_limit and the customer's per_customer_limit leave another use
+fn within_limits(db: &Connection, promotion: &PromotionRecord, cid: i64) -> bool {
+    if promotion.usage_limit.is_some_and(|limit| promotion.uses >= limit) {
+        return false;
+    }
+    if let Some(limit) = promotion.per_customer_limit {
+        let uses: i64 = db
+            .query_row("SELECT COUNT(*) FROM OrderDiscounts JOIN PurchaseOrders ON PurchaseOrders.id = OrderDiscounts.orderId \
+                        WHERE OrderDiscounts.promotionId = :pid AND PurchaseOrders.customerId = :cid",
+                       named_params! {":pid": promotion.id, ":cid": cid}, |row| row.get(0))
+            .log_expect("expected to be able to count OrderDiscounts table in query_row");
+        if uses >= limit {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Amounts are kept to the cent
+fn cents(amount: f64) -> f64 {
+    return (amount * 100.0).round() / 100.0;
+}
+
+// Checks the limits of the order's discounts again within the transaction placing it, as concurrent orders
+// may have used them up since it was priced
+pub fn check_limits(db: &Connection, cid: i64, discounts: &[AppliedDiscount]) -> Result<(), CouponRejected> {
+    for discount in discounts {
+        let used_up = query_promotion(db, discount.promotion_id).is_none_or(|promotion| !within_limits(db, &promotion, cid));
+        if used_up {
+            warn!(target: "file", "Promotion id: {} used up since pricing the order of customer id: {}", discount.promotion_id, cid);
+            return Err(CouponRejected::UsedUpSincePriced(discount.name.clone()));
+        }
+    }
+    return Ok(());
+}
+
+// Stores the discounts of a new order, within its transaction
+pub fn record_discounts(db: &Connection, oid: i64, discounts: &[AppliedDiscount]) {
+    for discount in discounts {
+        db.execute("INSERT INTO OrderDiscounts (orderId, promotionId, name, code, amount) VALUES (:oid, :pid, :name, :code, :amount)",
+                   named_params! {
+                       ":oid": oid, ":pid": discount.promotion_id, ":name": discount.name, ":code": discount.code, ":amount": discount.amount,
+                   })
+            .log_expect("expected to be able to insert into OrderDiscounts table in execute");
+    }
+}
+
+pub fn order_discounts(oid: i64) -> Vec<AppliedDiscount> {
+    let db = connect();
+    let query = "SELECT promotionId, name, code, amount FROM OrderDiscounts WHERE orderId = :oid ORDER BY id";
+    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map(named_params! {":oid": oid}, |row| Ok(AppliedDiscount {
+            promotion_id: row.get(0)?,
+            name: row.get(1)?,
+            code: row.get(2)?,
+            amount: row.get(3)?,
+        }))
+        .log_expect("expected to be able to get discounts from OrderDiscounts table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read OrderDiscounts rows");
+}
+
+// Removes the discounts of a cancelled order, within its transaction, so they no longer count towards usage limits
+pub fn remove_order(db: &Connection, oid: i64) {
+    db.execute("DELETE FROM OrderDiscounts WHERE orderId = :oid", named_params! {":oid": oid})
+        .log_expect("expected to be able to delete from OrderDiscounts table in execute");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn promotion(id: i64, kind: PromotionKind) -> PromotionRecord {
+        return PromotionRecord {
+            id,
+            name: format!("Promotion {}", id),
+            code: None,
+            kind,
+            percent: None,
+            amount: None,
+            buy_quantity: None,
+            free_quantity: None,
+            minimum_subtotal: None,
+            book_id: None,
+            author_id: None,
+            category_id: None,
+            customer_id: None,
+            usage_limit: None,
+            per_customer_limit: None,
+            starts_at: None,
+            ends_at: None,
+            uses: 0,
+        };
+    }
+
+    fn percentage(id: i64, percent: f64) -> PromotionRecord {
+        return PromotionRecord { percent: Some(percent), ..promotion(id, PromotionKind::Percentage) };
+    }
+
+    fn fixed(id: i64, amount: f64) -> PromotionRecord {
+        return PromotionRecord { amount: Some(amount), ..promotion(id, PromotionKind::Fixed) };
+    }
+
+    fn bundle(id: i64, buy: i64, free: i64) -> PromotionRecord {
+        return PromotionRecord { buy_quantity: Some(buy), free_quantity: Some(free), ..promotion(id, PromotionKind::Bundle) };
+    }
+
+    fn amounts(discounts: &[AppliedDiscount]) -> Vec<(i64, f64)> {
+        return discounts.iter().map(|d| (d.promotion_id, d.amount)).collect();
+    }
+
+    #[test]
+    fn free_copies_come_off_before_percentages_before_fixed_amounts() {
+        let (discounts, total) = apply_discounts(vec![fixed(1, 5.0), percentage(2, 10.0), bundle(3, 2, 1)], 3, 10.0, 30.0);
+        assert_eq!(amounts(&discounts), vec![(3, 10.0), (2, 2.0), (1, 5.0)]);
+        assert_eq!(total, 13.0);
+    }
+
+    #[test]
+    fn percentages_are_of_what_is_left() {
+        let (discounts, total) = apply_discounts(vec![percentage(1, 50.0), percentage(2, 50.0)], 1, 20.0, 20.0);
+        assert_eq!(amounts(&discounts), vec![(1, 10.0), (2, 5.0)]);
+        assert_eq!(total, 5.0);
+    }
+
+    #[test]
+    fn bundles_give_free_copies_per_complete_set() {
+        let (discounts, total) = apply_discounts(vec![bundle(1, 2, 1)], 7, 4.5, 31.5);
+        assert_eq!(amounts(&discounts), vec![(1, 9.0)]);
+        assert_eq!(total, 22.5);
+        let (discounts, total) = apply_discounts(vec![bundle(1, 3, 2)], 10, 2.0, 20.0);
+        assert_eq!(amounts(&discounts), vec![(1, 8.0)]);
+        assert_eq!(total, 12.0);
+    }
+
+    #[test]
+    fn discounts_never_go_below_zero() {
+        let (discounts, total) = apply_discounts(vec![fixed(1, 50.0), fixed(2, 5.0)], 2, 10.0, 20.0);
+        assert_eq!(amounts(&discounts), vec![(1, 20.0)]);
+        assert_eq!(total, 0.0);
+    }
+
+    #[test]
+    fn discounts_of_nothing_are_left_out() {
+        let (discounts, total) = apply_discounts(vec![percentage(1, 0.0), bundle(2, 3, 1)], 2, 10.0, 20.0);
+        assert!(discounts.is_empty());
+        assert_eq!(total, 20.0);
+    }
+
+    #[test]
+    fn amounts_are_kept_to_the_cent() {
+        let (discounts, total) = apply_discounts(vec![percentage(1, 33.0)], 1, 10.01, 10.01);
+        assert_eq!(amounts(&discounts), vec![(1, 3.3)]);
+        assert_eq!(total, 6.71);
+    }
+}
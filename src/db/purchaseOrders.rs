@@ -1,4 +1,13 @@
 use super::db::connect;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::Serialize;
+use super::addresses::CustomerAddress;
+use super::audit::{self, Action, Actor};
+use super::books;
+use super::promotions::{self, CouponRejected, OrderPricing};
+use crate::shipping::ShippingQuote;
+use crate::tax::TaxLine;
+use rocket::serde::json::serde_json::json;
 use log::{info, error};
 use std::fmt::Debug;
 
@@ -13,16 +22,111 @@ impl<T, E: Debug> LogErrResult<T, E> for Result<T, E> {
     }
 }
 
-pub fn create_purchase_order(cid: i64, bid: i64) -> i64 {
-    let db = connect();
-    let query = "INSERT INTO PurchaseOrders (customerId, bookId, shipped) VALUES (:cid, :bid, 0)";
-    let mut stmt = db.prepare(query).log_expect("expected to prepare statement correctly in prepare");
-    stmt.execute(&[(":cid",  &format!("{}",cid)), (":bid",  &format!("{}",bid))])
-        .log_expect("expected to be able to insert into PurchaseOrders table in execute");
-    info!(target: "file", "Successfully created order of book id: {} from customer id: {}", bid, cid);
+// Why create_purchase_order placed no order
+#[derive(Debug, Clone, PartialEq)]
+pub enum PurchaseRejected {
+    // By the customer's balance
+    InsufficientFunds(f64),
+    OutOfStock,
+    Coupon(CouponRejected),
+}
 
+// The address is copied onto the order so later changes to it don't alter where this order ships,
+// and the pricing with its discounts, shipping and taxes so later price and rate changes don't alter what it cost.
+// Rejected if another order used up one of its promotions since it was priced, the customer's balance doesn't cover it,
+// or fewer copies are left.
+pub fn create_purchase_order(actor: &Actor, cid: i64, pricing: &OrderPricing, address: &CustomerAddress) -> Result<i64, PurchaseRejected> {
+    let mut db = connect();
+    // Immediate, so no other order can take a promotion's last use between checking its limits and recording it
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    promotions::check_limits(&tx, cid, &pricing.discounts).map_err(PurchaseRejected::Coupon)?;
+    debit(&tx, actor, cid, pricing.total)?;
+    if !books::take_from_stock(&tx, pricing.book_id, pricing.quantity) {
+        return Err(PurchaseRejected::OutOfStock);
+    }
+    let query = "INSERT INTO PurchaseOrders (customerId, bookId, shipped, quantity, subtotal, pricePaid, createdAt, addressId, shippingAddress, \
+                 shippingMethod, shippingName, shippingCost, deliveryFrom, deliveryBy) \
+                 VALUES (:cid, :bid, 0, :quantity, :subtotal, :price, datetime('now'), :aid, :address, \
+                 :method, :method_name, :shipping_cost, :delivery_from, :delivery_by)";
+    let shipping = pricing.shipping.as_ref();
+    tx.execute(query, named_params! {
+        ":cid": cid, ":bid": pricing.book_id, ":quantity": pricing.quantity, ":subtotal": pricing.subtotal, ":price": pricing.total,
+        ":aid": address.id, ":address": address.fields.formatted(), ":method": shipping.map(|s| &s.method),
+        ":method_name": shipping.map(|s| &s.name), ":shipping_cost": shipping.map(|s| s.cost),
+        ":delivery_from": shipping.map(|s| &s.earliest_delivery), ":delivery_by": shipping.map(|s| &s.latest_delivery),
+    })
+        .log_expect("expected to be able to insert into PurchaseOrders table in execute");
     // This return is now used to give the user their order id
-    return get_purchase_order_id(cid, bid);
+    // A customer may order the same book more than once, so the id can't be looked up by (cid, bid)
+    let oid = tx.last_insert_rowid();
+    promotions::record_discounts(&tx, oid, &pricing.discounts);
+    record_taxes(&tx, oid, &pricing.taxes);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully created order of {} of book id: {} from customer id: {}", pricing.quantity, pricing.book_id, cid);
+    return Ok(oid);
+}
+
+// Takes the price from the customer's balance in the order's transaction, so concurrent orders can't both spend it
+fn debit(db: &Connection, actor: &Actor, cid: i64, price: f64) -> Result<(), PurchaseRejected> {
+    let balance: f64 = db
+        .query_row("SELECT accountBalance FROM customers WHERE id = :cid", named_params! {":cid": cid}, |row| row.get(0))
+        .log_expect("expected to be able to select from Customers table in query_row");
+    let debited = db.execute("UPDATE customers SET accountBalance = accountBalance - :price WHERE id = :cid AND accountBalance >= :price",
+                             named_params! {":price": price, ":cid": cid})
+        .log_expect("expected to be able to update Customers table in execute");
+    if debited == 0 {
+        return Err(PurchaseRejected::InsufficientFunds(balance));
+    }
+    audit::record(db, actor, Action::BalanceChanged, "customer", cid,
+                  Some(&json!({"account_balance": balance})), Some(&json!({"account_balance": balance - price})));
+    return Ok(());
+}
+
+fn record_taxes(db: &Connection, oid: i64, taxes: &[TaxLine]) {
+    for tax in taxes {
+        db.execute("INSERT INTO OrderTaxes (orderId, line, jurisdiction, rate, taxable, amount, included) \
+                    VALUES (:oid, :line, :jurisdiction, :rate, :taxable, :amount, :included)",
+                   named_params! {
+                       ":oid": oid, ":line": tax.line, ":jurisdiction": tax.jurisdiction, ":rate": tax.rate, ":taxable": tax.taxable,
+                       ":amount": tax.amount, ":included": tax.included,
+                   })
+            .log_expect("expected to be able to insert into OrderTaxes table in execute");
+    }
+}
+
+pub fn get_po_taxes(poid: i64) -> Vec<TaxLine> {
+    let db = connect();
+    let query = "SELECT line, jurisdiction, rate, taxable, amount, included FROM OrderTaxes WHERE orderId = :poid ORDER BY id";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from OrderTaxes table in prepare");
+    return stmt
+        .query_map(named_params! {":poid": poid}, |row| Ok(TaxLine {
+            line: row.get(0)?,
+            jurisdiction: row.get(1)?,
+            rate: row.get(2)?,
+            taxable: row.get(3)?,
+            amount: row.get(4)?,
+            included: row.get(5)?,
+        }))
+        .log_expect("expected to be able to get taxes from OrderTaxes table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read OrderTaxes rows");
+}
+
+// Orders placed without shipping rules have none
+pub fn get_po_shipping(poid: i64) -> Option<ShippingQuote> {
+    let db = connect();
+    let query = "SELECT shippingMethod, shippingName, shippingCost, deliveryFrom, deliveryBy FROM PurchaseOrders \
+                 WHERE id = :poid AND shippingMethod IS NOT NULL";
+    return db
+        .query_row(query, named_params! {":poid": poid}, |row| Ok(ShippingQuote {
+            method: row.get(0)?,
+            name: row.get(1)?,
+            cost: row.get(2)?,
+            earliest_delivery: row.get(3)?,
+            latest_delivery: row.get(4)?,
+        }))
+        .optional()
+        .log_expect("expected to be able to select from PurchaseOrders table in query_row");
 }
 
 pub fn get_purchase_order_id(cid: i64, bid: i64) -> i64 {
@@ -38,23 +142,182 @@ pub fn get_purchase_order_id(cid: i64, bid: i64) -> i64 {
     return id;
 }
 
-pub fn is_po_shipped(poid: i64) -> i64 {
+// None for an order that doesn't exist
+pub fn is_po_shipped(poid: i64) -> Option<i64> {
     let db = connect();
     let query = "SELECT shipped FROM PurchaseOrders WHERE id = :poid";
     let mut stmt = db.prepare(query).log_expect("expected to be able to select from PurchaseOrders table in prepare");
 
     let mut rows = stmt
-        .query_map(&[(":poid",  &format!("{}",poid))], |row| row.get(0))
+        .query_map(&[(":poid", &poid)], |row| row.get(0))
         .log_expect("expected to be able to get shipped status from PurchaseOrders table in query_map");
-    let shipped: i64 = rows.next().unwrap().unwrap();
+    let shipped: i64 = rows.next()?.log_expect("expected to be able to read PurchaseOrders row");
     info!(target: "file", "Successfully got shipping status of {} for purchase order id {}", shipped, poid);
-    return shipped;
+    return Some(shipped);
 }
 
-pub fn ship_po(poid: i64) {
+// Orders placed before addresses were recorded per order have no snapshot
+pub fn get_po_shipping_address(poid: i64) -> Option<String> {
     let db = connect();
-    let query = "UPDATE PurchaseOrders SET shipped = 1 WHERE id = :poid";
-    let mut stmt = db.prepare(query).log_expect("expected to be able to update PurchaseOrders table in prepare");
-    stmt.execute(&[(":poid", &format!("{}",poid))]).log_expect("expected to be able to update PurchaseOrders table in execute");
+    let query = "SELECT shippingAddress FROM PurchaseOrders WHERE id = :poid";
+    let mut stmt = db.prepare(query).log_expect("expected to be able to select from PurchaseOrders table in prepare");
+
+    let mut rows = stmt
+        .query_map(&[(":poid", &poid)], |row| row.get(0))
+        .log_expect("expected to be able to get shippingAddress from PurchaseOrders table in query_map");
+    let address: Option<String> = rows.next().and_then(|r| r.log_expect("expected to be able to read PurchaseOrders row"));
+    return address;
+}
+
+pub fn ship_po(actor: &Actor, poid: i64) {
+    let mut db = connect();
+    let tx = db.transaction().log_expect("expected to be able to begin transaction");
+    let query = "UPDATE PurchaseOrders SET shipped = 1, shippedAt = datetime('now') WHERE id = :poid AND shipped = 0";
+    let updated = tx.execute(query, named_params! {":poid": poid})
+        .log_expect("expected to be able to update PurchaseOrders table in execute");
+    if updated > 0 {
+        let shipped_at: Option<String> = tx
+            .query_row("SELECT shippedAt FROM PurchaseOrders WHERE id = :poid", named_params! {":poid": poid}, |row| row.get(0))
+            .log_expect("expected to be able to select from PurchaseOrders table in query_row");
+        audit::record(&tx, actor, Action::OrderShipped, "purchase_order", poid,
+                      Some(&json!({"shipped": 0, "shipped_at": null})), Some(&json!({"shipped": 1, "shipped_at": shipped_at})));
+    }
+    tx.commit().log_expect("expected to be able to commit transaction");
     info!(target: "file", "Successfully updated shipped status of purchase order id {} to {}", poid, 1);
 }
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PurchaseOrderSummary {
+    pub order_id: i64,
+    pub customer_id: i64,
+    pub book_id: i64,
+    pub book_title: String,
+    pub quantity: i64,
+    // Before discounts, None like price_paid for orders from before prices were recorded
+    pub subtotal: Option<f64>,
+    pub price_paid: Option<f64>,
+    pub shipped: i64,
+    pub created_at: Option<String>,
+    pub shipped_at: Option<String>,
+    pub shipping_address: Option<String>,
+}
+
+const SUMMARY_COLUMNS: &str = "po.id, po.customerId, po.bookId, b.title, po.pricePaid, po.shipped, po.createdAt, \
+                               po.shippedAt, po.shippingAddress, po.quantity, po.subtotal";
+
+fn summary_from_row(row: &Row) -> rusqlite::Result<PurchaseOrderSummary> {
+    Ok(PurchaseOrderSummary {
+        order_id: row.get(0)?,
+        customer_id: row.get(1)?,
+        book_id: row.get(2)?,
+        book_title: row.get(3)?,
+        quantity: row.get(9)?,
+        subtotal: row.get(10)?,
+        price_paid: row.get(4)?,
+        shipped: row.get(5)?,
+        created_at: row.get(6)?,
+        shipped_at: row.get(7)?,
+        shipping_address: row.get(8)?,
+    })
+}
+
+pub fn get_purchase_order(poid: i64) -> Option<PurchaseOrderSummary> {
+    let db = connect();
+    let query = format!("SELECT {} FROM PurchaseOrders po JOIN Books b ON b.id = po.bookId WHERE po.id = :poid", SUMMARY_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to be able to select from PurchaseOrders table in prepare");
+    let mut rows = stmt
+        .query_map(named_params! {":poid": poid}, summary_from_row)
+        .log_expect("expected to be able to get order from PurchaseOrders table in query_map");
+    return rows.next().map(|r| r.log_expect("expected to be able to read PurchaseOrders row"));
+}
+
+// Filters are optional, None matches every order. Dates are inclusive and of form YYYY-MM-DD.
+pub struct PurchaseOrderFilter {
+    pub shipped: Option<i64>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+// Returns one page of a customer's orders, newest first, along with the total number matching the filter
+pub fn get_customer_purchase_orders(cid: i64, filter: &PurchaseOrderFilter, limit: i64, offset: i64) -> (Vec<PurchaseOrderSummary>, i64) {
+    let (orders, total) = query_purchase_orders(Some(cid), filter, limit, offset);
+    info!(target: "file", "Successfully retrieved {} of {} orders for customer id: {}", orders.len(), total, cid);
+    return (orders, total);
+}
+
+// Like get_customer_purchase_orders, for every customer
+pub fn get_purchase_orders(filter: &PurchaseOrderFilter, limit: i64, offset: i64) -> (Vec<PurchaseOrderSummary>, i64) {
+    let (orders, total) = query_purchase_orders(None, filter, limit, offset);
+    info!(target: "file", "Successfully retrieved {} of {} orders", orders.len(), total);
+    return (orders, total);
+}
+
+fn query_purchase_orders(cid: Option<i64>, filter: &PurchaseOrderFilter, limit: i64, offset: i64) -> (Vec<PurchaseOrderSummary>, i64) {
+    let db = connect();
+    let conditions = "(:cid IS NULL OR po.customerId = :cid) \
+                      AND (:shipped IS NULL OR po.shipped = :shipped) \
+                      AND (:since IS NULL OR date(po.createdAt) >= :since) \
+                      AND (:until IS NULL OR date(po.createdAt) <= :until)";
+
+    let count_query = format!("SELECT COUNT(*) FROM PurchaseOrders po WHERE {}", conditions);
+    let total: i64 = db
+        .query_row(&count_query, named_params! {
+            ":cid": cid, ":shipped": filter.shipped, ":since": filter.since, ":until": filter.until,
+        }, |row| row.get(0))
+        .log_expect("expected to be able to count PurchaseOrders table in query_row");
+
+    let query = format!("SELECT {} FROM PurchaseOrders po JOIN Books b ON b.id = po.bookId \
+                         WHERE {} ORDER BY po.id DESC LIMIT :limit OFFSET :offset", SUMMARY_COLUMNS, conditions);
+    let mut stmt = db.prepare(&query).log_expect("expected to be able to select from PurchaseOrders table in prepare");
+    let orders = stmt
+        .query_map(named_params! {
+            ":cid": cid, ":shipped": filter.shipped, ":since": filter.since, ":until": filter.until,
+            ":limit": limit, ":offset": offset,
+        }, summary_from_row)
+        .log_expect("expected to be able to get orders from PurchaseOrders table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read PurchaseOrders rows");
+    return (orders, total);
+}
+
+pub enum CancelOrderError {
+    NotFound,
+    AlreadyShipped,
+}
+
+// Deletes an order that hasn't shipped and refunds what was paid to the customer's balance.
+// The order is kept in the audit log. Returns the cancelled order.
+pub fn cancel_po(actor: &Actor, poid: i64) -> Result<PurchaseOrderSummary, CancelOrderError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let query = format!("SELECT {} FROM PurchaseOrders po JOIN Books b ON b.id = po.bookId WHERE po.id = :poid", SUMMARY_COLUMNS);
+    let order = tx.query_row(&query, named_params! {":poid": poid}, summary_from_row)
+        .optional()
+        .log_expect("expected to be able to select from PurchaseOrders table in query_row")
+        .ok_or(CancelOrderError::NotFound)?;
+    if order.shipped != 0 {
+        return Err(CancelOrderError::AlreadyShipped);
+    }
+    // Its discounts and taxes go first, freeing the promotions' uses
+    promotions::remove_order(&tx, poid);
+    tx.execute("DELETE FROM OrderTaxes WHERE orderId = :poid", named_params! {":poid": poid})
+        .log_expect("expected to be able to delete from OrderTaxes table in execute");
+    tx.execute("DELETE FROM PurchaseOrders WHERE id = :poid", named_params! {":poid": poid})
+        .log_expect("expected to be able to delete from PurchaseOrders table in execute");
+    audit::record(&tx, actor, Action::OrderCancelled, "purchase_order", poid, Some(&order), None);
+    books::return_to_stock(&tx, order.book_id, order.quantity);
+
+    // Orders from before prices were recorded refund nothing
+    let refund = order.price_paid.unwrap_or(0.0);
+    let balance: f64 = tx
+        .query_row("SELECT accountBalance FROM customers WHERE id = :cid", named_params! {":cid": order.customer_id}, |row| row.get(0))
+        .log_expect("expected to be able to select from Customers table in query_row");
+    tx.execute("UPDATE customers SET accountBalance = :balance WHERE id = :cid",
+               named_params! {":balance": balance + refund, ":cid": order.customer_id})
+        .log_expect("expected to be able to update Customers table in execute");
+    audit::record(&tx, actor, Action::BalanceChanged, "customer", order.customer_id,
+                  Some(&json!({"account_balance": balance})), Some(&json!({"account_balance": balance + refund})));
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully cancelled purchase order id {} and refunded {:.2} to customer id {}", poid, refund, order.customer_id);
+    return Ok(order);
+}
@@ -0,0 +1,346 @@
+use super::books::{book_from_row, BookRecord, LogErrResult, BOOK_COLUMNS};
+use super::db::connect;
+use crate::text::canonical_key;
+use rusqlite::{named_params, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::Serialize;
+use log::info;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CategoryRecord {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    // Books in the category or any of its descendants
+    pub books: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: CategoryRecord,
+    pub children: Vec<CategoryNode>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TagRecord {
+    pub id: i64,
+    pub name: String,
+    pub books: i64,
+}
+
+// The categories and tags of one book
+#[derive(Serialize, Debug, Clone)]
+pub struct Classification {
+    pub categories: Vec<CategoryRecord>,
+    pub tags: Vec<String>,
+}
+
+// Books of a catalog listing in each subcategory of the category listed, or each top-level category,
+// and with each tag, leaving out those with none
+#[derive(Serialize, Debug, Clone)]
+pub struct Facets {
+    pub categories: Vec<CategoryFacet>,
+    pub tags: Vec<TagFacet>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CategoryFacet {
+    pub id: i64,
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TagFacet {
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug)]
+pub enum CategoryError {
+    NotFound(i64),
+    // A parent or book category that doesn't exist
+    Unknown(i64),
+    // A sibling already has the name, by its id
+    Duplicate(i64),
+    // A category can't be moved below itself
+    Cycle,
+    // Only categories without subcategories can be deleted, by their count
+    HasChildren(i64),
+    // Nor categories promotions are for, by their count
+    HasPromotions(i64),
+}
+
+// Every category with the categories below it, Tree.rootId being the one they are below
+const CATEGORY_TREE: &str = "WITH RECURSIVE Tree(rootId, id) AS \
+    (SELECT id, id FROM Categories UNION SELECT Tree.rootId, Categories.id FROM Categories JOIN Tree ON Categories.parentId = Tree.id)";
+
+const CATEGORY_COLUMNS: &str = "Categories.id, Categories.name, Categories.parentId, \
+    (SELECT COUNT(DISTINCT BookCategories.bookId) FROM BookCategories JOIN Tree ON Tree.id = BookCategories.categoryId \
+     WHERE Tree.rootId = Categories.id)";
+
+// The ids of the books matching a catalog listing's :cid, including its descendants, and :tag_key, either of which may be NULL
+const MATCHING_BOOKS: &str = "WITH RECURSIVE Subtree(id) AS \
+    (SELECT :cid UNION SELECT Categories.id FROM Categories JOIN Subtree ON Categories.parentId = Subtree.id), \
+    Matching(id) AS (SELECT Books.id FROM Books \
+     WHERE (:cid IS NULL OR Books.id IN (SELECT bookId FROM BookCategories WHERE categoryId IN Subtree)) \
+     AND (:tag_key IS NULL OR Books.id IN (SELECT bookId FROM BookTags JOIN Tags ON Tags.id = BookTags.tagId WHERE Tags.nameKey = :tag_key)))";
+
+fn category_from_row(row: &Row) -> rusqlite::Result<CategoryRecord> {
+    Ok(CategoryRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        books: row.get(3)?,
+    })
+}
+
+fn query_categories(db: &Connection, filter: &str, bid: Option<i64>) -> Vec<CategoryRecord> {
+    let query = format!("{} SELECT {} FROM Categories {} ORDER BY Categories.nameKey", CATEGORY_TREE, CATEGORY_COLUMNS, filter);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let rows = match bid {
+        Some(bid) => stmt.query_map(named_params! {":bid": bid}, category_from_row),
+        None => stmt.query_map((), category_from_row),
+    };
+    return rows
+        .log_expect("expected to be able to get categories from Categories table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Categories rows");
+}
+
+fn query_category(db: &Connection, cid: i64) -> Option<CategoryRecord> {
+    let query = format!("{} SELECT {} FROM Categories WHERE Categories.id = :cid", CATEGORY_TREE, CATEGORY_COLUMNS);
+    return db
+        .query_row(&query, named_params! {":cid": cid}, category_from_row)
+        .optional()
+        .log_expect("expected to be able to get category from Categories table in query_row");
+}
+
+pub fn get_category(cid: i64) -> Option<CategoryRecord> {
+    return query_category(&connect(), cid);
+}
+
+// Every category below its parent, siblings by name
+pub fn category_tree() -> Vec<CategoryNode> {
+    let categories = query_categories(&connect(), "", None);
+    return children_of(&categories, None);
+}
+
+fn children_of(categories: &[CategoryRecord], parent_id: Option<i64>) -> Vec<CategoryNode> {
+    return categories
+        .iter()
+        .filter(|c| c.parent_id == parent_id)
+        .map(|c| CategoryNode { category: c.clone(), children: children_of(categories, Some(c.id)) })
+        .collect();
+}
+
+pub fn create_category(name: &str, parent_id: Option<i64>) -> Result<CategoryRecord, CategoryError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    check_parent(&tx, 0, name, parent_id)?;
+    tx.execute("INSERT INTO Categories (name, nameKey, parentId) VALUES (:name, :name_key, :parent_id)",
+               named_params! {":name": name, ":name_key": canonical_key(name), ":parent_id": parent_id})
+        .log_expect("expected to be able to insert into Categories table in execute");
+    let cid = tx.last_insert_rowid();
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully created category id: {} named {}", cid, name);
+    return Ok(get_category(cid).expect("a category that was just created should exist"));
+}
+
+// Renames the category and moves it, with its descendants, below another parent or to the top
+pub fn update_category(cid: i64, name: &str, parent_id: Option<i64>) -> Result<CategoryRecord, CategoryError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    query_category(&tx, cid).ok_or(CategoryError::NotFound(cid))?;
+    check_parent(&tx, cid, name, parent_id)?;
+    if let Some(parent_id) = parent_id {
+        let query = "WITH RECURSIVE Subtree(id) AS \
+            (SELECT :cid UNION SELECT Categories.id FROM Categories JOIN Subtree ON Categories.parentId = Subtree.id) \
+            SELECT COUNT(*) FROM Subtree WHERE id = :parent_id";
+        let below: i64 = tx.query_row(query, named_params! {":cid": cid, ":parent_id": parent_id}, |row| row.get(0))
+            .log_expect("expected to be able to count Categories table in query_row");
+        if below > 0 {
+            return Err(CategoryError::Cycle);
+        }
+    }
+    tx.execute("UPDATE Categories SET name = :name, nameKey = :name_key, parentId = :parent_id WHERE id = :cid",
+               named_params! {":name": name, ":name_key": canonical_key(name), ":parent_id": parent_id, ":cid": cid})
+        .log_expect("expected to be able to update Categories table in execute");
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully updated category id: {} to {} below {:?}", cid, name, parent_id);
+    return Ok(get_category(cid).expect("a category that was just updated should exist"));
+}
+
+// The parent must exist and not have another child with the name, cid being the category itself if it exists
+fn check_parent(db: &Connection, cid: i64, name: &str, parent_id: Option<i64>) -> Result<(), CategoryError> {
+    if let Some(parent_id) = parent_id {
+        query_category(db, parent_id).ok_or(CategoryError::Unknown(parent_id))?;
+    }
+    let sibling: Option<i64> = db
+        .query_row("SELECT id FROM Categories WHERE parentId IS :parent_id AND nameKey = :name_key AND id != :cid",
+                   named_params! {":parent_id": parent_id, ":name_key": canonical_key(name), ":cid": cid}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Categories table in query_row");
+    return match sibling {
+        Some(sibling) => Err(CategoryError::Duplicate(sibling)),
+        None => Ok(()),
+    };
+}
+
+// Books in the category keep their other categories
+pub fn delete_category(cid: i64) -> Result<CategoryRecord, CategoryError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    let category = query_category(&tx, cid).ok_or(CategoryError::NotFound(cid))?;
+    let children: i64 = tx.query_row("SELECT COUNT(*) FROM Categories WHERE parentId = :cid", named_params! {":cid": cid}, |row| row.get(0))
+        .log_expect("expected to be able to count Categories table in query_row");
+    if children > 0 {
+        return Err(CategoryError::HasChildren(children));
+    }
+    let promotions: i64 = tx.query_row("SELECT COUNT(*) FROM Promotions WHERE categoryId = :cid", named_params! {":cid": cid}, |row| row.get(0))
+        .log_expect("expected to be able to count Promotions table in query_row");
+    if promotions > 0 {
+        return Err(CategoryError::HasPromotions(promotions));
+    }
+    tx.execute("DELETE FROM BookCategories WHERE categoryId = :cid", named_params! {":cid": cid})
+        .log_expect("expected to be able to delete from BookCategories table in execute");
+    tx.execute("DELETE FROM Categories WHERE id = :cid", named_params! {":cid": cid})
+        .log_expect("expected to be able to delete from Categories table in execute");
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully deleted category id: {}", cid);
+    return Ok(category);
+}
+
+pub fn get_classification(bid: i64) -> Classification {
+    return query_classification(&connect(), bid);
+}
+
+fn query_classification(db: &Connection, bid: i64) -> Classification {
+    let categories = query_categories(db, "WHERE Categories.id IN (SELECT categoryId FROM BookCategories WHERE bookId = :bid)", Some(bid));
+    let mut stmt = db
+        .prepare("SELECT Tags.name FROM BookTags JOIN Tags ON Tags.id = BookTags.tagId WHERE BookTags.bookId = :bid ORDER BY Tags.nameKey")
+        .log_expect("expected to prepare statement correctly in prepare");
+    let tags = stmt
+        .query_map(named_params! {":bid": bid}, |row| row.get(0))
+        .log_expect("expected to be able to get tags from Tags table in query_map")
+        .collect::<Result<Vec<String>, _>>()
+        .log_expect("expected to be able to read Tags rows");
+    return Classification { categories, tags };
+}
+
+// Replaces the book's categories and tags with those given, leaving either as it is when None.
+// Tags are matched regardless of case and Unicode normalization form, and added when not seen before.
+pub fn classify_book(bid: i64, categories: Option<&[i64]>, tags: Option<&[String]>) -> Result<Classification, CategoryError> {
+    let mut db = connect();
+    let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).log_expect("expected to be able to begin transaction");
+    if let Some(categories) = categories {
+        tx.execute("DELETE FROM BookCategories WHERE bookId = :bid", named_params! {":bid": bid})
+            .log_expect("expected to be able to delete from BookCategories table in execute");
+        for cid in categories {
+            query_category(&tx, *cid).ok_or(CategoryError::Unknown(*cid))?;
+            tx.execute("INSERT OR IGNORE INTO BookCategories (bookId, categoryId) VALUES (:bid, :cid)", named_params! {":bid": bid, ":cid": cid})
+                .log_expect("expected to be able to insert into BookCategories table in execute");
+        }
+    }
+    if let Some(tags) = tags {
+        tx.execute("DELETE FROM BookTags WHERE bookId = :bid", named_params! {":bid": bid})
+            .log_expect("expected to be able to delete from BookTags table in execute");
+        for tag in tags {
+            let tid = tag_id(&tx, tag);
+            tx.execute("INSERT OR IGNORE INTO BookTags (bookId, tagId) VALUES (:bid, :tid)", named_params! {":bid": bid, ":tid": tid})
+                .log_expect("expected to be able to insert into BookTags table in execute");
+        }
+        remove_unused_tags(&tx);
+    }
+    let classification = query_classification(&tx, bid);
+    tx.commit().log_expect("expected to be able to commit transaction");
+    info!(target: "file", "Successfully classified book id: {} in {} categories with {} tags",
+          bid, classification.categories.len(), classification.tags.len());
+    return Ok(classification);
+}
+
+fn tag_id(db: &Connection, name: &str) -> i64 {
+    let key = canonical_key(name);
+    let existing = db
+        .query_row("SELECT id FROM Tags WHERE nameKey = :name_key", named_params! {":name_key": key}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Tags table in query_row");
+    if let Some(tid) = existing {
+        return tid;
+    }
+    db.execute("INSERT INTO Tags (name, nameKey) VALUES (:name, :name_key)", named_params! {":name": name, ":name_key": key})
+        .log_expect("expected to be able to insert into Tags table in execute");
+    return db.last_insert_rowid();
+}
+
+fn remove_unused_tags(db: &Connection) {
+    db.execute("DELETE FROM Tags WHERE id NOT IN (SELECT tagId FROM BookTags)", ())
+        .log_expect("expected to be able to delete from Tags table in execute");
+}
+
+// For books::delete_book, within its transaction
+pub fn remove_book(db: &Connection, bid: i64) {
+    db.execute("DELETE FROM BookCategories WHERE bookId = :bid", named_params! {":bid": bid})
+        .log_expect("expected to be able to delete from BookCategories table in execute");
+    db.execute("DELETE FROM BookTags WHERE bookId = :bid", named_params! {":bid": bid})
+        .log_expect("expected to be able to delete from BookTags table in execute");
+    remove_unused_tags(db);
+}
+
+// Every tag by name, with the number of books that have it
+pub fn list_tags() -> Vec<TagRecord> {
+    let db = connect();
+    let mut stmt = db
+        .prepare("SELECT Tags.id, Tags.name, COUNT(BookTags.bookId) FROM Tags JOIN BookTags ON BookTags.tagId = Tags.id \
+                  GROUP BY Tags.id ORDER BY Tags.nameKey")
+        .log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map((), |row| Ok(TagRecord { id: row.get(0)?, name: row.get(1)?, books: row.get(2)? }))
+        .log_expect("expected to be able to get tags from Tags table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Tags rows");
+}
+
+// A page of the books by id in the category, including its descendants, and with the tag if given,
+// the count of all that match and their facets
+pub fn list_catalog(category: Option<i64>, tag: Option<&str>, limit: i64, offset: i64) -> (Vec<BookRecord>, i64, Facets) {
+    let db = connect();
+    let tag_key = tag.map(canonical_key);
+
+    let query = format!("{} SELECT {} FROM Books WHERE Books.id IN Matching ORDER BY Books.id LIMIT :limit OFFSET :offset",
+                        MATCHING_BOOKS, BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let books = stmt
+        .query_map(named_params! {":cid": category, ":tag_key": tag_key, ":limit": limit, ":offset": offset}, book_from_row)
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+    let total = db
+        .query_row(&format!("{} SELECT COUNT(*) FROM Matching", MATCHING_BOOKS), named_params! {":cid": category, ":tag_key": tag_key},
+                   |row| row.get(0))
+        .log_expect("expected to be able to count Books table in query_row");
+
+    let query = format!("{}, Below(rootId, id) AS \
+        (SELECT id, id FROM Categories WHERE parentId IS :cid UNION SELECT Below.rootId, Categories.id FROM Categories JOIN Below ON Categories.parentId = Below.id) \
+        SELECT Categories.id, Categories.name, COUNT(DISTINCT BookCategories.bookId) FROM Categories \
+        JOIN Below ON Below.rootId = Categories.id JOIN BookCategories ON BookCategories.categoryId = Below.id \
+        WHERE BookCategories.bookId IN Matching GROUP BY Categories.id ORDER BY Categories.nameKey", MATCHING_BOOKS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let categories = stmt
+        .query_map(named_params! {":cid": category, ":tag_key": tag_key},
+                   |row| Ok(CategoryFacet { id: row.get(0)?, name: row.get(1)?, count: row.get(2)? }))
+        .log_expect("expected to be able to get categories from Categories table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Categories rows");
+
+    let query = format!("{} SELECT Tags.name, COUNT(BookTags.bookId) FROM Tags JOIN BookTags ON BookTags.tagId = Tags.id \
+        WHERE BookTags.bookId IN Matching GROUP BY Tags.id ORDER BY Tags.nameKey", MATCHING_BOOKS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let tags = stmt
+        .query_map(named_params! {":cid": category, ":tag_key": tag_key}, |row| Ok(TagFacet { name: row.get(0)?, count: row.get(1)? }))
+        .log_expect("expected to be able to get tags from Tags table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Tags rows");
+
+    return (books, total, Facets { categories, tags });
+}
@@ -0,0 +1,150 @@
+use super::books::{book_from_row, BookRecord, LogErrResult, BOOK_COLUMNS};
+use super::db::connect;
+use crate::text::canonical_key;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use log::info;
+
+// A title by its author, sold as one or more editions, the rows of Books with its workId
+#[derive(Serialize, Debug, Clone)]
+pub struct WorkRecord {
+    pub id: i64,
+    pub title: String,
+    pub author: String,
+    pub series: Option<String>,
+    pub series_position: Option<i64>,
+    pub editions: Vec<BookRecord>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SeriesRecord {
+    pub id: i64,
+    pub name: String,
+    pub works: i64,
+}
+
+// The work with the title and author, added when there is none yet
+pub fn work_id(db: &Connection, title: &str, author: &str) -> i64 {
+    let (title_key, author_key) = (canonical_key(title), canonical_key(author));
+    let existing = db
+        .query_row("SELECT id FROM Works WHERE titleKey = :title_key AND authorKey = :author_key",
+                   named_params! {":title_key": title_key, ":author_key": author_key}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Works table in query_row");
+    if let Some(wid) = existing {
+        return wid;
+    }
+    db.execute("INSERT INTO Works (title, author, titleKey, authorKey) VALUES (:title, :author, :title_key, :author_key)",
+               named_params! {":title": title, ":author": author, ":title_key": title_key, ":author_key": author_key})
+        .log_expect("expected to be able to insert into Works table in execute");
+    info!(target: "file", "Successfully created work id: {}", db.last_insert_rowid());
+    return db.last_insert_rowid();
+}
+
+// Puts the work in the series at the position, adding the series when it's new
+pub fn set_series(db: &Connection, wid: i64, series: &str, position: Option<i64>) {
+    let key = canonical_key(series);
+    let existing = db
+        .query_row("SELECT id FROM Series WHERE nameKey = :name_key", named_params! {":name_key": key}, |row| row.get(0))
+        .optional()
+        .log_expect("expected to be able to get id from Series table in query_row");
+    let sid: i64 = match existing {
+        Some(sid) => sid,
+        None => {
+            db.execute("INSERT INTO Series (name, nameKey) VALUES (:name, :name_key)", named_params! {":name": series, ":name_key": key})
+                .log_expect("expected to be able to insert into Series table in execute");
+            db.last_insert_rowid()
+        },
+    };
+    db.execute("UPDATE Works SET seriesId = :sid, seriesPosition = :position WHERE id = :wid",
+               named_params! {":sid": sid, ":position": position, ":wid": wid})
+        .log_expect("expected to be able to update Works table in execute");
+}
+
+// Works left without editions, and series without works, after books were changed or deleted
+pub fn remove_unused(db: &Connection) {
+    db.execute("DELETE FROM Works WHERE id NOT IN (SELECT workId FROM Books WHERE workId IS NOT NULL)", ())
+        .log_expect("expected to be able to delete from Works table in execute");
+    db.execute("DELETE FROM Series WHERE id NOT IN (SELECT seriesId FROM Works WHERE seriesId IS NOT NULL)", ())
+        .log_expect("expected to be able to delete from Series table in execute");
+}
+
+const WORK_COLUMNS: &str = "Works.id, Works.title, Works.author, Series.name, Works.seriesPosition";
+
+fn work_from_row(row: &Row) -> rusqlite::Result<WorkRecord> {
+    Ok(WorkRecord {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        author: row.get(2)?,
+        series: row.get(3)?,
+        series_position: row.get(4)?,
+        editions: Vec::new(),
+    })
+}
+
+fn with_editions(db: &Connection, mut work: WorkRecord) -> WorkRecord {
+    let query = format!("SELECT {} FROM Books WHERE Books.workId = :wid ORDER BY Books.id", BOOK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    work.editions = stmt
+        .query_map(named_params! {":wid": work.id}, book_from_row)
+        .log_expect("expected to be able to get books from Books table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Books rows");
+    return work;
+}
+
+// The work with each of its editions by id
+pub fn get_work(wid: i64) -> Option<WorkRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Works LEFT JOIN Series ON Series.id = Works.seriesId WHERE Works.id = :wid", WORK_COLUMNS);
+    let work = db
+        .query_row(&query, named_params! {":wid": wid}, work_from_row)
+        .optional()
+        .log_expect("expected to be able to get work from Works table in query_row")?;
+    return Some(with_editions(&db, work));
+}
+
+const SERIES_COLUMNS: &str = "Series.id, Series.name, (SELECT COUNT(*) FROM Works WHERE Works.seriesId = Series.id)";
+
+fn series_from_row(row: &Row) -> rusqlite::Result<SeriesRecord> {
+    Ok(SeriesRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        works: row.get(2)?,
+    })
+}
+
+// Every series by name, with the number of works in it
+pub fn list_series() -> Vec<SeriesRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Series ORDER BY Series.nameKey", SERIES_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    return stmt
+        .query_map((), series_from_row)
+        .log_expect("expected to be able to get series from Series table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Series rows");
+}
+
+pub fn get_series(sid: i64) -> Option<SeriesRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Series WHERE Series.id = :sid", SERIES_COLUMNS);
+    return db
+        .query_row(&query, named_params! {":sid": sid}, series_from_row)
+        .optional()
+        .log_expect("expected to be able to get series from Series table in query_row");
+}
+
+// The works of a series in reading order, those without a position last, each with its editions
+pub fn series_works(sid: i64) -> Vec<WorkRecord> {
+    let db = connect();
+    let query = format!("SELECT {} FROM Works JOIN Series ON Series.id = Works.seriesId WHERE Works.seriesId = :sid \
+                         ORDER BY Works.seriesPosition IS NULL, Works.seriesPosition, Works.titleKey", WORK_COLUMNS);
+    let mut stmt = db.prepare(&query).log_expect("expected to prepare statement correctly in prepare");
+    let works = stmt
+        .query_map(named_params! {":sid": sid}, work_from_row)
+        .log_expect("expected to be able to get works from Works table in query_map")
+        .collect::<Result<Vec<_>, _>>()
+        .log_expect("expected to be able to read Works rows");
+    return works.into_iter().map(|work| with_editions(&db, work)).collect();
+}
@@ -0,0 +1,183 @@
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+use log::error;
+
+use crate::address::normalize_address;
+use crate::db::addresses::{self, AddressFields, CustomerAddress};
+use crate::db::customers;
+use crate::request_id::RequestActor;
+use crate::valid::Valid;
+use crate::validation::{normalize_text_in, FieldError, Rule, Rules, Validate, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Address {
+    line1: Option<String>,
+    line2: Option<String>,
+    city: Option<String>,
+    region: Option<String>,
+    postal_code: Option<String>,
+    country: Option<String>,
+    is_default: Option<bool>,
+}
+
+impl Validate for Address {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "line1" => Some(Value::Text(&self.line1)),
+            "line2" => Some(Value::Text(&self.line2)),
+            "city" => Some(Value::Text(&self.city)),
+            "region" => Some(Value::Text(&self.region)),
+            "postal_code" => Some(Value::Text(&self.postal_code)),
+            "country" => Some(Value::Text(&self.country)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        for field in [&mut self.line1, &mut self.line2, &mut self.city, &mut self.region, &mut self.postal_code, &mut self.country] {
+            normalize_text_in(field);
+        }
+        // Optional fields given as empty strings are treated as not given
+        for field in [&mut self.line2, &mut self.region, &mut self.postal_code] {
+            if field.as_deref() == Some("") {
+                *field = None;
+            }
+        }
+    }
+
+    // The country decides which rules apply to the other fields, see address::normalize_address
+    fn check(&self, prefix: &str, function: &str) -> Vec<FieldError> {
+        match validate_address(self, prefix, function) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors,
+        }
+    }
+}
+
+pub struct SaveAddress;
+impl Rules<Address> for SaveAddress {
+    const FUNCTION: &'static str = "save_address";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[];
+}
+
+impl Address {
+    // The stored form of an address that passed validation
+    pub fn fields(&self, function: &str) -> AddressFields {
+        return validate_address(self, "", function).expect("addresses are checked by the Valid data guard");
+    }
+}
+
+#[get("/<cid>/addresses")]
+pub fn get_addresses(cid: i64) -> Result<Json<Vec<CustomerAddress>>, String> {
+    match validate_customer(cid, "get_addresses") {
+        Ok(()) => 0,
+        Err(err_msg) => return Err(err_msg),
+    };
+    Ok(Json(addresses::get_addresses(cid)))
+}
+
+#[post("/<cid>/addresses", data = "<address>")]
+pub fn create_address(cid: i64, address: Valid<Address, SaveAddress>, actor: RequestActor) -> Result<String, String> {
+    match validate_customer(cid, "create_address") {
+        Ok(()) => 0,
+        Err(err_msg) => return Err(err_msg),
+    };
+    let fields = address.fields(SaveAddress::FUNCTION);
+
+    let aid = addresses::create_address(&actor, cid, &fields, address.is_default.unwrap_or(false));
+    let success_msg = format!("Successfully added address ID: {} for customer ID: {}", aid, cid);
+    Ok(success_msg)
+}
+
+#[put("/<cid>/addresses/<aid>", data = "<address>")]
+pub fn update_address(cid: i64, aid: i64, address: Valid<Address, SaveAddress>, actor: RequestActor) -> Result<String, String> {
+    match validate_customer(cid, "update_address") {
+        Ok(()) => 0,
+        Err(err_msg) => return Err(err_msg),
+    };
+    let fields = address.fields(SaveAddress::FUNCTION);
+
+    if !addresses::update_address(&actor, cid, aid, &fields) {
+        return Err(format!("No address ID {} for customer ID {}", aid, cid));
+    }
+    if address.is_default == Some(true) {
+        addresses::set_default_address(&actor, cid, aid);
+    }
+    let success_msg = format!("Successfully updated address ID: {} to {}", aid, fields.formatted());
+    Ok(success_msg)
+}
+
+#[put("/<cid>/addresses/<aid>/default")]
+pub fn set_default_address(cid: i64, aid: i64, actor: RequestActor) -> Result<String, String> {
+    match validate_customer(cid, "set_default_address") {
+        Ok(()) => 0,
+        Err(err_msg) => return Err(err_msg),
+    };
+    if !addresses::set_default_address(&actor, cid, aid) {
+        return Err(format!("No address ID {} for customer ID {}", aid, cid));
+    }
+    let success_msg = format!("Address ID: {} is now the default for customer ID: {}", aid, cid);
+    Ok(success_msg)
+}
+
+#[delete("/<cid>/addresses/<aid>")]
+pub fn delete_address(cid: i64, aid: i64, actor: RequestActor) -> Result<String, String> {
+    match validate_customer(cid, "delete_address") {
+        Ok(()) => 0,
+        Err(err_msg) => return Err(err_msg),
+    };
+    // Orders need somewhere to ship to, so the last address can only be replaced, not removed
+    if addresses::get_addresses(cid).len() <= 1 {
+        return Err("A customer must keep at least one address".to_string());
+    }
+    if !addresses::delete_address(&actor, cid, aid) {
+        return Err(format!("No address ID {} for customer ID {}", aid, cid));
+    }
+    let success_msg = format!("Successfully deleted address ID: {}", aid);
+    Ok(success_msg)
+}
+
+fn validate_customer(cid: i64, function: &str) -> Result<(), String> {
+    match cid > 0 {
+        true => 0,
+        false => return Err("Id numbers must be positive".to_string()),
+    };
+    if !customers::customer_exists(cid) {
+        error!(target: "file", "Unknown customer id in {}: {}", function, cid);
+        return Err(format!("No customer with customerID {}", cid));
+    }
+    return Ok(());
+}
+
+// Checks each field of a structured address is present, line2, region and postal_code are optional
+fn validate_address(address: &Address, prefix: &str, function: &str) -> Result<AddressFields, Vec<FieldError>> {
+    let mut missing = Vec::new();
+    let mut required = |value: &Option<String>, field: &str| -> String {
+        match value {
+            Some(v) => v.clone(),
+            None => {
+                error!(target: "file", "No {}{} given in {}", prefix, field, function);
+                missing.push(FieldError::new(&format!("{}{}", prefix, field), "required", "Please provide a value"));
+                String::new()
+            },
+        }
+    };
+
+    let fields = AddressFields {
+        line1: required(&address.line1, "line1"),
+        line2: address.line2.clone(),
+        city: required(&address.city, "city"),
+        region: address.region.clone(),
+        postal_code: address.postal_code.clone(),
+        country: required(&address.country, "country"),
+    };
+    // Missing fields are checked as empty, so only report the other fields' problems alongside them
+    return match normalize_address(fields, prefix, function) {
+        Ok(normalized) if missing.is_empty() => Ok(normalized),
+        Ok(_) => Err(missing),
+        Err(errors) => {
+            let invalid = errors.into_iter().filter(|e| !missing.iter().any(|m| m.field == e.field));
+            Err(missing.iter().cloned().chain(invalid).collect())
+        },
+    };
+}
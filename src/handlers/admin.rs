@@ -0,0 +1,56 @@
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::Json;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use log::{info, warn};
+
+use crate::db::audit::{self, ChainVerification};
+
+const HEADER: &str = "X-Admin-Token";
+
+// Read from the `admin_token` key in Rocket.toml, the /admin routes answer 404 while it's unset
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AdminConfig {
+    admin_token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn fairing() -> impl Fairing {
+        AdHoc::config::<AdminConfig>()
+    }
+}
+
+// Given by requests carrying the configured X-Admin-Token
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let expected = match req.rocket().state::<AdminConfig>().and_then(|c| c.admin_token.as_deref()) {
+            Some(token) if !token.is_empty() => token,
+            _ => return request::Outcome::Failure((Status::NotFound, "Admin routes are disabled".to_string())),
+        };
+        // Digests are compared so the time taken doesn't reveal how much of the token matched
+        let given = req.headers().get_one(HEADER).unwrap_or_default();
+        if Sha256::digest(given.as_bytes()) != Sha256::digest(expected.as_bytes()) {
+            warn!(target: "file", "Rejected {} {} without a valid {}", req.method(), req.uri(), HEADER);
+            return request::Outcome::Failure((Status::Unauthorized, format!("Please give a valid {}", HEADER)));
+        }
+        request::Outcome::Success(AdminToken)
+    }
+}
+
+// 409 if an audit event was edited, removed or reordered since it was recorded
+#[get("/audit/verify")]
+pub fn verify_audit(_admin: AdminToken) -> (Status, Json<ChainVerification>) {
+    let verification = audit::verify_chain();
+    info!(target: "file", "Audit chain verification requested, valid: {}", verification.valid);
+    if !verification.valid {
+        return (Status::Conflict, Json(verification));
+    }
+    (Status::Ok, Json(verification))
+}
@@ -1,46 +1,40 @@
-use crate::db::books;
+use crate::catalog::{self, Book, CatalogFormat, CreateBook, FindBook, ImportReport};
+use crate::db::audit::Actor;
+use crate::db::books::{self, BookConflict};
+use crate::db::prices::{self, PriceRecord};
+use crate::onix::{self, OnixReport};
+use rocket::data::{ByteUnit, Data, Limits, ToByteUnit};
+use rocket::futures::stream::{self, Stream};
+use rocket::http::{ContentType, Status};
+use rocket::response::status::{BadRequest, Custom, NotFound};
+use rocket::response::stream::ByteStream;
 use rocket::serde::json::Json;
-use serde::{Deserialize, Serialize};
-use regex::Regex;
-use log::error;
+use rocket::State;
+use log::{error, info};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Book {
-    id: Option<i64>,
-    title: Option<String>,
-    author: Option<String>,
-    price: Option<f64>,
-}
+use super::idempotency::{Idempotent, IdempotencyKey};
+use crate::request_id::RequestActor;
+use crate::valid::Valid;
+use crate::validation::{required, ValidationConfig};
+
+// Request bodies of POST /books/import and /books/onix, unless `limits.catalog` or `limits.onix` is set in Rocket.toml
+const DEFAULT_CATALOG_LIMIT_MIB: u64 = 8;
+const DEFAULT_ONIX_LIMIT_MIB: u64 = 32;
 
 #[post("/new", data = "<book>")]
-pub fn create_book(book: Json<Book>) -> Result<(), String> {
-    let mut title = match book.title.clone() {
-        Some(t) => t,
-        None => return Err("No title provided".to_string()),
-    };
-    let mut author = match book.author.clone() {
-        Some(a) => a,
-        None => return Err("No author provided".to_string()),
-    };
-    title = fix_whitespace(title.clone());
-    author = fix_whitespace(author.clone());
+pub fn create_book(book: Valid<Book, CreateBook>, idempotency_key: IdempotencyKey, actor: RequestActor) -> Idempotent {
+    idempotency_key.run("create_book", 0, &*book, || new_book(&actor, &book).map(|()| String::new()))
+}
 
-    match validate_title_and_author(title.clone(), author.clone(), "create_book".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
-    
-    let price = match book.price {
-        Some(p) => p,
-        None => return Err("No price provided".to_string()),
-    };
-    match validate_price(price, "create_book".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
+fn new_book(actor: &Actor, book: &Book) -> Result<(), String> {
+    return books::create_book(actor, book.to_record()).map(|_| ()).map_err(book_conflict);
+}
 
-    books::create_book(title, author, price);
-    Ok(())
+pub fn book_conflict(conflict: BookConflict) -> String {
+    return match conflict {
+        BookConflict::SameEdition(bid) => format!("A book with this title, author and format exists with bookId {}", bid),
+        BookConflict::SameIsbn(bid) => format!("A book with this ISBN exists with bookId {}", bid),
+    };
 }
 
 // yes this throws a warning, it's how we're going it
@@ -48,92 +42,94 @@ pub fn create_book(book: Json<Book>) -> Result<(), String> {
 // because putting and posting to get the price makes less
 // sense in my mind
 #[get("/price", format = "json", data = "<book>")]
-pub fn get_price(book: Json<Book>) -> Result<String, String> {
-    let mut title = match book.title.clone() {
-        Some(t) => t,
-        None => return Err("No title provided".to_string()),
-    };
-    let mut author = match book.author.clone() {
-        Some(a) => a,
-        None => return Err("No author provided".to_string()),
-    };
-    title = fix_whitespace(title.clone());
-    author = fix_whitespace(author.clone());
+pub fn get_price(book: Valid<Book, FindBook>) -> Result<String, NotFound<String>> {
+    let title = required(&book.title);
+    let author = required(&book.author);
 
-    match validate_title_and_author(title.clone(), author.clone(), "get_price".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+    let bid = books::find_book_id(&title, &author);
+    let (bid, price) = match (bid, bid.and_then(books::get_book_price)) {
+        (Some(bid), Some(price)) => (bid, price),
+        _ => {
+            error!(target: "file", "Price requested for unknown book: {} by {}", title, author);
+            return Err(NotFound(format!("No book titled {} by {}", title, author)));
+        },
     };
-
-    let bid = books::get_book_id(title.clone(), author);
-    let price = books::get_book_price(bid);
     let result_string = format!("{}, with bookId {}, has price: ${:.2}", title, bid, price);
     Ok(result_string)
 }
 
-fn validate_price(price : f64, function : String) -> Result<(), String> {
-    if price <= 0.00 {
-        error!(target: "file", "Price of 0.00 given in {}", function);
-        let error_msg = format!("Please give a positive value (>0) for price");
-        return Err(error_msg);
-    }
-    // Adding .'s to integer prices for regex
-    let mut price_string = price.to_string();
-    match price_string.contains(".") {
-        true => (),
-        false => price_string.push('.'),
+// Every price the book had and has scheduled, oldest first, see prices::price_history
+#[get("/<bid>/prices")]
+pub fn get_prices(bid: i64) -> Result<Json<Vec<PriceRecord>>, NotFound<String>> {
+    return match prices::price_history(bid) {
+        Some(history) => Ok(Json(history)),
+        None => {
+            error!(target: "file", "Prices requested for unknown book id: {}", bid);
+            Err(NotFound(format!("No book with bookId {}", bid)))
+        },
     };
-
-
-    // Unwraps the regex error to see if it's a valid regex, decimals no greater than 10000
-    let re = Regex::new(r"^\d{1,4}\.\d{0,2}$").unwrap();
-    let valid = re.is_match(&price_string);
-    if !valid {
-        error!(target: "file", "Invalid price in {}: {}", function, price);
-        let error_msg = "Please input a valid price of form X.YY: 0 <= X <= 9999, 0 <= Y <= 9".to_string();
-        return Err(error_msg);
-    }
-    else {
-        return Ok(());
-    }
 }
 
-// Allows only alphabetic and numeric input for these fields, no weird ones like 💜 or < or /
-fn validate_alphanumeric_input(input : String, field : String, function: String) -> Result<(), String>{
-    if input.is_empty() || input.chars().all(char::is_whitespace) {
-        error!(target: "file", "Empty input given in {}, field: {}", function, field);
-        let error_msg = format!("Please input a valid {}:\n\t Please do not input only empty space.", field);
-        return Err(error_msg);
-    }
-    let valid = input.chars().all(|x| (x.is_alphanumeric() || x.is_whitespace() || x == '.' || x == ',')); // Gets only 'word' characters and spaces
-
-    if !valid {
-        error!(target: "file", "Invalid {} in {}: {}", field, function, input);
-        let error_msg = format!("Please input a valid {}:\n\t Please use only alphabet and numeric values.", field);
-        return Err(error_msg);
-    }
-    else {
-        return Ok(());
-    }   
+// The body is the whole catalog as CSV or JSON Lines, see catalog::import. Nothing is added if any row fails,
+// and a dry run only checks the rows. Answers with the report, with 422 if any row failed.
+#[post("/import?<format>&<dry_run>", data = "<catalog>")]
+pub async fn import_books(format: Option<CatalogFormat>, dry_run: Option<bool>, catalog: Data<'_>, limits: &Limits,
+                          config: &State<ValidationConfig>, actor: RequestActor) -> Result<Custom<Json<ImportReport>>, BadRequest<String>> {
+    let format = format_given(format, "import_books")?;
+    let limit = limits.get("catalog").unwrap_or(DEFAULT_CATALOG_LIMIT_MIB.mebibytes());
+    let input = read_upload(catalog, limit, "catalog", "import_books").await?;
+    let report = catalog::import(&actor, &input, format, dry_run.unwrap_or(false), config);
+    let status = if report.errors.is_empty() { Status::Ok } else { Status::UnprocessableEntity };
+    return Ok(Custom(status, Json(report)));
 }
 
-fn fix_whitespace(input : String) -> String {
-    // Remove spaces at beginning and end of string
-    let temp_string = input.trim().to_string();
-    // Remove extra spaces within
-    let ex_sp_re = Regex::new(r"\s+").unwrap();
-    return ex_sp_re.replace_all(temp_string.as_str(), " ").to_string();
+// The body is an ONIX 3.0 message, whose products are upserted by ISBN, see onix::ingest.
+// Answers with the report of what was done and skipped, or 400 if the body isn't an ONIX 3.0 message.
+#[post("/onix", data = "<message>")]
+pub async fn ingest_onix(message: Data<'_>, limits: &Limits, config: &State<ValidationConfig>, actor: RequestActor)
+                         -> Result<Json<OnixReport>, BadRequest<String>> {
+    let limit = limits.get("onix").unwrap_or(DEFAULT_ONIX_LIMIT_MIB.mebibytes());
+    let input = read_upload(message, limit, "onix", "ingest_onix").await?;
+    let xml = String::from_utf8(input).map_err(|_| {
+        error!(target: "file", "ONIX message not in UTF-8 in ingest_onix");
+        BadRequest(Some("Please send the ONIX message in UTF-8".to_string()))
+    })?;
+    return onix::ingest(&actor, &xml, config).map(Json).map_err(|e| {
+        error!(target: "file", "Unreadable ONIX message in ingest_onix: {}", e);
+        BadRequest(Some(e))
+    });
 }
 
-// Validates both the names and addresses, returning errors if they fail
-fn validate_title_and_author(title : String, author : String, function: String) -> Result<(), String> {
-    match validate_alphanumeric_input(title.clone(), "title".to_string(), function.clone()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+async fn read_upload(data: Data<'_>, limit: ByteUnit, name: &str, function: &str) -> Result<Vec<u8>, BadRequest<String>> {
+    return match data.open(limit).into_bytes().await {
+        Ok(input) if input.is_complete() => Ok(input.into_inner()),
+        Ok(_) => {
+            error!(target: "file", "Upload larger than {} rejected in {}", limit, function);
+            Err(BadRequest(Some(format!("Please send at most {} at once, or raise limits.{}", limit, name))))
+        },
+        Err(e) => {
+            error!(target: "file", "Unreadable upload in {}: {}", function, e);
+            Err(BadRequest(Some("The request body could not be read".to_string())))
+        },
     };
-    match validate_alphanumeric_input(author.clone(), "author".to_string(), function) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+}
+
+// Every book by id, streamed a page at a time, see catalog::export
+#[get("/export?<format>")]
+pub fn export_books(format: Option<CatalogFormat>) -> Result<(ContentType, ByteStream<impl Stream<Item = Vec<u8>>>), BadRequest<String>> {
+    let format = format_given(format, "export_books")?;
+    let content_type = match format {
+        CatalogFormat::Csv => ContentType::CSV,
+        CatalogFormat::Jsonl => ContentType::new("application", "x-ndjson"),
     };
-    return Ok(());
-}
\ No newline at end of file
+    info!(target: "file", "Exporting the catalog as {:?}", format);
+    return Ok((content_type, ByteStream(stream::iter(catalog::export(format)))));
+}
+
+// Missing and unknown formats are both None
+fn format_given(format: Option<CatalogFormat>, function: &str) -> Result<CatalogFormat, BadRequest<String>> {
+    return format.ok_or_else(|| {
+        error!(target: "file", "No valid format given in {}", function);
+        BadRequest(Some("Please give a format of csv or jsonl".to_string()))
+    });
+}
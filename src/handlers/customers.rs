@@ -1,84 +1,130 @@
+use rocket::http::Status;
+use rocket::response::status::Custom;
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
 use regex::Regex;
 
-use crate::db::customers::{self, get_customer_id};
+use crate::address::normalize_free_text;
+use crate::db::addresses::AddressFields;
+use crate::db::audit::Actor;
+use crate::db::customers;
+use crate::db::purchaseOrders::{self, PurchaseOrderFilter};
+use crate::redact::pii;
+use crate::valid::Valid;
+use crate::validation::{normalize_text_in, required, Rule, Rules, Validate, Value};
 use log::error;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::addresses::Address;
+use super::idempotency::{Idempotent, IdempotencyKey};
+use crate::request_id::RequestActor;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Customer {
-    id: Option<i64>,
-    name: Option<String>,
-    shipping_address: Option<String>,
-    account_balance: Option<f64>,
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    pub shipping_address: Option<String>,
+    // Structured alternative to shipping_address when creating a customer
+    pub address: Option<Address>,
+    pub account_balance: Option<f64>,
+}
+
+impl Validate for Customer {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "id" => Some(Value::Id(self.id)),
+            "name" => Some(Value::Text(&self.name)),
+            "shipping_address" => Some(Value::Text(&self.shipping_address)),
+            "address" => Some(Value::Nested(self.address.as_ref().map(|a| a as &dyn Validate))),
+            "account_balance" => Some(Value::Amount(self.account_balance)),
+            _ => None,
+        }
+    }
+
+    fn customer_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    fn normalize(&mut self) {
+        normalize_text_in(&mut self.name);
+        normalize_text_in(&mut self.shipping_address);
+        if let Some(address) = &mut self.address {
+            address.normalize();
+        }
+    }
+}
+
+pub struct CreateCustomer;
+impl Rules<Customer> for CreateCustomer {
+    const FUNCTION: &'static str = "create_customer";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("shipping_address", &[Rule::RequiredWithout("address"), Rule::StreetAddress]),
+        ("address", &[]),
+    ];
+}
+
+pub struct UpdateAddress;
+impl Rules<Customer> for UpdateAddress {
+    const FUNCTION: &'static str = "update_address";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("id", &[Rule::Required, Rule::PositiveId]),
+        ("shipping_address", &[Rule::Required, Rule::StreetAddress]),
+    ];
+}
+
+// Customers are identified by id when one is given, otherwise by name and any of their saved addresses
+pub struct FindCustomer;
+impl Rules<Customer> for FindCustomer {
+    const FUNCTION: &'static str = "get_balance";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("id", &[Rule::PositiveId]),
+        ("name", &[Rule::RequiredWithout("id"), Rule::Alphanumeric, Rule::SingleScript]),
+        ("shipping_address", &[Rule::RequiredWithout("id"), Rule::StreetAddress]),
+    ];
+}
+
+pub struct UpdateBalance;
+impl Rules<Customer> for UpdateBalance {
+    const FUNCTION: &'static str = "update_balance";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("id", &[Rule::PositiveId]),
+        ("name", &[Rule::RequiredWithout("id"), Rule::Alphanumeric, Rule::SingleScript]),
+        ("shipping_address", &[Rule::RequiredWithout("id"), Rule::StreetAddress]),
+        ("account_balance", &[Rule::Required, Rule::Amount]),
+    ];
 }
 
 #[post("/new", data = "<customer>")]
-pub fn create_customer(customer: Json<Customer>) -> Result<(), String> {
-    let mut name = match customer.name.clone() {
-        Some(n) => n,
-        None => return Err("No name provided".to_string()),
-    };
-    let mut address = match customer.shipping_address.clone() {
-        Some(a) => a,
-        None => return Err("No shipping_address provided".to_string()),
-    };
-    name = fix_whitespace(name.clone());
-    address = fix_whitespace(address.clone());
+pub fn create_customer(customer: Valid<Customer, CreateCustomer>, idempotency_key: IdempotencyKey, actor: RequestActor) -> Idempotent {
+    idempotency_key.run("create_customer", 0, &*customer, || {
+        new_customer(&actor, &customer);
+        Ok(String::new())
+    })
+}
 
-    match validate_name_and_address(name.clone(), address.clone(), "create_customer".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+// Shared with v2, returns the new customer's id
+pub fn new_customer(actor: &Actor, customer: &Customer) -> i64 {
+    let address = match &customer.address {
+        Some(structured) => structured.fields(CreateCustomer::FUNCTION),
+        None => AddressFields::free_text(street_address(customer, CreateCustomer::FUNCTION)),
     };
 
-    customers::create_customer(name, address);
-    Ok(())
+    return customers::create_customer(actor, required(&customer.name), &address);
 }
 
 #[put("/updateAddress", data = "<customer>")]
-pub fn update_address(customer: Json<Customer>) -> Result<String, String> {
-    let mut address = match customer.shipping_address.clone() {
-        Some(a) => a,
-        None => return Err("No shipping_address provided".to_string()),
-    };
-    address = fix_whitespace(address.clone());
-    match validate_alphanumeric_input(address.clone(), "address".to_string(), "update_address".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
+pub fn update_address(customer: Valid<Customer, UpdateAddress>, actor: RequestActor) -> Result<String, String> {
+    let cid = required(&customer.id);
+    let address = street_address(&customer, UpdateAddress::FUNCTION);
 
-    let cid= match customer.id {
-        Some(a) => a,
-        None => return Err("No id provided".to_string()),
-    };
-    match cid > 0 {
-        true => 0,
-        false => return Err("Id numbers must be positive".to_string()),
-    };
-    customers::update_customer_address(cid, address.clone());
+    customers::update_customer_address(&actor.for_customer(Some(cid)), cid, address.clone());
     let success_msg = format!("Successfully updated address for customer ID: {} to {}", cid, address);
     Ok(success_msg)
 }
 
 #[get("/balance", format = "json", data = "<customer>")]
-pub fn get_balance(customer: Json<Customer>) -> Result<String, String> {
-    let mut name = match customer.name.clone() {
-        Some(n) => n,
-        None => return Err("No name provided".to_string()),
-    };
-    let mut address = match customer.shipping_address.clone() {
-        Some(a) => a,
-        None => return Err("No shipping_address provided".to_string()),
-    };
-    name = fix_whitespace(name.clone());
-    address = fix_whitespace(address.clone());
-    
-    match validate_name_and_address(name.clone(), address.clone(), "get_balance".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
-
-    let cid = customers::get_customer_id(name.clone(), address);
+pub fn get_balance(customer: Valid<Customer, FindCustomer>) -> Result<String, String> {
+    let (cid, name) = identify_customer(&customer, FindCustomer::FUNCTION)?;
     let balance = customers::get_customer_balance(cid);
 
     let result_string = format!("Customer {}, with customerID {}, has balance: ${:.2}", name, cid, balance);
@@ -87,101 +133,151 @@ pub fn get_balance(customer: Json<Customer>) -> Result<String, String> {
 
 
 #[put("/updateBalance", data = "<customer>")]
-pub fn update_balance(customer: Json<Customer>) -> Result<String, String> {
-    let mut name = match customer.name.clone() {
-        Some(n) => n,
-        None => return Err("No name provided".to_string()),
-    };
-    let mut address = match customer.shipping_address.clone() {
-        Some(a) => a,
-        None => return Err("No shipping_address provided".to_string()),
-    };
-    name = fix_whitespace(name.clone());
-    address = fix_whitespace(address.clone());
-    
-    match validate_name_and_address(name.clone(), address.clone(), "update_balance".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
+pub fn update_balance(customer: Valid<Customer, UpdateBalance>, idempotency_key: IdempotencyKey, actor: RequestActor) -> Idempotent {
+    let cid = customer.id.unwrap_or(0);
+    idempotency_key.run("update_balance", cid, &*customer, || change_balance(&actor, &customer))
+}
 
-    let balance = match customer.account_balance {
-        Some(p) => p,
-        None => return Err("No account_balance provided".to_string()),
-    };
-    match validate_balance(balance, "update_balance".to_string()) {
-        Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
-    };
-    let cid = get_customer_id(name.clone(), address);
-    customers::update_customer_balance(cid, balance);
+fn change_balance(actor: &Actor, customer: &Customer) -> Result<String, String> {
+    let (cid, name) = identify_customer(customer, UpdateBalance::FUNCTION)?;
+    let balance = required(&customer.account_balance);
+    customers::update_customer_balance(&actor.for_customer(Some(cid)), cid, balance);
 
     let success_msg = format!("Successfully updated balance for customer: {} to ${:.2}", name, balance);
     Ok(success_msg)
 }
 
-fn validate_balance(balance : f64, function : String) -> Result<(), String> {
-    if balance <= 0.00 {
-        error!(target: "file", "Balance of 0.00 given in {}", function);
-        let error_msg = format!("Please give a positive value (>0) for balance");
-        return Err(error_msg);
+fn identify_customer(customer: &Customer, function: &str) -> Result<(i64, String), String> {
+    if let Some(cid) = customer.id {
+        return match customers::get_customer_name(cid) {
+            Some(name) => Ok((cid, name)),
+            None => {
+                error!(target: "file", "Unknown customer id in {}: {}", function, cid);
+                Err(format!("No customer with customerID {}", cid))
+            },
+        };
     }
-    // Adding .'s to integer prices for regex
-    let mut balance_string = balance.to_string();
-    match balance_string.contains(".") {
-        true => (),
-        false => balance_string.push('.'),
-    };
 
+    let name = required(&customer.name);
+    return match customers::get_customer_id(name.clone(), street_address(customer, function)) {
+        Some(cid) => Ok((cid, name)),
+        None => {
+            error!(target: "file", "Unknown customer in {}: {}", function, pii(&name));
+            Err(format!("No customer named {} at that address", name))
+        },
+    };
+}
 
-    // Unwraps the regex error to see if it's a valid regex, decimals no greater than 10000
-    let re = Regex::new(r"^\d{1,4}\.\d{0,2}$").unwrap();
-    let valid = re.is_match(&balance_string);
-    if !valid {
-        error!(target: "file", "Invalid balance in {}: {}", function, balance);
-        let error_msg = "Please input a valid balance of form X.YY: 0 <= X <= 9999, 0 <= Y <= 9".to_string();
-        return Err(error_msg);
-    }
-    else {
-        return Ok(());
-    }
+// Normalized the same way whether stored or looked up, so "1 Main Street" finds "1 Main St"
+fn street_address(customer: &Customer, function: &str) -> String {
+    return normalize_free_text(&required(&customer.shipping_address), "shipping_address", function)
+        .expect("street addresses are checked by the Valid data guard");
 }
 
-// Allows only alphabetic and numeric input for these fields, no weird ones like 💜 or < or /
-fn validate_alphanumeric_input(input : String, field : String, function: String) -> Result<(), String>{
-    if input.is_empty() || input.chars().all(char::is_whitespace) {
-        error!(target: "file", "Empty input given in {}, field: {}", function, field);
-        let error_msg = format!("Please input a valid {}:\n\t Please do not input only empty space.", field);
-        return Err(error_msg);
-    }
-    let valid = input.chars().all(|x| (x.is_alphanumeric() || x.is_whitespace() || x == '.' || x == ',')); // Gets only 'word' characters and spaces
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderHistory {
+    customer_id: i64,
+    page: i64,
+    per_page: i64,
+    total: i64,
+    orders: Vec<OrderHistoryEntry>,
+}
 
-    if !valid {
-        error!(target: "file", "Invalid {} in {}: {}", field, function, input);
-        let error_msg = format!("Please input a valid {}:\n\t Please use only alphabet and numeric values.", field);
-        return Err(error_msg);
-    }
-    else {
-        return Ok(());
-    }
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderHistoryEntry {
+    order_id: i64,
+    book_id: i64,
+    book_title: String,
+    quantity: i64,
+    price_paid: Option<f64>,
+    status: String,
+    created_at: Option<String>,
+    shipped_at: Option<String>,
+    shipping_address: Option<String>,
 }
 
-fn fix_whitespace(input : String) -> String {
-    // Remove spaces at beginning and end of string
-    let temp_string = input.trim().to_string();
-    // Remove extra spaces within
-    let ex_sp_re = Regex::new(r"\s+").unwrap();
-    return ex_sp_re.replace_all(temp_string.as_str(), " ").to_string();
+pub const DEFAULT_PER_PAGE: i64 = 20;
+pub const MAX_PER_PAGE: i64 = 100;
+
+// The offset of the page's first record, or why the page can't be listed
+pub fn page_offset(page: i64, per_page: i64) -> Result<i64, String> {
+    if page <= 0 || per_page <= 0 || per_page > MAX_PER_PAGE {
+        error!(target: "file", "Invalid page {} of {} given", page, per_page);
+        return Err(format!("Please give a positive page and a per_page between 1 and {}", MAX_PER_PAGE));
+    }
+    return (page - 1).checked_mul(per_page).ok_or_else(|| {
+        error!(target: "file", "Page {} of {} is past any offset", page, per_page);
+        format!("Please give a page of at most {}", i64::MAX / per_page)
+    });
 }
 
-// Validates both the names and addresses, returning errors if they fail
-fn validate_name_and_address(name : String, address : String, function : String) -> Result<(), String> {
-    match validate_alphanumeric_input(name.clone(), "name".to_string(), function.clone()) {
+// status is one of shipped or not_shipped, since and until are dates of form YYYY-MM-DD
+#[get("/<cid>/orders?<status>&<since>&<until>&<page>&<per_page>")]
+pub fn get_orders(cid: i64, status: Option<String>, since: Option<String>, until: Option<String>,
+                  page: Option<i64>, per_page: Option<i64>) -> Result<Json<OrderHistory>, Custom<String>> {
+    let bad_request = |message: String| Custom(Status::BadRequest, message);
+    match cid > 0 {
+        true => 0,
+        false => return Err(bad_request("Id numbers must be positive".to_string())),
+    };
+    let shipped = match status.as_deref() {
+        None => None,
+        Some("shipped") => Some(1),
+        Some("not_shipped") => Some(0),
+        Some(s) => {
+            error!(target: "file", "Invalid status in get_orders: {}", s);
+            return Err(bad_request("Please give a status of shipped or not_shipped".to_string()));
+        }
+    };
+    match validate_date(&since, "since", "get_orders") {
         Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+        Err(err_msg) => return Err(bad_request(err_msg)),
     };
-    match validate_alphanumeric_input(address.clone(), "address".to_string(), function) {
+    match validate_date(&until, "until", "get_orders") {
         Ok(()) => 0,
-        Err(err_msg) => return Err(err_msg),
+        Err(err_msg) => return Err(bad_request(err_msg)),
+    };
+    let page = page.unwrap_or(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+    let offset = match page_offset(page, per_page) {
+        Ok(offset) => offset,
+        Err(err_msg) => return Err(bad_request(err_msg)),
+    };
+
+    if !customers::customer_exists(cid) {
+        error!(target: "file", "Order history requested for unknown customer id: {}", cid);
+        return Err(Custom(Status::NotFound, format!("No customer with customerID {}", cid)));
+    }
+
+    let filter = PurchaseOrderFilter { shipped, since, until };
+    let (orders, total) = purchaseOrders::get_customer_purchase_orders(cid, &filter, per_page, offset);
+    let orders = orders.into_iter().map(|o| OrderHistoryEntry {
+        order_id: o.order_id,
+        book_id: o.book_id,
+        book_title: o.book_title,
+        quantity: o.quantity,
+        price_paid: o.price_paid,
+        status: match o.shipped {
+            0 => "not_shipped".to_string(),
+            _ => "shipped".to_string(),
+        },
+        created_at: o.created_at,
+        shipped_at: o.shipped_at,
+        shipping_address: o.shipping_address,
+    }).collect();
+
+    Ok(Json(OrderHistory { customer_id: cid, page, per_page, total, orders }))
+}
+
+fn validate_date(date : &Option<String>, field : &str, function : &str) -> Result<(), String> {
+    let date = match date {
+        Some(d) => d,
+        None => return Ok(()),
     };
+    let re = Regex::new(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])$").unwrap();
+    if !re.is_match(date) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, date);
+        return Err(format!("Please input a valid {} of form YYYY-MM-DD", field));
+    }
     return Ok(());
-}
\ No newline at end of file
+}
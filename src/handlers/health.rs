@@ -0,0 +1,95 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use log::warn;
+
+use crate::db::health;
+use crate::LOG_CONFIG;
+
+// The logger that info!(target: "file", ...) writes through
+const FILE_LOGGER: &str = "file";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Readiness {
+    status: &'static str,
+    checks: Vec<Check>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    name: &'static str,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<(), String>) -> Check {
+        match result {
+            Ok(()) => Check { name, status: "ok", error: None },
+            Err(error) => Check { name, status: "failed", error: Some(error) },
+        }
+    }
+}
+
+// Answers whenever the server can handle a request at all
+#[get("/live")]
+pub fn live() -> Json<Liveness> {
+    Json(Liveness { status: "alive" })
+}
+
+// 503 until the database is usable and logs can be written
+#[get("/ready")]
+pub fn ready() -> (Status, Json<Readiness>) {
+    let mut checks = Vec::new();
+    match health::open_existing() {
+        Ok(connection) => {
+            checks.push(Check::new("database", Ok(())));
+            checks.push(Check::new("migrations", health::check_migrations(&connection)));
+            checks.push(Check::new("schema", health::check_schema(&connection)));
+        },
+        Err(error) => {
+            checks.push(Check::new("database", Err(error)));
+            checks.push(Check::new("migrations", Err("The database could not be opened".to_string())));
+            checks.push(Check::new("schema", Err("The database could not be opened".to_string())));
+        },
+    };
+    checks.push(Check::new("log_file", check_log_file()));
+
+    let failed = checks.iter().filter(|c| c.error.is_some()).map(|c| c.name).collect::<Vec<_>>();
+    if !failed.is_empty() {
+        warn!(target: "file", "Not ready, failed checks: {}", failed.join(", "));
+        return (Status::ServiceUnavailable, Json(Readiness { status: "not_ready", checks }));
+    }
+    (Status::Ok, Json(Readiness { status: "ready", checks }))
+}
+
+fn check_log_file() -> Result<(), String> {
+    let path = log_file()?;
+    return OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .map(|_| ())
+        .map_err(|e| format!("Unable to write {}: {}", path, e));
+}
+
+// The path of the first file appender of the file logger in log4rs.yml
+fn log_file() -> Result<String, String> {
+    let config = fs::read_to_string(LOG_CONFIG).map_err(|e| format!("Unable to read {}: {}", LOG_CONFIG, e))?;
+    let config: serde_yaml::Value = serde_yaml::from_str(&config).map_err(|e| format!("Unable to parse {}: {}", LOG_CONFIG, e))?;
+    let names = config["loggers"][FILE_LOGGER]["appenders"].as_sequence().cloned().unwrap_or_default();
+    return names
+        .iter()
+        .filter_map(|name| name.as_str())
+        .map(|name| &config["appenders"][name])
+        .filter(|appender| matches!(appender["kind"].as_str(), Some("file" | "rolling_file")))
+        .find_map(|appender| appender["path"].as_str().map(str::to_string))
+        .ok_or_else(|| format!("{} has no file appender for the {} logger", LOG_CONFIG, FILE_LOGGER));
+}
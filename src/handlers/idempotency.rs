@@ -0,0 +1,175 @@
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{info, warn, error};
+
+use crate::db::idempotency;
+use crate::rate_limit;
+use crate::valid::GuardMessage;
+
+const HEADER: &str = "Idempotency-Key";
+
+// Read from the `idempotency_ttl` key in Rocket.toml, in seconds
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdempotencyConfig {
+    #[serde(default = "default_ttl")]
+    idempotency_ttl: i64,
+}
+
+fn default_ttl() -> i64 {
+    // 24 hours
+    return 24 * 60 * 60;
+}
+
+impl IdempotencyConfig {
+    pub fn fairing() -> impl Fairing {
+        AdHoc::config::<IdempotencyConfig>()
+    }
+}
+
+// The optional Idempotency-Key header sent with mutating requests
+pub struct IdempotencyKey {
+    key: Option<String>,
+    ttl: i64,
+    // The client's IP, which scopes keys of requests not tied to a customer
+    client: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let ttl = match req.rocket().state::<IdempotencyConfig>() {
+            Some(config) => config.idempotency_ttl,
+            None => default_ttl(),
+        };
+        let client = rate_limit::client_ip(req);
+        let key = match req.headers().get_one(HEADER) {
+            Some(k) => k.trim().to_string(),
+            None => return request::Outcome::Success(IdempotencyKey { key: None, ttl, client }),
+        };
+        // Keys are opaque to us but are echoed in logs, so keep them short and printable
+        if key.is_empty() || key.len() > 255 || !key.chars().all(|c| c.is_ascii_graphic()) {
+            error!(target: "file", "Invalid {} header given: {}", HEADER, key);
+            let error_msg = format!("Please give an {} of 1 to 255 printable characters", HEADER);
+            req.local_cache(|| GuardMessage(Some(error_msg.clone())));
+            return request::Outcome::Failure((Status::BadRequest, error_msg));
+        }
+        request::Outcome::Success(IdempotencyKey { key: Some(key), ttl, client })
+    }
+}
+
+pub enum Idempotent {
+    // The handler ran for this request
+    Fresh(Result<String, String>),
+    // A retry of an earlier request, answered with the stored response
+    Replayed(String),
+    // The key was already used for a different request
+    Conflict(String),
+    // A request with the key is still being handled
+    InProgress(String),
+}
+
+impl<'r> Responder<'r, 'static> for Idempotent {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Idempotent::Fresh(result) => result.respond_to(req),
+            Idempotent::Replayed(body) => Response::build()
+                .header(Header::new("Idempotent-Replayed", "true"))
+                .sized_body(body.len(), Cursor::new(body))
+                .ok(),
+            Idempotent::Conflict(msg) => Response::build()
+                .status(Status::UnprocessableEntity)
+                .sized_body(msg.len(), Cursor::new(msg))
+                .ok(),
+            Idempotent::InProgress(msg) => Response::build()
+                .status(Status::Conflict)
+                .sized_body(msg.len(), Cursor::new(msg))
+                .ok(),
+        }
+    }
+}
+
+impl IdempotencyKey {
+    // Runs the handler once per key and customer, replaying its successful response for retries.
+    // The key is reserved first, so a retry sent while the handler runs is answered with 409 rather than
+    // running it again. cid is 0 for requests not tied to a customer, whose keys are scoped by client IP.
+    // Failed requests are not stored so the client can correct the body and retry with the same key.
+    pub fn run<B, F>(&self, function: &str, cid: i64, body: &B, handler: F) -> Idempotent
+    where
+        B: Serialize,
+        F: FnOnce() -> Result<String, String>,
+    {
+        let key = match &self.key {
+            Some(k) => k,
+            None => return Idempotent::Fresh(handler()),
+        };
+        let scope = match cid {
+            0 => format!("ip:{}", self.client),
+            cid => format!("customer:{}", cid),
+        };
+
+        let now = unix_time();
+        idempotency::purge_expired(now - self.ttl);
+
+        let request_hash = hash_request(function, body);
+        if let Some(stored) = idempotency::reserve(key, &scope, &request_hash, now) {
+            if stored.request_hash != request_hash {
+                warn!(target: "file", "Idempotency key {} reused with a different request in {}", key, function);
+                return Idempotent::Conflict(format!("{} {} was already used for a different request", HEADER, key));
+            }
+            return match stored.response {
+                Some(response) => {
+                    info!(target: "file", "Replaying stored response for idempotency key {} in {}", key, function);
+                    Idempotent::Replayed(response)
+                },
+                None => {
+                    warn!(target: "file", "Idempotency key {} retried while in progress in {}", key, function);
+                    Idempotent::InProgress(format!("A request with {} {} is still in progress, please retry later", HEADER, key))
+                },
+            };
+        }
+
+        let reservation = Reservation { key, scope: &scope };
+        let result = handler();
+        if let Ok(response) = &result {
+            idempotency::store_response(key, &scope, response);
+        }
+        // Dropping the reservation releases the key unless a response was stored
+        drop(reservation);
+        Idempotent::Fresh(result)
+    }
+}
+
+// Releases a reserved key whose handler failed or panicked
+struct Reservation<'a> {
+    key: &'a str,
+    scope: &'a str,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        idempotency::release(self.key, self.scope);
+    }
+}
+
+// The endpoint is part of the hash so one key can't replay a response from another route
+fn hash_request<B: Serialize>(function: &str, body: &B) -> String {
+    let body = json::to_string(body).expect("request bodies should always serialize");
+    let mut hasher = Sha256::new();
+    hasher.update(function.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body.as_bytes());
+    return format!("{:x}", hasher.finalize());
+}
+
+fn unix_time() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).expect("system clock is before 1970").as_secs() as i64;
+}
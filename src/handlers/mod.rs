@@ -1,3 +1,19 @@
+// Rocket's route codegen re-exports each handler, which newer rustc flags as unused
+#[allow(unused_imports)]
+pub mod addresses;
+#[allow(unused_imports)]
+pub mod admin;
+#[allow(unused_imports)]
 pub mod books;
+#[allow(unused_imports)]
 pub mod customers;
+#[allow(unused_imports)]
+pub mod health;
+pub mod idempotency;
+#[allow(unused_imports)]
+pub mod openapi;
+#[allow(unused_imports)]
 pub mod orders;
+pub mod v2;
+#[allow(unused_imports)]
+pub mod versions;
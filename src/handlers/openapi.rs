@@ -0,0 +1,1065 @@
+use rocket::http::Method;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::content::RawHtml;
+use rocket::serde::json::serde_json::{json, Map, Value};
+use rocket::serde::json::Json;
+use rocket::Route;
+
+use crate::validation::{Rule, Rules, Validate};
+use super::addresses::{Address, SaveAddress};
+use crate::catalog::{Book, CreateBook, FindBook};
+use crate::db::books::AVAILABILITY;
+use super::customers::{CreateCustomer, Customer, FindCustomer, UpdateAddress, UpdateBalance};
+use super::orders::{CreateOrder, FindOrder, Order, OrderStatus, ShipOrder};
+use super::v2::books::{BookClassification, ClassifyBook, PriceChange, SchedulePrice, SetStock, Stock};
+use super::v2::customers::{Balance, SetBalance};
+use super::v2::promotions::{Promotion, SavePromotion};
+use super::v2::taxonomy::{Category, SaveCategory};
+use super::versions::Version;
+
+// What the route attributes can't tell us about an endpoint. Paths are as mounted, in Rocket's syntax,
+// with v1 routes listed once by their unversioned path.
+struct Operation {
+    method: Method,
+    path: &'static str,
+    summary: &'static str,
+    // The request body schema with the fields its Rules require, see request_body
+    body: Option<fn() -> Value>,
+    // Query parameters as (name, type, description)
+    query: &'static [(&'static str, &'static str, &'static str)],
+    response: Body,
+    idempotent: bool,
+    // Needs the X-Admin-Token, see handlers/admin.rs
+    admin: bool,
+}
+
+enum Body {
+    Text,
+    Json(fn() -> Value),
+    // 201 with the new record
+    Created(fn() -> Value),
+    Html,
+    // A catalog of books as CSV or JSON Lines, see catalog::CatalogFormat
+    Catalog,
+    // Takes a Catalog and answers with the ImportReport
+    Import,
+    // Takes an ONIX 3.0 message and answers with the OnixReport
+    Onix,
+}
+
+const CATALOG_QUERY: &[(&str, &str, &str)] = &[
+    ("category", "integer", "A category id, its descendants' books are included"),
+    ("tag", "string", "Matched regardless of case"),
+    ("page", "integer", "Starting from 1"),
+    ("per_page", "integer", "Default 20, at most 100"),
+];
+
+const ORDER_HISTORY_QUERY: &[(&str, &str, &str)] = &[
+    ("status", "string", "shipped or not_shipped"),
+    ("since", "string", "Inclusive date of form YYYY-MM-DD"),
+    ("until", "string", "Inclusive date of form YYYY-MM-DD"),
+    ("page", "integer", "Starting from 1"),
+    ("per_page", "integer", "Default 20, at most 100"),
+];
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: Method::Post, path: "/books/new", summary: "Add a book",
+        body: Some(request_body::<Book, CreateBook>), query: &[], response: Body::Text, idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/books/price", summary: "Look up a book's price by title and author",
+        body: Some(request_body::<Book, FindBook>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/books/<bid>/prices", summary: "List a book's prices over time, scheduled ones last",
+        body: None, query: &[], response: Body::Json(price_history_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/books/import", summary: "Add many books at once, or none if any row fails",
+        body: None,
+        query: &[("format", "string", "csv or jsonl"), ("dry_run", "boolean", "Only check the rows, default false")],
+        response: Body::Import, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/books/export", summary: "Every book, by id",
+        body: None, query: &[("format", "string", "csv or jsonl")], response: Body::Catalog, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/books/onix", summary: "Add or update books by ISBN from a publisher's ONIX 3.0 message",
+        body: None, query: &[], response: Body::Onix, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/customers/new", summary: "Add a customer",
+        body: Some(request_body::<Customer, CreateCustomer>), query: &[], response: Body::Text, idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/customers/updateAddress", summary: "Replace a customer's default address with free text",
+        body: Some(request_body::<Customer, UpdateAddress>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/customers/balance", summary: "Look up a customer's balance",
+        body: Some(request_body::<Customer, FindCustomer>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/customers/updateBalance", summary: "Set a customer's balance",
+        body: Some(request_body::<Customer, UpdateBalance>), query: &[], response: Body::Text, idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/customers/<cid>/orders", summary: "List a customer's orders, newest first",
+        body: None, query: ORDER_HISTORY_QUERY, response: Body::Json(order_history_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/customers/<cid>/addresses", summary: "List a customer's saved addresses, default first",
+        body: None, query: &[], response: Body::Json(address_list_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/customers/<cid>/addresses", summary: "Save an address for a customer",
+        body: Some(request_body::<Address, SaveAddress>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/customers/<cid>/addresses/<aid>", summary: "Replace a saved address",
+        body: Some(request_body::<Address, SaveAddress>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/customers/<cid>/addresses/<aid>/default", summary: "Make a saved address the default",
+        body: None, query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Delete, path: "/customers/<cid>/addresses/<aid>", summary: "Remove a saved address",
+        body: None, query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/orders/new", summary: "Order a book, paid from the customer's balance",
+        body: Some(request_body::<Order, CreateOrder>), query: &[], response: Body::Text, idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/orders/shipped", summary: "Check whether a customer's order of a book has shipped",
+        body: Some(request_body::<Order, FindOrder>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/orders/ship", summary: "Mark an order as shipped",
+        body: Some(request_body::<Order, ShipOrder>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/orders/status", summary: "Show an order's status, shipping address, shipping method and taxes",
+        body: Some(request_body::<Order, OrderStatus>), query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/books", summary: "Add a book",
+        body: Some(request_body::<Book, CreateBook>), query: &[], response: Body::Created(|| schema_ref("BookRecord")), idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/books/<bid>", summary: "Get a book",
+        body: None, query: &[], response: Body::Json(|| schema_ref("BookRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/books", summary: "Find a book by title and author",
+        body: None,
+        query: &[("title", "string", "Matched regardless of case"),
+                 ("author", "string", "All the authors, or any one contributor, matched regardless of case")],
+        response: Body::Json(|| schema_ref("BookRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/v2/books/<bid>/stock", summary: "Set the copies on hand of a book",
+        body: Some(request_body::<Stock, SetStock>), query: &[], response: Body::Json(|| schema_ref("BookRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/books/<bid>/prices", summary: "List a book's prices over time, scheduled ones last",
+        body: None, query: &[], response: Body::Json(price_history_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/books/<bid>/prices", summary: "Set a book's price from now, or schedule it for later",
+        body: Some(request_body::<PriceChange, SchedulePrice>), query: &[], response: Body::Created(|| schema_ref("PriceRecord")),
+        idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Delete, path: "/v2/books/<bid>/prices/<pid>", summary: "Cancel a price that hasn't started yet",
+        body: None, query: &[], response: Body::Json(|| schema_ref("PriceRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/books/<bid>/classification", summary: "Get a book's categories and tags",
+        body: None, query: &[], response: Body::Json(|| schema_ref("Classification")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/v2/books/<bid>/classification", summary: "Replace a book's categories, tags or both",
+        body: Some(request_body::<BookClassification, ClassifyBook>), query: &[], response: Body::Json(|| schema_ref("Classification")),
+        idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/books/import", summary: "Add many books at once, or none if any row fails",
+        body: None,
+        query: &[("format", "string", "csv or jsonl"), ("dry_run", "boolean", "Only check the rows, default false")],
+        response: Body::Import, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/books/export", summary: "Every book, by id",
+        body: None, query: &[("format", "string", "csv or jsonl")], response: Body::Catalog, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/books/onix", summary: "Add or update books by ISBN from a publisher's ONIX 3.0 message",
+        body: None, query: &[], response: Body::Onix, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/catalog", summary: "List books, optionally by category and tag, with facet counts",
+        body: None, query: CATALOG_QUERY, response: Body::Json(catalog_page_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/categories", summary: "List the category tree",
+        body: None, query: &[], response: Body::Json(category_tree_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/categories", summary: "Add a category",
+        body: Some(request_body::<Category, SaveCategory>), query: &[], response: Body::Created(|| schema_ref("CategoryRecord")), idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/categories/<cid>", summary: "Get a category",
+        body: None, query: &[], response: Body::Json(|| schema_ref("CategoryRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/v2/categories/<cid>", summary: "Rename a category or move it below another",
+        body: Some(request_body::<Category, SaveCategory>), query: &[], response: Body::Json(|| schema_ref("CategoryRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Delete, path: "/v2/categories/<cid>", summary: "Delete a category without subcategories",
+        body: None, query: &[], response: Body::Json(|| schema_ref("CategoryRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/categories/<cid>/books", summary: "List the books in a category or its descendants, with facet counts",
+        body: None, query: CATALOG_QUERY, response: Body::Json(catalog_page_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/tags", summary: "List tags with the number of books of each",
+        body: None, query: &[], response: Body::Json(tag_list_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/works/<wid>", summary: "Get a work with each of its editions",
+        body: None, query: &[], response: Body::Json(|| schema_ref("WorkRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/series", summary: "List series with the number of works in each",
+        body: None, query: &[], response: Body::Json(|| json!({"type": "array", "items": schema_ref("SeriesRecord")})), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/series/<sid>", summary: "Get a series",
+        body: None, query: &[], response: Body::Json(|| schema_ref("SeriesRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/series/<sid>/works", summary: "List the works of a series in reading order, with their editions",
+        body: None, query: &[], response: Body::Json(|| json!({"type": "array", "items": schema_ref("WorkRecord")})), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/authors", summary: "List authors and other contributors by name",
+        body: None,
+        query: &[("name", "string", "Part of the name, matched regardless of case"), ("page", "integer", "Starting from 1"),
+                 ("per_page", "integer", "Default 20, at most 100")],
+        response: Body::Json(author_page_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/authors/<aid>", summary: "Get an author",
+        body: None, query: &[], response: Body::Json(|| schema_ref("AuthorRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/authors/<aid>/books", summary: "List the books an author contributed to, with their role",
+        body: None, query: &[], response: Body::Json(contributed_books_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/customers", summary: "Add a customer",
+        body: Some(request_body::<Customer, CreateCustomer>), query: &[], response: Body::Created(|| schema_ref("CustomerRecord")), idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/customers/<cid>", summary: "Get a customer",
+        body: None, query: &[], response: Body::Json(|| schema_ref("CustomerRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/v2/customers/<cid>/balance", summary: "Set a customer's balance",
+        body: Some(request_body::<Balance, SetBalance>), query: &[], response: Body::Json(|| schema_ref("CustomerRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/customers/<cid>/addresses", summary: "List a customer's saved addresses, default first",
+        body: None, query: &[], response: Body::Json(address_list_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/customers/<cid>/orders", summary: "List a customer's orders, newest first",
+        body: None, query: ORDER_HISTORY_QUERY, response: Body::Json(order_history_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/orders", summary: "Order a book, paid from the customer's balance",
+        body: Some(request_body::<Order, CreateOrder>), query: &[], response: Body::Created(|| schema_ref("OrderRecord")), idempotent: true, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/orders/quote", summary: "Price an order with the promotions it would get, without placing it",
+        body: Some(request_body::<Order, CreateOrder>), query: &[], response: Body::Json(|| schema_ref("OrderPricing")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/orders/shipping-options",
+        summary: "List the shipping methods an order could use, with their costs and delivery dates, cheapest first",
+        body: Some(request_body::<Order, CreateOrder>), query: &[],
+        response: Body::Json(|| json!({"type": "array", "items": schema_ref("ShippingQuote")})), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/orders/<oid>", summary: "Get an order",
+        body: None, query: &[], response: Body::Json(|| schema_ref("OrderRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/orders/<oid>/ship", summary: "Mark an order as shipped",
+        body: None, query: &[], response: Body::Json(|| schema_ref("OrderRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/promotions", summary: "List promotions, with how many orders used each",
+        body: None, query: &[], response: Body::Json(|| json!({"type": "array", "items": schema_ref("PromotionRecord")})), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Post, path: "/v2/promotions", summary: "Add a promotion, with a code or applied to every eligible order",
+        body: Some(request_body::<Promotion, SavePromotion>), query: &[], response: Body::Created(|| schema_ref("PromotionRecord")),
+        idempotent: true, admin: true,
+    },
+    Operation {
+        method: Method::Get, path: "/v2/promotions/<pid>", summary: "Get a promotion",
+        body: None, query: &[], response: Body::Json(|| schema_ref("PromotionRecord")), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Put, path: "/v2/promotions/<pid>", summary: "Replace a promotion",
+        body: Some(request_body::<Promotion, SavePromotion>), query: &[], response: Body::Json(|| schema_ref("PromotionRecord")),
+        idempotent: false, admin: true,
+    },
+    Operation {
+        method: Method::Delete, path: "/v2/promotions/<pid>", summary: "Delete a promotion no order used, 409 otherwise",
+        body: None, query: &[], response: Body::Json(|| schema_ref("PromotionRecord")), idempotent: false, admin: true,
+    },
+    Operation {
+        method: Method::Get, path: "/versions", summary: "API versions with their deprecation dates and request counts",
+        body: None, query: &[], response: Body::Json(versions_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/health/live", summary: "Whether the server is running",
+        body: None, query: &[], response: Body::Json(|| json!({"type": "object", "properties": {"status": {"type": "string"}}})),
+        idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/health/ready", summary: "Whether the database and log file are usable, 503 if not",
+        body: None, query: &[], response: Body::Json(readiness_schema), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/admin/audit/verify", summary: "Whether the audit log's hash chain is intact, 409 if not",
+        body: None, query: &[], response: Body::Json(audit_verification_schema), idempotent: false, admin: true,
+    },
+    Operation {
+        method: Method::Get, path: "/metrics", summary: "Request counts, latencies and order totals in the Prometheus text format",
+        body: None, query: &[], response: Body::Text, idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/openapi.json", summary: "This document",
+        body: None, query: &[], response: Body::Json(|| json!({"type": "object"})), idempotent: false, admin: false,
+    },
+    Operation {
+        method: Method::Get, path: "/docs", summary: "Browsable documentation of this API",
+        body: None, query: &[], response: Body::Html, idempotent: false, admin: false,
+    },
+];
+
+// The OpenAPI 3 document for the routes as mounted
+pub struct Spec(Value);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Spec {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(Spec(spec(req.rocket().routes())))
+    }
+}
+
+#[get("/openapi.json")]
+pub fn openapi_json(spec: Spec) -> Json<Value> {
+    Json(spec.0)
+}
+
+// RapiDoc pointed at /openapi.json
+#[get("/docs")]
+pub fn docs() -> RawHtml<&'static str> {
+    RawHtml(DOCS_PAGE)
+}
+
+const DOCS_PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>bookshop-rs API</title>
+  <script type="module" src="https://unpkg.com/rapidoc@9.3.4/dist/rapidoc-min.js"></script>
+</head>
+<body>
+  <rapi-doc spec-url="/openapi.json" render-style="read" allow-try="true" show-header="false"></rapi-doc>
+</body>
+</html>
+"#;
+
+// Path and query parameters come from the route itself, everything else from its Operation.
+// Routes without an Operation are still listed, the spec test catches them.
+pub fn spec<'a>(routes: impl Iterator<Item = &'a Route>) -> Value {
+    let mut paths = Map::new();
+    for route in routes {
+        let operation = find_operation(&route.method, route.uri.path());
+        let mut parameters = dynamic_segments(route.uri.path())
+            .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "integer"}}))
+            .collect::<Vec<_>>();
+        for name in dynamic_segments(route.uri.query().unwrap_or("")) {
+            let (kind, description) = operation
+                .and_then(|op| op.query.iter().find(|(n, _, _)| *n == name))
+                .map(|(_, kind, description)| (*kind, *description))
+                .unwrap_or(("string", ""));
+            parameters.push(json!({"name": name, "in": "query", "required": false,
+                                   "description": description, "schema": {"type": kind}}));
+        }
+
+        let mut entry = json!({
+            "operationId": route.name.as_deref().unwrap_or(""),
+            "parameters": parameters,
+            "responses": {
+                "200": {"description": "Success"},
+                "429": {"description": "Too many requests, see the rate_limits in Rocket.toml", "headers": {
+                    "Retry-After": {"description": "Seconds to wait", "schema": {"type": "integer"}},
+                }},
+            },
+        });
+        let version = Version::of(route.uri.path());
+        if version.is_some_and(|v| v != Version::V2) {
+            entry["deprecated"] = json!(true);
+        }
+        if version == Some(Version::V2) {
+            entry["responses"]["4XX"] = json!({"description": "The record doesn't exist or the request can't be carried out",
+                "content": {"application/json": {"schema": schema_ref("ApiError")}}});
+        }
+        if operation.is_some_and(|op| op.admin) {
+            entry["parameters"].as_array_mut().unwrap().push(json!({
+                "name": "X-Admin-Token", "in": "header", "required": true,
+                "description": "The admin_token from Rocket.toml", "schema": {"type": "string"},
+            }));
+            entry["responses"]["401"] = json!({"description": "The X-Admin-Token is missing or wrong"});
+            entry["responses"]["404"] = json!({"description": "No admin_token is configured"});
+        }
+        if let Some(op) = operation {
+            entry["summary"] = json!(op.summary);
+            let (status, content) = match op.response {
+                Body::Text => ("200", json!({"text/plain": {"schema": {"type": "string"}}})),
+                Body::Json(schema) => ("200", json!({"application/json": {"schema": schema()}})),
+                Body::Created(schema) => ("201", json!({"application/json": {"schema": schema()}})),
+                Body::Html => ("200", json!({"text/html": {"schema": {"type": "string"}}})),
+                Body::Catalog => ("200", catalog_content()),
+                Body::Import => ("200", json!({"application/json": {"schema": schema_ref("ImportReport")}})),
+                Body::Onix => ("200", json!({"application/json": {"schema": schema_ref("OnixReport")}})),
+            };
+            if status != "200" {
+                entry["responses"].as_object_mut().unwrap().remove("200");
+            }
+            entry["responses"][status] = json!({"description": "Success", "content": content});
+            if let Some(body) = op.body {
+                entry["requestBody"] = json!({"required": true, "content": {"application/json": {"schema": body()}}});
+                entry["responses"]["400"] = error_response("The body is not JSON");
+                entry["responses"]["422"] = error_response("The body failed validation, or the Idempotency-Key was used for a different body");
+            }
+            if matches!(op.response, Body::Import) {
+                entry["requestBody"] = json!({"required": true, "content": catalog_content()});
+                entry["responses"]["400"] = json!({"description": "No valid format, or the catalog is larger than limits.catalog"});
+                entry["responses"]["422"] = json!({"description": "Some rows failed and nothing was added",
+                    "content": {"application/json": {"schema": schema_ref("ImportReport")}}});
+            }
+            if matches!(op.response, Body::Onix) {
+                entry["requestBody"] = json!({"required": true, "content": {"application/xml": {"schema": {"type": "string"}}}});
+                entry["responses"]["400"] = json!({"description": "Not an ONIX 3.0 message with reference names, or larger than limits.onix"});
+            }
+            if matches!(op.response, Body::Catalog) {
+                entry["responses"]["400"] = json!({"description": "No valid format"});
+            }
+            // Uploads and exports answer 400 with an ApiError in v2, and with text in v1
+            if let (Some(Version::V2), Some(bad_request)) = (version, entry["responses"].get_mut("400")) {
+                bad_request["content"] = json!({"application/json": {"schema": schema_ref("ApiError")}});
+            }
+            if op.idempotent {
+                entry["parameters"].as_array_mut().unwrap().push(json!({
+                    "name": "Idempotency-Key", "in": "header", "required": false,
+                    "description": "Retries with the same key and body replay the first successful response",
+                    "schema": {"type": "string", "maxLength": 255},
+                }));
+                entry["responses"]["409"] = error_response("A request with the same Idempotency-Key is still in progress");
+            }
+        }
+
+        let path = openapi_path(route.uri.path());
+        let methods = paths.entry(path).or_insert_with(|| json!({}));
+        methods[route.method.as_str().to_lowercase()] = entry;
+    }
+
+    return json!({
+        "openapi": "3.0.3",
+        "info": {"title": "bookshop-rs", "version": env!("CARGO_PKG_VERSION")},
+        "paths": paths,
+        "components": {"schemas": {
+            "Book": book_schema(),
+            "Customer": customer_schema(),
+            "Order": order_schema(),
+            "Address": address_schema(),
+            "Balance": balance_schema(),
+            "BookRecord": book_record_schema(),
+            "AuthorRecord": author_record_schema(),
+            "WorkRecord": work_record_schema(),
+            "SeriesRecord": series_record_schema(),
+            "Stock": stock_schema(),
+            "PriceChange": price_change_schema(),
+            "PriceRecord": price_record_schema(),
+            "Category": category_schema(),
+            "CategoryRecord": category_record_schema(),
+            "BookClassification": book_classification_schema(),
+            "Classification": classification_schema(),
+            "CustomerRecord": customer_record_schema(),
+            "OrderRecord": order_record_schema(),
+            "OrderPricing": order_pricing_schema(),
+            "ShippingQuote": shipping_quote_schema(),
+            "Promotion": promotion_schema(),
+            "PromotionRecord": promotion_record_schema(),
+            "ApiError": api_error_schema(),
+            "ValidationErrors": validation_errors_schema(),
+            "ImportReport": import_report_schema(),
+            "OnixReport": onix_report_schema(),
+        }},
+    });
+}
+
+fn find_operation(method: &Method, path: &str) -> Option<&'static Operation> {
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    return OPERATIONS.iter().find(|op| op.method == *method && op.path == path);
+}
+
+fn schema_ref(name: &str) -> Value {
+    return json!({"$ref": format!("#/components/schemas/{}", name)});
+}
+
+// "/customers/<cid>/orders" becomes "/customers/{cid}/orders"
+fn openapi_path(path: &str) -> String {
+    return path.replace('<', "{").replace('>', "}");
+}
+
+fn dynamic_segments(uri: &str) -> impl Iterator<Item = &str> {
+    return uri
+        .split(['/', '&'])
+        .filter_map(|segment| segment.strip_prefix('<'))
+        .map(|segment| segment.trim_end_matches('>').trim_end_matches(".."));
+}
+
+fn error_response(description: &str) -> Value {
+    return json!({"description": description,
+                  "content": {"application/json": {"schema": {"$ref": "#/components/schemas/ValidationErrors"}}}});
+}
+
+// Every field of a request body is optional to serde, which ones are required depends on the endpoint's Rules
+fn request_body<T: Validate, R: Rules<T>>() -> Value {
+    let name = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+    let required = R::FIELDS.iter()
+        .filter(|(_, rules)| rules.iter().any(|rule| matches!(rule, Rule::Required)))
+        .map(|(field, _)| *field)
+        .collect::<Vec<_>>();
+    let schema = schema_ref(name);
+    if required.is_empty() {
+        return schema;
+    }
+    return json!({"allOf": [schema, {"required": required}]});
+}
+
+fn book_schema() -> Value {
+    let names = |role: &str| json!({"type": "string", "description": format!("Each {}, separated by commas", role)});
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer", "minimum": 1},
+        "title": {"type": "string"},
+        "author": names("author"),
+        "editor": names("editor"),
+        "translator": names("translator"),
+        "illustrator": names("illustrator"),
+        "isbn": {"type": "string", "description": "ISBN-13, hyphens are dropped"},
+        "availability": {"type": "string", "enum": AVAILABILITY},
+        "description": {"type": "string"},
+        "series": {"type": "string", "description": "The work's series, kept as it is when left out"},
+        "series_position": {"type": "integer", "minimum": 0},
+        "format": format_schema(),
+        "publisher": {"type": "string"},
+        "publication_date": {"type": "string", "description": "Date of form YYYY-MM-DD"},
+        "stock": {"type": "integer", "minimum": 0, "description": "Copies on hand, not counted when left out"},
+        "price": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99},
+    }});
+}
+
+fn customer_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer", "minimum": 1},
+        "name": {"type": "string"},
+        "shipping_address": {"type": "string", "description": "Free text, stored as the first line of the default address"},
+        "address": {"$ref": "#/components/schemas/Address"},
+        "account_balance": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99},
+    }});
+}
+
+fn order_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "order_id": {"type": "integer", "minimum": 1},
+        "customer_id": {"type": "integer", "minimum": 1},
+        "book_id": {"type": "integer", "minimum": 1},
+        "shipped": {"type": "integer", "enum": [0, 1]},
+        "address_id": {"type": "integer", "minimum": 1, "description": "The customer's default address if not given"},
+        "quantity": {"type": "integer", "minimum": 1, "description": "Copies of the book, 1 if not given"},
+        "coupon_code": {"type": "string", "description": "A promotion's code, regardless of case. The order is refused if it doesn't apply."},
+        "shipping_method": {"type": "string", "description": "A method's key in the shipping rules, such as standard, express or pickup. \
+                                                             Their default_method if not given."},
+    }});
+}
+
+fn address_schema() -> Value {
+    return json!({"type": "object", "required": ["line1", "city", "country"], "properties": {
+        "line1": {"type": "string"},
+        "line2": {"type": "string"},
+        "city": {"type": "string"},
+        "region": {"type": "string", "description": "State or province code, required for US and CA"},
+        "postal_code": {"type": "string", "description": "Required for US, CA, GB and DE"},
+        "country": {"type": "string", "description": "Two letter country code, or a name such as United Kingdom"},
+        "is_default": {"type": "boolean"},
+    }});
+}
+
+fn address_list_schema() -> Value {
+    let mut saved = address_schema();
+    saved["properties"]["id"] = json!({"type": "integer"});
+    saved["properties"]["customer_id"] = json!({"type": "integer"});
+    return json!({"type": "array", "items": saved});
+}
+
+fn order_history_schema() -> Value {
+    let nullable_string = json!({"type": "string", "nullable": true});
+    return json!({"type": "object", "properties": {
+        "customer_id": {"type": "integer"},
+        "page": {"type": "integer"},
+        "per_page": {"type": "integer"},
+        "total": {"type": "integer"},
+        "orders": {"type": "array", "items": {"type": "object", "properties": {
+            "order_id": {"type": "integer"},
+            "book_id": {"type": "integer"},
+            "book_title": {"type": "string"},
+            "quantity": {"type": "integer"},
+            "price_paid": {"type": "number", "nullable": true},
+            "status": {"type": "string", "enum": ["shipped", "not_shipped"]},
+            "created_at": nullable_string,
+            "shipped_at": nullable_string,
+            "shipping_address": nullable_string,
+        }}},
+    }});
+}
+
+fn balance_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "account_balance": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99},
+    }});
+}
+
+fn book_record_schema() -> Value {
+    let nullable_string = json!({"type": "string", "nullable": true});
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "title": {"type": "string"},
+        "author": {"type": "string"},
+        "price": {"type": "number"},
+        "editor": nullable_string,
+        "translator": nullable_string,
+        "illustrator": nullable_string,
+        "isbn": nullable_string,
+        "availability": {"type": "string", "nullable": true,
+                         "enum": ["available", "not_yet_available", "temporarily_unavailable", "unavailable", null]},
+        "description": nullable_string,
+        "work_id": {"type": "integer"},
+        "series": nullable_string,
+        "series_position": {"type": "integer", "nullable": true},
+        "format": format_schema(),
+        "publisher": nullable_string,
+        "publication_date": nullable_string,
+        "stock": {"type": "integer", "nullable": true, "description": "Copies on hand, null when they aren't counted"},
+    }});
+}
+
+fn format_schema() -> Value {
+    return json!({"type": "string", "nullable": true, "enum": ["hardcover", "paperback", "ebook", "audiobook", null]});
+}
+
+fn stock_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "stock": {"type": "integer", "minimum": 0, "description": "Left out to stop counting the copies"},
+    }});
+}
+
+fn price_change_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "price": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99},
+        "effective_from": {"type": "string",
+                           "description": "UTC time of form YYYY-MM-DD HH:MM:SS, or a date for its start. Now when left out, never earlier."},
+    }});
+}
+
+fn price_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "book_id": {"type": "integer"},
+        "price": {"type": "number"},
+        "effective_from": {"type": "string", "description": "UTC time of form YYYY-MM-DD HH:MM:SS"},
+        "effective_to": {"type": "string", "nullable": true, "description": "When the next price starts, null for the latest one"},
+    }});
+}
+
+fn price_history_schema() -> Value {
+    return json!({"type": "array", "items": schema_ref("PriceRecord")});
+}
+
+fn work_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "title": {"type": "string"},
+        "author": {"type": "string"},
+        "series": {"type": "string", "nullable": true},
+        "series_position": {"type": "integer", "nullable": true},
+        "editions": {"type": "array", "items": schema_ref("BookRecord")},
+    }});
+}
+
+fn series_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "name": {"type": "string"},
+        "works": {"type": "integer"},
+    }});
+}
+
+// CSV has a header row naming the BookRecord fields, ids are ignored on import
+fn catalog_content() -> Value {
+    let schema = json!({"type": "string"});
+    return json!({"text/csv": {"schema": schema}, "application/x-ndjson": {"schema": schema_ref("Book")}});
+}
+
+fn author_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "name": {"type": "string"},
+        "books": {"type": "integer", "description": "Books contributed to in any role"},
+    }});
+}
+
+fn author_page_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "page": {"type": "integer"},
+        "per_page": {"type": "integer"},
+        "total": {"type": "integer"},
+        "authors": {"type": "array", "items": schema_ref("AuthorRecord")},
+    }});
+}
+
+fn contributed_books_schema() -> Value {
+    return json!({"type": "array", "items": {"type": "object", "properties": {
+        "role": {"type": "string", "enum": ["author", "editor", "translator", "illustrator"]},
+        "book": schema_ref("BookRecord"),
+    }}});
+}
+
+fn category_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "name": {"type": "string"},
+        "parent_id": {"type": "integer", "minimum": 1, "description": "Left out for a top-level category"},
+    }});
+}
+
+fn category_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "name": {"type": "string"},
+        "parent_id": {"type": "integer", "nullable": true},
+        "books": {"type": "integer", "description": "Books in the category or any of its descendants"},
+    }});
+}
+
+fn category_tree_schema() -> Value {
+    return json!({"type": "array", "items": {"allOf": [schema_ref("CategoryRecord"), {"type": "object", "properties": {
+        "children": {"type": "array", "items": {"description": "The same as the parent, recursively"}},
+    }}]}});
+}
+
+fn book_classification_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "categories": {"type": "array", "items": {"type": "integer", "minimum": 1}, "description": "Left as it is when not given"},
+        "tags": {"type": "array", "items": {"type": "string"}, "description": "Left as it is when not given"},
+    }});
+}
+
+fn classification_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "categories": {"type": "array", "items": schema_ref("CategoryRecord")},
+        "tags": {"type": "array", "items": {"type": "string"}},
+    }});
+}
+
+fn tag_list_schema() -> Value {
+    return json!({"type": "array", "items": {"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "name": {"type": "string"},
+        "books": {"type": "integer"},
+    }}});
+}
+
+fn catalog_page_schema() -> Value {
+    let facet = |properties: Value| json!({"type": "array", "items": {"type": "object", "properties": properties}});
+    return json!({"type": "object", "properties": {
+        "page": {"type": "integer"},
+        "per_page": {"type": "integer"},
+        "total": {"type": "integer"},
+        "books": {"type": "array", "items": schema_ref("BookRecord")},
+        "facets": {"type": "object", "properties": {
+            "categories": facet(json!({"id": {"type": "integer"}, "name": {"type": "string"}, "count": {"type": "integer"}})),
+            "tags": facet(json!({"name": {"type": "string"}, "count": {"type": "integer"}})),
+        }, "description": "Matching books in each subcategory of the category, or each top-level category, and with each tag"},
+    }});
+}
+
+fn customer_record_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "name": {"type": "string"},
+        "account_balance": {"type": "number"},
+        "shipping_address": {"type": "string", "description": "The default address, formatted"},
+    }});
+}
+
+fn order_record_schema() -> Value {
+    let nullable_string = json!({"type": "string", "nullable": true});
+    return json!({"type": "object", "properties": {
+        "id": {"type": "integer"},
+        "customer_id": {"type": "integer"},
+        "book_id": {"type": "integer"},
+        "book_title": {"type": "string"},
+        "quantity": {"type": "integer"},
+        "subtotal": {"type": "number", "nullable": true, "description": "Before discounts"},
+        "discounts": {"type": "array", "items": discount_schema()},
+        "shipping": {"allOf": [schema_ref("ShippingQuote")], "nullable": true, "description": "Null without shipping rules"},
+        "taxes": {"type": "array", "items": tax_line_schema()},
+        "price_paid": {"type": "number", "nullable": true, "description": "With the taxes not included in the price"},
+        "status": {"type": "string", "enum": ["shipped", "not_shipped"]},
+        "created_at": nullable_string,
+        "shipped_at": nullable_string,
+        "shipping_address": nullable_string,
+    }});
+}
+
+fn discount_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "promotion_id": {"type": "integer"},
+        "name": {"type": "string"},
+        "code": {"type": "string", "nullable": true},
+        "amount": {"type": "number"},
+    }});
+}
+
+fn tax_line_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "line": {"type": "string", "enum": ["book", "shipping"]},
+        "jurisdiction": {"type": "string", "description": "The destination's country code, with the region's as in US-NY if its rate applied"},
+        "rate": {"type": "number", "description": "A percentage"},
+        "taxable": {"type": "number", "description": "The copies' price after discounts, or the shipping cost"},
+        "amount": {"type": "number"},
+        "included": {"type": "boolean", "description": "Whether the amount was part of the price rather than added to it"},
+    }});
+}
+
+fn shipping_quote_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "method": {"type": "string"},
+        "name": {"type": "string"},
+        "cost": {"type": "number"},
+        "earliest_delivery": {"type": "string", "description": "Of form YYYY-MM-DD"},
+        "latest_delivery": {"type": "string", "description": "Of form YYYY-MM-DD"},
+    }});
+}
+
+fn order_pricing_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "book_id": {"type": "integer"},
+        "quantity": {"type": "integer"},
+        "unit_price": {"type": "number"},
+        "subtotal": {"type": "number"},
+        "discounts": {"type": "array", "items": discount_schema()},
+        "shipping": {"allOf": [schema_ref("ShippingQuote")], "nullable": true, "description": "Null without shipping rules"},
+        "taxes": {"type": "array", "items": tax_line_schema()},
+        "total": {"type": "number", "description": "With the taxes not included in the price"},
+    }});
+}
+
+fn promotion_schema() -> Value {
+    let time = "UTC time of form YYYY-MM-DD HH:MM:SS, or a date for its start";
+    return json!({"type": "object", "properties": {
+        "name": {"type": "string"},
+        "code": {"type": "string", "description": "Left out for a promotion every eligible order gets"},
+        "kind": {"type": "string", "enum": ["percentage", "fixed", "bundle"]},
+        "percent": {"type": "number", "exclusiveMinimum": 0, "maximum": 100, "description": "Required for percentage"},
+        "amount": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99, "description": "Required for fixed"},
+        "buy_quantity": {"type": "integer", "minimum": 1, "description": "Required for bundle"},
+        "free_quantity": {"type": "integer", "minimum": 1, "description": "Required for bundle, free of every buy_quantity + free_quantity copies"},
+        "minimum_subtotal": {"type": "number", "exclusiveMinimum": 0, "maximum": 9999.99},
+        "book_id": {"type": "integer", "minimum": 1},
+        "author_id": {"type": "integer", "minimum": 1},
+        "category_id": {"type": "integer", "minimum": 1, "description": "Including the categories below it"},
+        "customer_id": {"type": "integer", "minimum": 1},
+        "usage_limit": {"type": "integer", "minimum": 0, "description": "Orders in all"},
+        "per_customer_limit": {"type": "integer", "minimum": 0, "description": "Orders by each customer"},
+        "starts_at": {"type": "string", "description": time},
+        "ends_at": {"type": "string", "description": time},
+    }});
+}
+
+fn promotion_record_schema() -> Value {
+    let mut record = promotion_schema();
+    record["properties"]["id"] = json!({"type": "integer"});
+    record["properties"]["uses"] = json!({"type": "integer", "description": "Orders that got the promotion and weren't cancelled"});
+    return record;
+}
+
+fn versions_schema() -> Value {
+    return json!({"type": "array", "items": {"type": "object", "properties": {
+        "version": {"type": "string", "enum": ["unversioned", "v1", "v2"]},
+        "status": {"type": "string", "enum": ["current", "supported", "deprecated"]},
+        "deprecated": {"type": "string", "nullable": true, "description": "Date of form YYYY-MM-DD"},
+        "sunset": {"type": "string", "nullable": true, "description": "Date of form YYYY-MM-DD"},
+        "requests": {"type": "integer", "description": "Since the server started"},
+    }}});
+}
+
+fn readiness_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "status": {"type": "string", "enum": ["ready", "not_ready"]},
+        "checks": {"type": "array", "items": {"type": "object", "properties": {
+            "name": {"type": "string", "enum": ["database", "migrations", "schema", "log_file"]},
+            "status": {"type": "string", "enum": ["ok", "failed"]},
+            "error": {"type": "string"},
+        }}},
+    }});
+}
+
+fn audit_verification_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "valid": {"type": "boolean"},
+        "events": {"type": "integer", "description": "Events checked, up to and including the first invalid one"},
+        "first_invalid_id": {"type": "integer", "nullable": true},
+        "reason": {"type": "string", "nullable": true},
+    }});
+}
+
+fn import_report_schema() -> Value {
+    let errors = validation_errors_schema();
+    return json!({"type": "object", "properties": {
+        "dry_run": {"type": "boolean"},
+        "rows": {"type": "integer"},
+        "imported": {"type": "integer", "description": "Books added, or that a dry run would add, 0 if any row failed"},
+        "errors": {"type": "array", "items": {"type": "object", "properties": {
+            "line": {"type": "integer", "description": "Where the row starts"},
+            "errors": errors["properties"]["errors"],
+        }}},
+    }});
+}
+
+fn onix_report_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "products": {"type": "integer"},
+        "created": {"type": "integer"},
+        "updated": {"type": "integer"},
+        "unchanged": {"type": "integer"},
+        "skipped": {"type": "array", "items": {"type": "object", "properties": {
+            "record_reference": {"type": "string", "nullable": true},
+            "isbn": {"type": "string", "nullable": true},
+            "reason": {"type": "string"},
+        }}},
+    }});
+}
+
+fn api_error_schema() -> Value {
+    return json!({"type": "object", "properties": {
+        "status": {"type": "integer"},
+        "error": {"type": "string"},
+    }});
+}
+
+fn validation_errors_schema() -> Value {
+    return json!({"type": "object", "properties": {"errors": {"type": "array", "items": {"type": "object", "properties": {
+        "field": {"type": "string"},
+        "code": {"type": "string"},
+        "message": {"type": "string"},
+    }}}}});
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::serde_json;
+    use serde::Serialize;
+    use std::collections::BTreeSet;
+
+    fn mounted_routes() -> Vec<Route> {
+        return crate::app().routes().cloned().collect();
+    }
+
+    #[test]
+    fn every_mounted_route_is_documented() {
+        let undocumented = mounted_routes().iter()
+            .filter(|route| find_operation(&route.method, route.uri.path()).is_none())
+            .map(|route| format!("{} {}", route.method, route.uri.path()))
+            .collect::<Vec<_>>();
+        assert!(undocumented.is_empty(), "add an Operation for: {:?}", undocumented);
+    }
+
+    #[test]
+    fn every_documented_route_is_mounted() {
+        let routes = mounted_routes();
+        let unmounted = OPERATIONS.iter()
+            .filter(|op| !routes.iter().any(|route| route.method == op.method && route.uri.path() == op.path))
+            .map(|op| format!("{} {}", op.method, op.path))
+            .collect::<Vec<_>>();
+        assert!(unmounted.is_empty(), "remove the Operation for: {:?}", unmounted);
+    }
+
+    #[test]
+    fn spec_lists_every_route_with_its_parameters() {
+        let routes = mounted_routes();
+        let spec = spec(routes.iter());
+        for route in &routes {
+            let operation = &spec["paths"][openapi_path(route.uri.path())][route.method.as_str().to_lowercase()];
+            assert!(operation.is_object(), "{} {} missing from spec", route.method, route.uri);
+            let documented = operation["parameters"].as_array().unwrap().iter()
+                .filter(|p| p["in"] != "header")
+                .map(|p| p["name"].as_str().unwrap().to_string())
+                .collect::<BTreeSet<_>>();
+            let declared = dynamic_segments(route.uri.path()).chain(dynamic_segments(route.uri.query().unwrap_or("")))
+                .map(str::to_string)
+                .collect::<BTreeSet<_>>();
+            assert_eq!(documented, declared, "parameters of {} {}", route.method, route.uri);
+        }
+    }
+
+    fn assert_fields_match<T: Serialize + Default>(name: &str) {
+        let spec = spec(std::iter::empty());
+        let schema = spec["components"]["schemas"][name]["properties"].as_object().unwrap();
+        let documented = schema.keys().cloned().collect::<BTreeSet<_>>();
+        let actual = serde_json::to_value(T::default()).unwrap().as_object().unwrap().keys().cloned().collect::<BTreeSet<_>>();
+        assert_eq!(documented, actual, "fields of {}", name);
+    }
+
+    #[test]
+    fn schemas_match_request_structs() {
+        assert_fields_match::<Book>("Book");
+        assert_fields_match::<Customer>("Customer");
+        assert_fields_match::<Order>("Order");
+        assert_fields_match::<Address>("Address");
+        assert_fields_match::<Balance>("Balance");
+        assert_fields_match::<Category>("Category");
+        assert_fields_match::<BookClassification>("BookClassification");
+        assert_fields_match::<Stock>("Stock");
+        assert_fields_match::<PriceChange>("PriceChange");
+        assert_fields_match::<Promotion>("Promotion");
+    }
+}
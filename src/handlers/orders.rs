@@ -1,77 +1,237 @@
 use log::warn;
-use rocket::serde::json::Json;
+use rocket::State;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-use crate::db::{customers, purchaseOrders, books};
+use crate::db::{addresses, customers, purchaseOrders, books};
+use crate::db::addresses::CustomerAddress;
+use crate::db::purchaseOrders::PurchaseRejected;
+use crate::db::audit::Actor;
+use crate::db::promotions::{self, CouponRejected, OrderPricing};
+use crate::metrics;
+use crate::request_id::RequestActor;
+use crate::shipping::{ShippingQuote, ShippingRejected, ShippingRules};
+use crate::tax::{TaxLine, TaxRules};
+use crate::valid::Valid;
+use crate::validation::{required, FieldError, Rule, Rules, Validate, Value};
+use super::idempotency::{Idempotent, IdempotencyKey};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Order {
-    order_id: Option<i64>,
-    customer_id: Option<i64>,
-    book_id: Option<i64>,
-    shipped: Option<i64>,
+    pub order_id: Option<i64>,
+    pub customer_id: Option<i64>,
+    pub book_id: Option<i64>,
+    pub shipped: Option<i64>,
+    // Which of the customer's saved addresses to ship to, their default if not given
+    pub address_id: Option<i64>,
+    // Copies of the book, 1 if not given
+    pub quantity: Option<i64>,
+    // A promotion's code, matched regardless of case, see promotions::price_order
+    pub coupon_code: Option<String>,
+    // A method's key in the shipping rules, their default_method if not given
+    pub shipping_method: Option<String>,
+}
+
+impl Validate for Order {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "order_id" => Some(Value::Id(self.order_id)),
+            "customer_id" => Some(Value::Id(self.customer_id)),
+            "book_id" => Some(Value::Id(self.book_id)),
+            "address_id" => Some(Value::Id(self.address_id)),
+            "quantity" => Some(Value::Count(self.quantity)),
+            "coupon_code" => Some(Value::Text(&self.coupon_code)),
+            "shipping_method" => Some(Value::Text(&self.shipping_method)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        if let Some(code) = &mut self.coupon_code {
+            *code = code.trim().to_uppercase();
+        }
+        if let Some(method) = &mut self.shipping_method {
+            *method = method.trim().to_lowercase();
+        }
+    }
+
+    fn check(&self, prefix: &str, _function: &str) -> Vec<FieldError> {
+        if self.quantity == Some(0) {
+            return vec![FieldError::new(&format!("{}quantity", prefix), "not_positive", "Please order at least one copy")];
+        }
+        return Vec::new();
+    }
+
+    fn customer_id(&self) -> Option<i64> {
+        self.customer_id
+    }
+}
+
+pub struct CreateOrder;
+impl Rules<Order> for CreateOrder {
+    const FUNCTION: &'static str = "create_order";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("customer_id", &[Rule::Required, Rule::PositiveId]),
+        ("book_id", &[Rule::Required, Rule::PositiveId]),
+        ("address_id", &[Rule::PositiveId]),
+        ("quantity", &[Rule::Count]),
+        ("coupon_code", &[Rule::Alphanumeric]),
+        ("shipping_method", &[Rule::Alphanumeric]),
+    ];
+}
+
+pub struct FindOrder;
+impl Rules<Order> for FindOrder {
+    const FUNCTION: &'static str = "get_shipped";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("customer_id", &[Rule::Required, Rule::PositiveId]),
+        ("book_id", &[Rule::Required, Rule::PositiveId]),
+    ];
+}
+
+pub struct ShipOrder;
+impl Rules<Order> for ShipOrder {
+    const FUNCTION: &'static str = "ship_order";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("order_id", &[Rule::Required, Rule::PositiveId]),
+    ];
+}
+
+pub struct OrderStatus;
+impl Rules<Order> for OrderStatus {
+    const FUNCTION: &'static str = "get_status";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("order_id", &[Rule::Required, Rule::PositiveId]),
+        ("customer_id", &[Rule::Required, Rule::PositiveId]),
+        ("book_id", &[Rule::Required, Rule::PositiveId]),
+    ];
 }
 
 #[post("/new", data = "<order>")]
-pub fn create_order(order: Json<Order>) -> Result<String, String> {
-    let cid = match order.customer_id {
-        Some(c) => c,
-        None => return Err("No customer_id provided".to_string()),
-    };
-    match cid > 0 {
-        true => 0,
-        false => return Err("Customer Id must be positive".to_string()),
-    };
-    let bid = match order.book_id {
-        Some(b) => b,
-        None => return Err("No book_id provided".to_string()),
-    };
-    match bid > 0 {
-        true => 0,
-        false => return Err("Book Id must be positive".to_string()),
+pub fn create_order(order: Valid<Order, CreateOrder>, idempotency_key: IdempotencyKey, shipping_rules: &State<ShippingRules>,
+                    tax_rules: &State<TaxRules>, actor: RequestActor) -> Idempotent {
+    let cid = order.customer_id.unwrap_or(0);
+    let actor = actor.for_customer(order.customer_id);
+    idempotency_key.run("create_order", cid, &*order, || new_order(&actor, &order, shipping_rules, tax_rules))
+}
+
+fn new_order(actor: &Actor, order: &Order, shipping_rules: &ShippingRules, tax_rules: &TaxRules) -> Result<String, String> {
+    let cid = required(&order.customer_id);
+    let (oid, pricing) = match place_order(actor, order, shipping_rules, tax_rules) {
+        Ok(placed) => placed,
+        Err(rejected) => return Err(rejected.to_string()),
     };
-    let balance = customers::get_customer_balance(cid);
-    let price = books::get_book_price(bid);
+    let mut success_msg = format!("Successfully created order for Customer id: {}\n\t Your orderId is {}", cid, oid);
+    for discount in &pricing.discounts {
+        success_msg.push_str(&format!("\n\t {}: -${:.2}", discount.name, discount.amount));
+    }
+    if let Some(shipping) = &pricing.shipping {
+        success_msg.push_str(&format!("\n\t {}", shipping_message(shipping)));
+    }
+    for tax in &pricing.taxes {
+        success_msg.push_str(&format!("\n\t {}", tax_message(tax)));
+    }
+    if !pricing.discounts.is_empty() || pricing.shipping.is_some() || !pricing.taxes.is_empty() {
+        success_msg.push_str(&format!("\n\t You paid ${:.2}", pricing.total));
+    }
+    Ok(success_msg)
+}
+
+pub enum OrderRejected {
+    UnknownBook(i64),
+    NoAddress(i64),
+    InsufficientFunds { balance: f64, price: f64 },
+    OutOfStock(i64),
+    Coupon(CouponRejected),
+    Shipping(ShippingRejected),
+}
 
-    match balance - price >= 0.0 {
-        true => (),
-        false => warn!(target: "file", "Insufficient funds for cid {}: Has ${:.2} but price is {:.2}", cid, balance, price),
+impl fmt::Display for OrderRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderRejected::UnknownBook(bid) => write!(f, "No book with bookId {}", bid),
+            OrderRejected::NoAddress(cid) => write!(f, "No shipping address found for customer ID {}", cid),
+            OrderRejected::InsufficientFunds { balance, price } =>
+                write!(f, "Insufficient funds. You have ${:.2}, the price of the order is ${:.2}", balance, price),
+            OrderRejected::OutOfStock(bid) => write!(f, "Book ID {} is out of stock", bid),
+            OrderRejected::Coupon(rejected) => write!(f, "{}", rejected),
+            OrderRejected::Shipping(rejected) => write!(f, "{}", rejected),
+        }
     }
-    match balance - price >= 0.0 {
-        true => 0,
-        false => return Err(format!("Insufficient funds. You have ${:.2}, the price of the book is ${:.2}", balance, price)),
+}
+
+// Where the order ships, the given address or the customer's default
+pub fn destination(order: &Order) -> Result<CustomerAddress, OrderRejected> {
+    let cid = required(&order.customer_id);
+    let address = match order.address_id {
+        Some(aid) => addresses::get_address(cid, aid),
+        None => addresses::get_default_address(cid),
     };
-    customers::update_customer_balance(cid, balance-price);
+    return address.ok_or(OrderRejected::NoAddress(cid));
+}
 
-    let oid = purchaseOrders::create_purchase_order(cid, bid);
-    let success_msg = format!("Successfully created order for Customer id: {}\n\t Your orderId is {}", cid, oid);
-    Ok(success_msg)
+fn discounted(order: &Order) -> Result<OrderPricing, OrderRejected> {
+    let bid = required(&order.book_id);
+    let quantity = order.quantity.unwrap_or(1);
+    let unit_price = books::get_book_price(bid).ok_or(OrderRejected::UnknownBook(bid))?;
+    return promotions::price_order(required(&order.customer_id), bid, quantity, unit_price, order.coupon_code.as_deref())
+        .map_err(OrderRejected::Coupon);
+}
+
+// What the order would come to with the promotions that apply, see promotions::price_order,
+// and the shipping and tax for where it ships, see ShippingRules::apply and TaxRules::apply
+pub fn price_order(order: &Order, destination: &CustomerAddress, shipping_rules: &ShippingRules, tax_rules: &TaxRules)
+                   -> Result<OrderPricing, OrderRejected> {
+    let mut pricing = discounted(order)?;
+    shipping_rules.apply(&mut pricing, order.shipping_method.as_deref(), &destination.fields).map_err(OrderRejected::Shipping)?;
+    let format = books::get_book(pricing.book_id).and_then(|book| book.format);
+    tax_rules.apply(&mut pricing, &destination.fields, format);
+    return Ok(pricing);
+}
+
+// The methods the order could ship by, before tax, see ShippingRules::options
+pub fn shipping_options(order: &Order, destination: &CustomerAddress, shipping_rules: &ShippingRules)
+                        -> Result<Vec<ShippingQuote>, OrderRejected> {
+    let pricing = discounted(order)?;
+    return Ok(shipping_rules.options(&destination.fields, pricing.quantity, pricing.total));
+}
+
+// Shared with v2, charges the customer and returns the new order's id with what it cost
+pub fn place_order(actor: &Actor, order: &Order, shipping_rules: &ShippingRules, tax_rules: &TaxRules)
+                   -> Result<(i64, OrderPricing), OrderRejected> {
+    let cid = required(&order.customer_id);
+    let bid = required(&order.book_id);
+    let address = destination(order)?;
+    let pricing = price_order(order, &address, shipping_rules, tax_rules)?;
+    let price = pricing.total;
+
+    let oid = purchaseOrders::create_purchase_order(actor, cid, &pricing, &address).map_err(|rejected| match rejected {
+        PurchaseRejected::InsufficientFunds(balance) => {
+            warn!(target: "file", "Insufficient funds for cid {}: Has ${:.2} but price is {:.2}", cid, balance, price);
+            metrics::record_insufficient_funds();
+            OrderRejected::InsufficientFunds { balance, price }
+        },
+        PurchaseRejected::OutOfStock => {
+            warn!(target: "file", "Book id {} is out of stock for cid {}", bid, cid);
+            OrderRejected::OutOfStock(bid)
+        },
+        PurchaseRejected::Coupon(rejected) => OrderRejected::Coupon(rejected),
+    })?;
+    metrics::record_order(price);
+    return Ok((oid, pricing));
 }
 
 #[get("/shipped", format = "json", data = "<order>")]
-pub fn get_shipped(order: Json<Order>) -> Result<String, String> {
-    let cid = match order.customer_id {
-        Some(c) => c,
-        None => return Err("No customer_id provided".to_string()),
-    };
-    match cid > 0 {
-        true => 0,
-        false => return Err("Customer Id must be positive".to_string()),
-    };
-    let bid = match order.book_id {
-        Some(b) => b,
-        None => return Err("No book_id provided".to_string()),
-    };
-    match bid > 0 {
-        true => 0,
-        false => return Err("Book Id must be positive".to_string()),
-    };
+pub fn get_shipped(order: Valid<Order, FindOrder>) -> Result<String, String> {
+    let cid = required(&order.customer_id);
+    let bid = required(&order.book_id);
 
     let oid = purchaseOrders::get_purchase_order_id(cid, bid);
     let shipped = purchaseOrders::is_po_shipped(oid);
     let shipped_status = match shipped {
-        0 => "Not Shipped".to_string(),
-        1 => "Shipped".to_string(),
+        None => return Err(no_such_order(oid)),
+        Some(0) => "Not Shipped".to_string(),
+        Some(1) => "Shipped".to_string(),
         _ => return Err("Invalid shipped status somehow".to_string()),
     };
 
@@ -80,64 +240,52 @@ pub fn get_shipped(order: Json<Order>) -> Result<String, String> {
 }
 
 #[put("/ship", data = "<order>")]
-pub fn ship_order(order: Json<Order>) -> Result<String, String> {
-    let oid = match order.order_id.clone() {
-        Some(o) => o,
-        None => return Err("No order_id provided".to_string()),
-    };
-    match oid > 0 {
-        true => 0,
-        false => return Err("Order Id must be positive".to_string()),
-    };
+pub fn ship_order(order: Valid<Order, ShipOrder>, actor: RequestActor) -> Result<String, String> {
+    let oid = required(&order.order_id);
 
-    purchaseOrders::ship_po(oid);
+    purchaseOrders::ship_po(&actor.for_customer(order.customer_id), oid);
     let success_msg = format!("Successfully shipped your Order ID: {}!", oid);
     Ok(success_msg)
 }
 
 #[get("/status", format = "json", data = "<order>")]
-pub fn get_status(order: Json<Order>) -> Result<String, String> {
-    // Removed clone for ints, not necessary
-    let oid = match order.order_id{
-        Some(o) => o,
-        None => return Err("No order_id provided".to_string()),
-    };
-    match oid > 0 {
-        true => 0,
-        false => return Err("Order Id must be positive".to_string()),
-    };
-
-    let cid = match order.customer_id.clone() {
-        Some(c) => c,
-        None => return Err("No customer_id provided".to_string()),
-    };
-    match cid > 0 {
-        true => 0,
-        false => return Err("Customer Id must be positive".to_string()),
-    };
+pub fn get_status(order: Valid<Order, OrderStatus>) -> Result<String, String> {
+    let oid = required(&order.order_id);
+    let cid = required(&order.customer_id);
+    let bid = required(&order.book_id);
 
-    let bid = match order.book_id.clone() {
-        Some(b) => b,
-        None => return Err("No book_id provided".to_string()),
+    // Orders placed before per-order addresses were recorded ship to the customer's current address
+    let addr = match purchaseOrders::get_po_shipping_address(oid) {
+        Some(a) => a,
+        None => customers::get_customer_address(cid).ok_or_else(|| no_such_order(oid))?,
     };
-    match bid > 0 {
-        true => 0,
-        false => return Err("Book Id must be positive".to_string()),
-    };
-
-    let addr = customers::get_customer_address(cid);
     let shipped = purchaseOrders::is_po_shipped(oid);
     let shipped_status = match shipped {
-        0 => "Not Shipped".to_string(),
-        1 => "Shipped".to_string(),
+        None => return Err(no_such_order(oid)),
+        Some(0) => "Not Shipped".to_string(),
+        Some(1) => "Shipped".to_string(),
         _ => return Err("Invalid shipped status somehow".to_string()),
     };
+    let shipping = purchaseOrders::get_po_shipping(oid).map(|s| format!("\n\t {}", shipping_message(&s))).unwrap_or_default();
+    let taxes = purchaseOrders::get_po_taxes(oid).iter().map(|tax| format!("\n\t {}", tax_message(tax))).collect::<String>();
 
     // Changed html output to just string since we don't use html anywhere else
     // Don't need to check address since it is already from the database, so it has been validated
-    let success_msg = format!("Order Status of Order ID: {} is {}\n\t Book ID: {}\n\t Customer ID: {}\n\t Shipping Address: {}",
-                                     oid, shipped_status, bid, cid, addr);
+    let success_msg = format!("Order Status of Order ID: {} is {}\n\t Book ID: {}\n\t Customer ID: {}\n\t Shipping Address: {}{}{}",
+                                     oid, shipped_status, bid, cid, addr, shipping, taxes);
 
     return Ok(success_msg)
 }
 
+fn no_such_order(oid: i64) -> String {
+    return format!("No order with Order ID {}", oid);
+}
+
+fn shipping_message(shipping: &ShippingQuote) -> String {
+    return format!("Shipping ({}): ${:.2}, arriving {} to {}", shipping.name, shipping.cost, shipping.earliest_delivery, shipping.latest_delivery);
+}
+
+fn tax_message(tax: &TaxLine) -> String {
+    let included = if tax.included { " included" } else { "" };
+    return format!("Tax on {} ({} {}%){}: ${:.2}", tax.line, tax.jurisdiction, tax.rate, included, tax.amount);
+}
@@ -0,0 +1,39 @@
+use rocket::serde::json::Json;
+use serde::Serialize;
+
+use crate::db::authors::{self, AuthorRecord, ContributedBook};
+use crate::validation::normalize_text;
+use super::{paging, ApiError};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AuthorPage {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub authors: Vec<AuthorRecord>,
+}
+
+// Authors by name, those whose names contain name regardless of case if given
+#[get("/?<name>&<page>&<per_page>")]
+pub fn list_authors(name: Option<&str>, page: Option<i64>, per_page: Option<i64>) -> Result<Json<AuthorPage>, ApiError> {
+    let (page, per_page, offset) = paging(page, per_page)?;
+    let name = name.map(normalize_text);
+    let (authors, total) = authors::list_authors(name.as_deref(), per_page, offset);
+    return Ok(Json(AuthorPage { page, per_page, total, authors }));
+}
+
+#[get("/<aid>")]
+pub fn get_author(aid: i64) -> Result<Json<AuthorRecord>, ApiError> {
+    return find(aid).map(Json);
+}
+
+// Every book the author contributed to, with their role on it
+#[get("/<aid>/books")]
+pub fn get_books(aid: i64) -> Result<Json<Vec<ContributedBook>>, ApiError> {
+    find(aid)?;
+    return Ok(Json(authors::author_books(aid)));
+}
+
+fn find(aid: i64) -> Result<AuthorRecord, ApiError> {
+    return authors::get_author(aid).ok_or_else(|| ApiError::not_found(format!("No author with authorId {}", aid)));
+}
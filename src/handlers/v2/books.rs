@@ -0,0 +1,216 @@
+use rocket::data::{Data, Limits};
+use rocket::futures::stream::Stream;
+use rocket::http::{ContentType, Status};
+use rocket::response::status::{BadRequest, Custom};
+use rocket::response::stream::ByteStream;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::db::audit::Actor;
+use crate::db::books::{self, BookRecord};
+use crate::db::prices::{self, PriceError, PriceRecord};
+use crate::db::taxonomy::{self, Classification};
+use crate::catalog::{Book, CatalogFormat, CreateBook, ImportReport};
+use crate::handlers::books as v1;
+use crate::handlers::idempotency::IdempotencyKey;
+use crate::onix::OnixReport;
+use crate::request_id::RequestActor;
+use crate::valid::Valid;
+use crate::validation::{normalize_text, required, validate_alphanumeric_input, validate_positive_id, FieldError, Rule, Rules, Validate, Value,
+                        ValidationConfig};
+use super::taxonomy::category_error;
+use super::{to_stored, ApiError, Created};
+
+// A book's categories by id and its tags by name, either left as it is when not given
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BookClassification {
+    pub categories: Option<Vec<i64>>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl Validate for BookClassification {
+    // No field has rules of its own, see check
+    fn value(&self, _field: &str) -> Option<Value<'_>> {
+        return None;
+    }
+
+    fn normalize(&mut self) {
+        if let Some(tags) = &mut self.tags {
+            for tag in tags.iter_mut() {
+                *tag = normalize_text(tag);
+            }
+        }
+    }
+
+    fn check(&self, prefix: &str, function: &str) -> Vec<FieldError> {
+        let categories = self.categories.iter().flatten().enumerate()
+            .filter_map(|(i, cid)| validate_positive_id(*cid, &format!("{}categories[{}]", prefix, i), function).err());
+        let tags = self.tags.iter().flatten().enumerate()
+            .filter_map(|(i, tag)| validate_alphanumeric_input(tag, &format!("{}tags[{}]", prefix, i), function).err());
+        return categories.chain(tags).collect();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Stock {
+    // Left out to stop counting the copies, as for ebooks
+    pub stock: Option<i64>,
+}
+
+impl Validate for Stock {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "stock" => Some(Value::Count(self.stock)),
+            _ => None,
+        }
+    }
+}
+
+pub struct SetStock;
+impl Rules<Stock> for SetStock {
+    const FUNCTION: &'static str = "v2_set_stock";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("stock", &[Rule::Count]),
+    ];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PriceChange {
+    pub price: Option<f64>,
+    // A UTC time of form YYYY-MM-DD HH:MM:SS, or a date for its start. Left out for the price to apply now.
+    pub effective_from: Option<String>,
+}
+
+impl Validate for PriceChange {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "price" => Some(Value::Amount(self.price)),
+            "effective_from" => Some(Value::Text(&self.effective_from)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        if let Some(time) = &mut self.effective_from {
+            *time = time.trim().to_string();
+        }
+    }
+}
+
+pub struct SchedulePrice;
+impl Rules<PriceChange> for SchedulePrice {
+    const FUNCTION: &'static str = "v2_schedule_price";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("price", &[Rule::Required, Rule::Amount]),
+        ("effective_from", &[Rule::Timestamp]),
+    ];
+}
+
+pub struct ClassifyBook;
+impl Rules<BookClassification> for ClassifyBook {
+    const FUNCTION: &'static str = "v2_classify_book";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[];
+}
+
+#[post("/", data = "<book>")]
+pub fn create_book(book: Valid<Book, CreateBook>, idempotency_key: IdempotencyKey, actor: RequestActor) -> Created {
+    Created(idempotency_key.run("v2_create_book", 0, &*book, || to_stored(new_book(&actor, &book))))
+}
+
+fn new_book(actor: &Actor, book: &Book) -> Result<BookRecord, ApiError> {
+    let bid = books::create_book(actor, book.to_record()).map_err(|e| ApiError::new(Status::Conflict, v1::book_conflict(e)))?;
+    return find(bid);
+}
+
+#[get("/<bid>")]
+pub fn get_book(bid: i64) -> Result<Json<BookRecord>, ApiError> {
+    return find(bid).map(Json);
+}
+
+// Matches regardless of case and Unicode normalization form, like v1's /books/price
+#[get("/?<title>&<author>")]
+pub fn find_book(title: &str, author: &str) -> Result<Json<BookRecord>, ApiError> {
+    let (title, author) = (normalize_text(title), normalize_text(author));
+    return match books::find_book_id(&title, &author) {
+        Some(bid) => find(bid).map(Json),
+        None => Err(ApiError::not_found(format!("No book {} by {}", title, author))),
+    };
+}
+
+// Sets rather than adjusts the stock, like a customer's balance
+#[put("/<bid>/stock", data = "<stock>")]
+pub fn set_stock(bid: i64, stock: Valid<Stock, SetStock>, actor: RequestActor) -> Result<Json<BookRecord>, ApiError> {
+    return books::set_stock(&actor, bid, stock.stock).map(Json).ok_or_else(|| ApiError::not_found(format!("No book with bookId {}", bid)));
+}
+
+#[get("/<bid>/prices")]
+pub fn get_prices(bid: i64) -> Result<Json<Vec<PriceRecord>>, ApiError> {
+    return prices::price_history(bid).map(Json).ok_or_else(|| ApiError::not_found(format!("No book with bookId {}", bid)));
+}
+
+// Sets a new price from now, or schedules it. Orders placed before it starts keep the price they were charged.
+#[post("/<bid>/prices", data = "<change>")]
+pub fn schedule_price(bid: i64, change: Valid<PriceChange, SchedulePrice>, idempotency_key: IdempotencyKey, actor: RequestActor)
+                      -> Created {
+    Created(idempotency_key.run("v2_schedule_price", 0, &*change, || {
+        to_stored(prices::schedule_price(&actor, bid, required(&change.price), change.effective_from.as_deref()).map_err(price_error))
+    }))
+}
+
+// Only prices that haven't started yet can be cancelled
+#[delete("/<bid>/prices/<pid>")]
+pub fn cancel_price(bid: i64, pid: i64, actor: RequestActor) -> Result<Json<PriceRecord>, ApiError> {
+    return prices::cancel_price(&actor, bid, pid).map(Json).map_err(price_error);
+}
+
+#[get("/<bid>/classification")]
+pub fn get_classification(bid: i64) -> Result<Json<Classification>, ApiError> {
+    find(bid)?;
+    return Ok(Json(taxonomy::get_classification(bid)));
+}
+
+// Replaces the book's categories, its tags or both, so repeating it is harmless
+#[put("/<bid>/classification", data = "<classification>")]
+pub fn classify_book(bid: i64, classification: Valid<BookClassification, ClassifyBook>) -> Result<Json<Classification>, ApiError> {
+    find(bid)?;
+    return taxonomy::classify_book(bid, classification.categories.as_deref(), classification.tags.as_deref())
+        .map(Json)
+        .map_err(category_error);
+}
+
+// Takes the same query parameters and body as v1, see handlers::books::import_books
+#[post("/import?<format>&<dry_run>", data = "<catalog>")]
+pub async fn import_books(format: Option<CatalogFormat>, dry_run: Option<bool>, catalog: Data<'_>, limits: &Limits,
+                          config: &State<ValidationConfig>, actor: RequestActor) -> Result<Custom<Json<ImportReport>>, ApiError> {
+    return v1::import_books(format, dry_run, catalog, limits, config, actor).await.map_err(bad_request);
+}
+
+#[get("/export?<format>")]
+pub fn export_books(format: Option<CatalogFormat>) -> Result<(ContentType, ByteStream<impl Stream<Item = Vec<u8>>>), ApiError> {
+    return v1::export_books(format).map_err(bad_request);
+}
+
+// Takes the same body as v1, see handlers::books::ingest_onix
+#[post("/onix", data = "<message>")]
+pub async fn ingest_onix(message: Data<'_>, limits: &Limits, config: &State<ValidationConfig>, actor: RequestActor)
+                         -> Result<Json<OnixReport>, ApiError> {
+    return v1::ingest_onix(message, limits, config, actor).await.map_err(bad_request);
+}
+
+fn bad_request(err: BadRequest<String>) -> ApiError {
+    return ApiError::new(Status::BadRequest, err.0.unwrap_or_default());
+}
+
+fn find(bid: i64) -> Result<BookRecord, ApiError> {
+    return books::get_book(bid).ok_or_else(|| ApiError::not_found(format!("No book with bookId {}", bid)));
+}
+
+fn price_error(err: PriceError) -> ApiError {
+    return match err {
+        PriceError::BookNotFound(bid) => ApiError::not_found(format!("No book with bookId {}", bid)),
+        PriceError::NotFound(pid) => ApiError::not_found(format!("No price with priceId {} for this book", pid)),
+        PriceError::InPast => ApiError::new(Status::UnprocessableEntity, "Please give an effective_from of now or later".to_string()),
+        PriceError::Started(pid) => ApiError::new(Status::Conflict, format!("The price with priceId {} has already started", pid)),
+    };
+}
@@ -0,0 +1,71 @@
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::addresses::{self, CustomerAddress};
+use crate::db::customers::{self, CustomerRecord};
+use crate::handlers::customers::{self as v1, CreateCustomer, Customer, OrderHistory};
+use crate::handlers::idempotency::IdempotencyKey;
+use crate::request_id::RequestActor;
+use crate::valid::Valid;
+use crate::validation::{required, Rule, Rules, Validate, Value};
+use super::{to_stored, ApiError, Created};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Balance {
+    pub account_balance: Option<f64>,
+}
+
+impl Validate for Balance {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "account_balance" => Some(Value::Amount(self.account_balance)),
+            _ => None,
+        }
+    }
+}
+
+pub struct SetBalance;
+impl Rules<Balance> for SetBalance {
+    const FUNCTION: &'static str = "v2_set_balance";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("account_balance", &[Rule::Required, Rule::Amount]),
+    ];
+}
+
+#[post("/", data = "<customer>")]
+pub fn create_customer(customer: Valid<Customer, CreateCustomer>, idempotency_key: IdempotencyKey, actor: RequestActor) -> Created {
+    Created(idempotency_key.run("v2_create_customer", 0, &*customer, || to_stored(find(v1::new_customer(&actor, &customer)))))
+}
+
+#[get("/<cid>")]
+pub fn get_customer(cid: i64) -> Result<Json<CustomerRecord>, ApiError> {
+    return find(cid).map(Json);
+}
+
+// Sets rather than adjusts the balance, so repeating it is harmless and no Idempotency-Key is needed
+#[put("/<cid>/balance", data = "<balance>")]
+pub fn set_balance(cid: i64, balance: Valid<Balance, SetBalance>, actor: RequestActor) -> Result<Json<CustomerRecord>, ApiError> {
+    find(cid)?;
+    customers::update_customer_balance(&actor, cid, required(&balance.account_balance));
+    return find(cid).map(Json);
+}
+
+#[get("/<cid>/addresses")]
+pub fn get_addresses(cid: i64) -> Result<Json<Vec<CustomerAddress>>, ApiError> {
+    find(cid)?;
+    Ok(Json(addresses::get_addresses(cid)))
+}
+
+// Takes the same query parameters as v1
+#[get("/<cid>/orders?<status>&<since>&<until>&<page>&<per_page>")]
+pub fn get_orders(cid: i64, status: Option<String>, since: Option<String>, until: Option<String>,
+                  page: Option<i64>, per_page: Option<i64>) -> Result<Json<OrderHistory>, ApiError> {
+    find(cid)?;
+    return v1::get_orders(cid, status, since, until, page, per_page).map_err(|Custom(status, e)| ApiError::new(status, e));
+}
+
+fn find(cid: i64) -> Result<CustomerRecord, ApiError> {
+    return customers::get_customer(cid).ok_or_else(|| ApiError::not_found(format!("No customer with customerId {}", cid)));
+}
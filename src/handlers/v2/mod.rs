@@ -0,0 +1,94 @@
+// The JSON redesign of the API, mounted at /v2. Successes return the affected record as JSON,
+// failures return {"status": ..., "error": ...} with a matching HTTP status.
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{self, Json};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+use super::customers::{page_offset, DEFAULT_PER_PAGE};
+use super::idempotency::Idempotent;
+
+#[allow(unused_imports)]
+pub mod authors;
+#[allow(unused_imports)]
+pub mod books;
+#[allow(unused_imports)]
+pub mod customers;
+#[allow(unused_imports)]
+pub mod orders;
+#[allow(unused_imports)]
+pub mod promotions;
+#[allow(unused_imports)]
+pub mod taxonomy;
+#[allow(unused_imports)]
+pub mod works;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiError {
+    status: u16,
+    error: String,
+}
+
+impl ApiError {
+    pub fn new(status: Status, error: String) -> ApiError {
+        ApiError { status: status.code, error }
+    }
+
+    pub fn not_found(error: String) -> ApiError {
+        return ApiError::new(Status::NotFound, error);
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
+        Response::build_from(Json(self).respond_to(req)?).status(status).ok()
+    }
+}
+
+// Creates are idempotent like their v1 counterparts, so the record or error is stored as JSON text, see to_stored
+pub struct Created(pub Idempotent);
+
+impl<'r> Responder<'r, 'static> for Created {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let (status, body, replayed) = match self.0 {
+            Idempotent::Fresh(Ok(body)) => (Status::Created, body, false),
+            Idempotent::Fresh(Err(body)) => {
+                let status = json::from_str::<ApiError>(&body).ok().and_then(|e| Status::from_code(e.status));
+                (status.unwrap_or(Status::InternalServerError), body, false)
+            },
+            Idempotent::Replayed(body) => (Status::Created, body, true),
+            Idempotent::Conflict(msg) => {
+                let body = json::to_string(&ApiError::new(Status::UnprocessableEntity, msg)).expect("errors should always serialize");
+                (Status::UnprocessableEntity, body, false)
+            },
+            Idempotent::InProgress(msg) => {
+                let body = json::to_string(&ApiError::new(Status::Conflict, msg)).expect("errors should always serialize");
+                (Status::Conflict, body, false)
+            },
+        };
+        let mut response = Response::build();
+        response.status(status).header(ContentType::JSON).sized_body(body.len(), Cursor::new(body));
+        if replayed {
+            response.header(Header::new("Idempotent-Replayed", "true"));
+        }
+        response.ok()
+    }
+}
+
+// The form a create's result is stored in for IdempotencyKey::run
+pub fn to_stored<T: Serialize>(result: Result<T, ApiError>) -> Result<String, String> {
+    return match result {
+        Ok(record) => Ok(json::to_string(&record).expect("records should always serialize")),
+        Err(err) => Err(json::to_string(&err).expect("errors should always serialize")),
+    };
+}
+
+// The page, per_page and offset of a listing, defaulting like v1's order history
+pub fn paging(page: Option<i64>, per_page: Option<i64>) -> Result<(i64, i64, i64), ApiError> {
+    let page = page.unwrap_or(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+    return page_offset(page, per_page).map(|offset| (page, per_page, offset)).map_err(|e| ApiError::new(Status::BadRequest, e));
+}
@@ -0,0 +1,134 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+
+use crate::db::audit::Actor;
+use crate::db::promotions::{self, AppliedDiscount, OrderPricing};
+use crate::db::purchaseOrders::{self, PurchaseOrderSummary};
+use crate::db::{books, customers};
+use crate::handlers::idempotency::IdempotencyKey;
+use crate::handlers::orders::{destination, place_order, price_order, shipping_options, CreateOrder, Order, OrderRejected};
+use crate::request_id::RequestActor;
+use crate::shipping::{ShippingQuote, ShippingRules};
+use crate::tax::{TaxLine, TaxRules};
+use crate::valid::Valid;
+use crate::validation::required;
+use super::{to_stored, ApiError, Created};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderRecord {
+    id: i64,
+    customer_id: i64,
+    book_id: i64,
+    book_title: String,
+    quantity: i64,
+    subtotal: Option<f64>,
+    discounts: Vec<AppliedDiscount>,
+    shipping: Option<ShippingQuote>,
+    taxes: Vec<TaxLine>,
+    price_paid: Option<f64>,
+    status: &'static str,
+    created_at: Option<String>,
+    shipped_at: Option<String>,
+    shipping_address: Option<String>,
+}
+
+impl From<PurchaseOrderSummary> for OrderRecord {
+    fn from(order: PurchaseOrderSummary) -> OrderRecord {
+        OrderRecord {
+            id: order.order_id,
+            customer_id: order.customer_id,
+            book_id: order.book_id,
+            book_title: order.book_title,
+            quantity: order.quantity,
+            subtotal: order.subtotal,
+            discounts: promotions::order_discounts(order.order_id),
+            shipping: purchaseOrders::get_po_shipping(order.order_id),
+            taxes: purchaseOrders::get_po_taxes(order.order_id),
+            price_paid: order.price_paid,
+            status: match order.shipped {
+                0 => "not_shipped",
+                _ => "shipped",
+            },
+            created_at: order.created_at,
+            shipped_at: order.shipped_at,
+            shipping_address: order.shipping_address,
+        }
+    }
+}
+
+#[post("/", data = "<order>")]
+pub fn create_order(order: Valid<Order, CreateOrder>, idempotency_key: IdempotencyKey, shipping_rules: &State<ShippingRules>,
+                    tax_rules: &State<TaxRules>, actor: RequestActor) -> Created {
+    let cid = order.customer_id.unwrap_or(0);
+    let actor = actor.for_customer(order.customer_id);
+    Created(idempotency_key.run("v2_create_order", cid, &*order, || to_stored(new_order(&actor, &order, shipping_rules, tax_rules))))
+}
+
+fn new_order(actor: &Actor, order: &Order, shipping_rules: &ShippingRules, tax_rules: &TaxRules) -> Result<OrderRecord, ApiError> {
+    check_order(order)?;
+    let (oid, _) = place_order(actor, order, shipping_rules, tax_rules).map_err(order_rejected)?;
+    return find(oid);
+}
+
+// What the order would cost, with the discounts it would get, without placing it
+#[post("/quote", data = "<order>")]
+pub fn quote_order(order: Valid<Order, CreateOrder>, shipping_rules: &State<ShippingRules>, tax_rules: &State<TaxRules>)
+                   -> Result<Json<OrderPricing>, ApiError> {
+    check_order(&order)?;
+    let address = destination(&order).map_err(order_rejected)?;
+    return price_order(&order, &address, shipping_rules, tax_rules).map(Json).map_err(order_rejected);
+}
+
+// The methods the order could ship by to its address, with their costs and delivery dates, cheapest first
+#[post("/shipping-options", data = "<order>")]
+pub fn get_shipping_options(order: Valid<Order, CreateOrder>, shipping_rules: &State<ShippingRules>)
+                            -> Result<Json<Vec<ShippingQuote>>, ApiError> {
+    check_order(&order)?;
+    let address = destination(&order).map_err(order_rejected)?;
+    return shipping_options(&order, &address, shipping_rules).map(Json).map_err(order_rejected);
+}
+
+fn check_order(order: &Order) -> Result<(), ApiError> {
+    let cid = required(&order.customer_id);
+    let bid = required(&order.book_id);
+    if !customers::customer_exists(cid) {
+        return Err(ApiError::not_found(format!("No customer with customerId {}", cid)));
+    }
+    if books::get_book(bid).is_none() {
+        return Err(ApiError::not_found(format!("No book with bookId {}", bid)));
+    }
+    return Ok(());
+}
+
+fn order_rejected(rejected: OrderRejected) -> ApiError {
+    return match rejected {
+        OrderRejected::UnknownBook(_) | OrderRejected::NoAddress(_) => ApiError::not_found(rejected.to_string()),
+        OrderRejected::InsufficientFunds { .. } => ApiError::new(Status::Conflict, rejected.to_string()),
+        OrderRejected::OutOfStock(_) => ApiError::new(Status::Conflict, rejected.to_string()),
+        OrderRejected::Coupon(_) => ApiError::new(Status::UnprocessableEntity, rejected.to_string()),
+        OrderRejected::Shipping(_) => ApiError::new(Status::UnprocessableEntity, rejected.to_string()),
+    };
+}
+
+#[get("/<oid>")]
+pub fn get_order(oid: i64) -> Result<Json<OrderRecord>, ApiError> {
+    return find(oid).map(Json);
+}
+
+#[post("/<oid>/ship")]
+pub fn ship_order(oid: i64, actor: RequestActor) -> Result<Json<OrderRecord>, ApiError> {
+    let order = find(oid)?;
+    if order.status == "shipped" {
+        return Err(ApiError::new(Status::Conflict, format!("Order {} has already shipped", oid)));
+    }
+    purchaseOrders::ship_po(&actor, oid);
+    return find(oid).map(Json);
+}
+
+fn find(oid: i64) -> Result<OrderRecord, ApiError> {
+    return purchaseOrders::get_purchase_order(oid)
+        .map(OrderRecord::from)
+        .ok_or_else(|| ApiError::not_found(format!("No order with orderId {}", oid)));
+}
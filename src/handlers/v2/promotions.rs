@@ -0,0 +1,185 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::promotions::{self, PromotionError, PromotionKind, PromotionRecord};
+use crate::handlers::admin::AdminToken;
+use crate::handlers::idempotency::IdempotencyKey;
+use crate::request_id::RequestActor;
+use crate::valid::Valid;
+use crate::validation::{normalize_text_in, required, FieldError, Rule, Rules, Validate, Value};
+use super::{to_stored, ApiError, Created};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Promotion {
+    pub name: Option<String>,
+    // Left out for a promotion every eligible order gets without one
+    pub code: Option<String>,
+    pub kind: Option<PromotionKind>,
+    // For percentage promotions
+    pub percent: Option<f64>,
+    // For fixed promotions
+    pub amount: Option<f64>,
+    // For bundles, free_quantity of every buy_quantity + free_quantity copies are free
+    pub buy_quantity: Option<i64>,
+    pub free_quantity: Option<i64>,
+    pub minimum_subtotal: Option<f64>,
+    // Each narrows down the orders eligible, the category including those below it
+    pub book_id: Option<i64>,
+    pub author_id: Option<i64>,
+    pub category_id: Option<i64>,
+    pub customer_id: Option<i64>,
+    pub usage_limit: Option<i64>,
+    pub per_customer_limit: Option<i64>,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+}
+
+impl Promotion {
+    // What create_promotion stores for a promotion that passed SavePromotion
+    pub fn to_record(&self) -> PromotionRecord {
+        let kind = self.kind.expect("SavePromotion requires a kind");
+        return PromotionRecord {
+            id: 0,
+            name: required(&self.name),
+            code: self.code.clone(),
+            kind,
+            percent: self.percent.filter(|_| kind == PromotionKind::Percentage),
+            amount: self.amount.filter(|_| kind == PromotionKind::Fixed),
+            buy_quantity: self.buy_quantity.filter(|_| kind == PromotionKind::Bundle),
+            free_quantity: self.free_quantity.filter(|_| kind == PromotionKind::Bundle),
+            minimum_subtotal: self.minimum_subtotal,
+            book_id: self.book_id,
+            author_id: self.author_id,
+            category_id: self.category_id,
+            customer_id: self.customer_id,
+            usage_limit: self.usage_limit,
+            per_customer_limit: self.per_customer_limit,
+            starts_at: self.starts_at.clone(),
+            ends_at: self.ends_at.clone(),
+            uses: 0,
+        };
+    }
+}
+
+impl Validate for Promotion {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "name" => Some(Value::Text(&self.name)),
+            "code" => Some(Value::Text(&self.code)),
+            "percent" => Some(Value::Amount(self.percent)),
+            "amount" => Some(Value::Amount(self.amount)),
+            "buy_quantity" => Some(Value::Count(self.buy_quantity)),
+            "free_quantity" => Some(Value::Count(self.free_quantity)),
+            "minimum_subtotal" => Some(Value::Amount(self.minimum_subtotal)),
+            "book_id" => Some(Value::Id(self.book_id)),
+            "author_id" => Some(Value::Id(self.author_id)),
+            "category_id" => Some(Value::Id(self.category_id)),
+            "customer_id" => Some(Value::Id(self.customer_id)),
+            "usage_limit" => Some(Value::Count(self.usage_limit)),
+            "per_customer_limit" => Some(Value::Count(self.per_customer_limit)),
+            "starts_at" => Some(Value::Text(&self.starts_at)),
+            "ends_at" => Some(Value::Text(&self.ends_at)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        normalize_text_in(&mut self.name);
+        if let Some(code) = &mut self.code {
+            *code = code.trim().to_uppercase();
+        }
+    }
+
+    // Each kind needs its own fields
+    fn check(&self, prefix: &str, _function: &str) -> Vec<FieldError> {
+        let field = |name: &str| format!("{}{}", prefix, name);
+        let missing = |name: &str, kind: &str| FieldError::new(&field(name), "required", &format!("Please provide {} for a {} promotion", name, kind));
+        return match self.kind {
+            None => vec![FieldError::new(&field("kind"), "required", "Please provide a kind of percentage, fixed or bundle")],
+            Some(PromotionKind::Percentage) => match self.percent {
+                None => vec![missing("percent", "percentage")],
+                Some(percent) if percent > 100.0 => vec![FieldError::new(&field("percent"), "invalid_amount", "Please give a percent of at most 100")],
+                Some(_) => Vec::new(),
+            },
+            Some(PromotionKind::Fixed) => match self.amount {
+                None => vec![missing("amount", "fixed")],
+                Some(_) => Vec::new(),
+            },
+            Some(PromotionKind::Bundle) => ["buy_quantity", "free_quantity"]
+                .into_iter()
+                .zip([self.buy_quantity, self.free_quantity])
+                .filter_map(|(name, quantity)| match quantity {
+                    None => Some(missing(name, "bundle")),
+                    Some(0) => Some(FieldError::new(&field(name), "not_positive", &format!("Please give 1 or more for {}", name))),
+                    Some(_) => None,
+                })
+                .collect(),
+        };
+    }
+}
+
+pub struct SavePromotion;
+impl Rules<Promotion> for SavePromotion {
+    const FUNCTION: &'static str = "v2_save_promotion";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("code", &[Rule::Alphanumeric]),
+        ("percent", &[Rule::Amount]),
+        ("amount", &[Rule::Amount]),
+        ("buy_quantity", &[Rule::Count]),
+        ("free_quantity", &[Rule::Count]),
+        ("minimum_subtotal", &[Rule::Amount]),
+        ("book_id", &[Rule::PositiveId]),
+        ("author_id", &[Rule::PositiveId]),
+        ("category_id", &[Rule::PositiveId]),
+        ("customer_id", &[Rule::PositiveId]),
+        ("usage_limit", &[Rule::Count]),
+        ("per_customer_limit", &[Rule::Count]),
+        ("starts_at", &[Rule::Timestamp]),
+        ("ends_at", &[Rule::Timestamp]),
+    ];
+}
+
+#[get("/")]
+pub fn list_promotions() -> Json<Vec<PromotionRecord>> {
+    return Json(promotions::list_promotions());
+}
+
+// Writes need the X-Admin-Token, as a promotion without a code applies to every order
+#[post("/", data = "<promotion>")]
+pub fn create_promotion(_admin: AdminToken, promotion: Valid<Promotion, SavePromotion>, idempotency_key: IdempotencyKey,
+                        actor: RequestActor) -> Created {
+    Created(idempotency_key.run("v2_create_promotion", 0, &*promotion, || {
+        to_stored(promotions::create_promotion(&actor, promotion.to_record()).map_err(promotion_error))
+    }))
+}
+
+#[get("/<pid>")]
+pub fn get_promotion(pid: i64) -> Result<Json<PromotionRecord>, ApiError> {
+    return promotions::get_promotion(pid).map(Json).ok_or_else(|| ApiError::not_found(format!("No promotion with promotionId {}", pid)));
+}
+
+// Replaces every field, those left out are cleared
+#[put("/<pid>", data = "<promotion>")]
+pub fn update_promotion(_admin: AdminToken, pid: i64, promotion: Valid<Promotion, SavePromotion>, actor: RequestActor)
+                        -> Result<Json<PromotionRecord>, ApiError> {
+    return promotions::update_promotion(&actor, pid, promotion.to_record()).map(Json).map_err(promotion_error);
+}
+
+#[delete("/<pid>")]
+pub fn delete_promotion(_admin: AdminToken, pid: i64, actor: RequestActor) -> Result<Json<PromotionRecord>, ApiError> {
+    return promotions::delete_promotion(&actor, pid).map(Json).map_err(promotion_error);
+}
+
+fn promotion_error(err: PromotionError) -> ApiError {
+    return match err {
+        PromotionError::NotFound(pid) => ApiError::not_found(format!("No promotion with promotionId {}", pid)),
+        PromotionError::Unknown(field, id) => ApiError::new(Status::UnprocessableEntity, format!("No {} {}", field, id)),
+        PromotionError::DuplicateCode(pid) => ApiError::new(Status::Conflict, format!("The code is already used by promotionId {}", pid)),
+        PromotionError::EmptyWindow => ApiError::new(Status::UnprocessableEntity, "Please give an ends_at after starts_at".to_string()),
+        PromotionError::Used(uses) => {
+            ApiError::new(Status::Conflict, format!("The promotion was used by {} orders, please set ends_at to end it instead", uses))
+        },
+    };
+}
@@ -0,0 +1,124 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::books::BookRecord;
+use crate::db::taxonomy::{self, CategoryError, CategoryNode, CategoryRecord, Facets, TagRecord};
+use crate::handlers::idempotency::IdempotencyKey;
+use crate::valid::Valid;
+use crate::validation::{normalize_text, normalize_text_in, required, Rule, Rules, Validate, Value};
+use super::{paging, to_stored, ApiError, Created};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Category {
+    pub name: Option<String>,
+    // Left out for a top-level category
+    pub parent_id: Option<i64>,
+}
+
+impl Validate for Category {
+    fn value(&self, field: &str) -> Option<Value<'_>> {
+        match field {
+            "name" => Some(Value::Text(&self.name)),
+            "parent_id" => Some(Value::Id(self.parent_id)),
+            _ => None,
+        }
+    }
+
+    fn normalize(&mut self) {
+        normalize_text_in(&mut self.name);
+    }
+}
+
+pub struct SaveCategory;
+impl Rules<Category> for SaveCategory {
+    const FUNCTION: &'static str = "v2_save_category";
+    const FIELDS: &'static [(&'static str, &'static [Rule])] = &[
+        ("name", &[Rule::Required, Rule::Alphanumeric, Rule::SingleScript]),
+        ("parent_id", &[Rule::PositiveId]),
+    ];
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CatalogPage {
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub books: Vec<BookRecord>,
+    pub facets: Facets,
+}
+
+#[get("/")]
+pub fn list_categories() -> Json<Vec<CategoryNode>> {
+    return Json(taxonomy::category_tree());
+}
+
+#[post("/", data = "<category>")]
+pub fn create_category(category: Valid<Category, SaveCategory>, idempotency_key: IdempotencyKey) -> Created {
+    Created(idempotency_key.run("v2_create_category", 0, &*category, || {
+        to_stored(taxonomy::create_category(&required(&category.name), category.parent_id).map_err(category_error))
+    }))
+}
+
+#[get("/<cid>")]
+pub fn get_category(cid: i64) -> Result<Json<CategoryRecord>, ApiError> {
+    return find(cid).map(Json);
+}
+
+// Renames the category and moves it below parent_id, or to the top when there is none
+#[put("/<cid>", data = "<category>")]
+pub fn update_category(cid: i64, category: Valid<Category, SaveCategory>) -> Result<Json<CategoryRecord>, ApiError> {
+    return taxonomy::update_category(cid, &required(&category.name), category.parent_id).map(Json).map_err(category_error);
+}
+
+#[delete("/<cid>")]
+pub fn delete_category(cid: i64) -> Result<Json<CategoryRecord>, ApiError> {
+    return taxonomy::delete_category(cid).map(Json).map_err(category_error);
+}
+
+// The books in the category or any of its descendants
+#[get("/<cid>/books?<tag>&<page>&<per_page>")]
+pub fn get_books(cid: i64, tag: Option<&str>, page: Option<i64>, per_page: Option<i64>) -> Result<Json<CatalogPage>, ApiError> {
+    find(cid)?;
+    return catalog(Some(cid), tag, page, per_page);
+}
+
+#[get("/")]
+pub fn list_tags() -> Json<Vec<TagRecord>> {
+    return Json(taxonomy::list_tags());
+}
+
+// Every book, or those in the category including its descendants and with the tag, with facet counts to narrow them down by
+#[get("/?<category>&<tag>&<page>&<per_page>")]
+pub fn list_catalog(category: Option<i64>, tag: Option<&str>, page: Option<i64>, per_page: Option<i64>) -> Result<Json<CatalogPage>, ApiError> {
+    if let Some(cid) = category {
+        find(cid)?;
+    }
+    return catalog(category, tag, page, per_page);
+}
+
+fn catalog(category: Option<i64>, tag: Option<&str>, page: Option<i64>, per_page: Option<i64>) -> Result<Json<CatalogPage>, ApiError> {
+    let (page, per_page, offset) = paging(page, per_page)?;
+    let tag = tag.map(normalize_text);
+    let (books, total, facets) = taxonomy::list_catalog(category, tag.as_deref(), per_page, offset);
+    return Ok(Json(CatalogPage { page, per_page, total, books, facets }));
+}
+
+fn find(cid: i64) -> Result<CategoryRecord, ApiError> {
+    return taxonomy::get_category(cid).ok_or_else(|| ApiError::not_found(format!("No category with categoryId {}", cid)));
+}
+
+pub fn category_error(err: CategoryError) -> ApiError {
+    return match err {
+        CategoryError::NotFound(cid) => ApiError::not_found(format!("No category with categoryId {}", cid)),
+        CategoryError::Unknown(cid) => ApiError::new(Status::UnprocessableEntity, format!("No category with categoryId {}", cid)),
+        CategoryError::Duplicate(cid) => ApiError::new(Status::Conflict, format!("A category with this name is already there with categoryId {}", cid)),
+        CategoryError::Cycle => ApiError::new(Status::UnprocessableEntity, "A category can't be moved below itself or its descendants".to_string()),
+        CategoryError::HasChildren(count) => {
+            ApiError::new(Status::Conflict, format!("The category has {} subcategories, please move or delete them first", count))
+        },
+        CategoryError::HasPromotions(count) => {
+            ApiError::new(Status::Conflict, format!("{} promotions are for the category, please change or delete them first", count))
+        },
+    };
+}
@@ -0,0 +1,31 @@
+use rocket::serde::json::Json;
+
+use crate::db::works::{self, SeriesRecord, WorkRecord};
+use super::ApiError;
+
+// The work with each of its editions
+#[get("/<wid>")]
+pub fn get_work(wid: i64) -> Result<Json<WorkRecord>, ApiError> {
+    return works::get_work(wid).map(Json).ok_or_else(|| ApiError::not_found(format!("No work with workId {}", wid)));
+}
+
+#[get("/")]
+pub fn list_series() -> Json<Vec<SeriesRecord>> {
+    return Json(works::list_series());
+}
+
+#[get("/<sid>")]
+pub fn get_series(sid: i64) -> Result<Json<SeriesRecord>, ApiError> {
+    return find_series(sid).map(Json);
+}
+
+// In reading order, each work with its editions
+#[get("/<sid>/works")]
+pub fn get_series_works(sid: i64) -> Result<Json<Vec<WorkRecord>>, ApiError> {
+    find_series(sid)?;
+    return Ok(Json(works::series_works(sid)));
+}
+
+fn find_series(sid: i64) -> Result<SeriesRecord, ApiError> {
+    return works::get_series(sid).ok_or_else(|| ApiError::not_found(format!("No series with seriesId {}", sid)));
+}
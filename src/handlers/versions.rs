@@ -0,0 +1,188 @@
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::serde::json::Json;
+use rocket::{Build, Request, Response, Rocket, State};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use log::{error, info};
+
+use crate::dates;
+
+// Read from the `v1_deprecated` and `v1_sunset` keys in Rocket.toml, dates of form YYYY-MM-DD
+#[derive(Deserialize, Debug, Clone, Default)]
+struct VersionConfig {
+    v1_deprecated: Option<String>,
+    v1_sunset: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    // The original routes at /books, /customers and /orders, served the same as /v1
+    Unversioned,
+    V1,
+    V2,
+}
+
+impl Version {
+    // None for routes outside the versioned API, such as /docs
+    pub fn of(path: &str) -> Option<Version> {
+        let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        match first {
+            "v1" => Some(Version::V1),
+            "v2" => Some(Version::V2),
+            "books" | "customers" | "orders" => Some(Version::Unversioned),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Version::Unversioned => "unversioned",
+            Version::V1 => "v1",
+            Version::V2 => "v2",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VersionCounters {
+    unversioned: AtomicU64,
+    v1: AtomicU64,
+    v2: AtomicU64,
+}
+
+impl VersionCounters {
+    pub fn get(&self, version: Version) -> u64 {
+        return self.counter(version).load(Ordering::Relaxed);
+    }
+
+    fn counter(&self, version: Version) -> &AtomicU64 {
+        match version {
+            Version::Unversioned => &self.unversioned,
+            Version::V1 => &self.v1,
+            Version::V2 => &self.v2,
+        }
+    }
+}
+
+// Counts requests per API version and marks v1 responses as deprecated, see RFC 9745 and RFC 8594
+pub struct ApiVersions {
+    counters: Arc<VersionCounters>,
+    // Set from Rocket.toml on ignite
+    v1_headers: OnceLock<Vec<Header<'static>>>,
+}
+
+impl ApiVersions {
+    pub fn fairing() -> ApiVersions {
+        ApiVersions { counters: Arc::new(VersionCounters::default()), v1_headers: OnceLock::new() }
+    }
+}
+
+// The dates as given in Rocket.toml, for GET /versions
+pub struct V1Dates {
+    deprecated: Option<String>,
+    sunset: Option<String>,
+}
+
+#[rocket::async_trait]
+impl Fairing for ApiVersions {
+    fn info(&self) -> Info {
+        Info { name: "API versions", kind: Kind::Ignite | Kind::Response }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = rocket.figment().extract::<VersionConfig>().unwrap_or_default();
+        let mut headers = Vec::new();
+        match parse_optional_date(&config.v1_deprecated, "v1_deprecated") {
+            Ok(Some(days)) => {
+                headers.push(Header::new("Deprecation", format!("@{}", days * 24 * 60 * 60)));
+                headers.push(Header::new("Link", "</docs>; rel=\"deprecation\"; type=\"text/html\""));
+            },
+            Ok(None) => (),
+            Err(()) => return Err(rocket),
+        };
+        match parse_optional_date(&config.v1_sunset, "v1_sunset") {
+            Ok(Some(days)) => headers.push(Header::new("Sunset", http_date(days))),
+            Ok(None) => (),
+            Err(()) => return Err(rocket),
+        };
+        let _ = self.v1_headers.set(headers);
+        let dates = V1Dates { deprecated: config.v1_deprecated, sunset: config.v1_sunset };
+        Ok(rocket.manage(self.counters.clone()).manage(dates))
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let version = match Version::of(req.uri().path().as_str()) {
+            Some(v) => v,
+            None => return,
+        };
+        self.counters.counter(version).fetch_add(1, Ordering::Relaxed);
+        if version == Version::V2 {
+            return;
+        }
+        for header in self.v1_headers.get().into_iter().flatten() {
+            res.set_header(header.clone());
+        }
+    }
+}
+
+fn parse_optional_date(date: &Option<String>, key: &str) -> Result<Option<i64>, ()> {
+    let date = match date {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+    match dates::parse_date(date) {
+        Some(days) => {
+            info!(target: "file", "{} set to {}", key, date);
+            Ok(Some(days))
+        },
+        None => {
+            error!(target: "file", "Invalid {} in Rocket.toml, expected a date of form YYYY-MM-DD: {}", key, date);
+            Err(())
+        },
+    }
+}
+
+// The IMF-fixdate form HTTP uses, such as "Fri, 30 Apr 2027 00:00:00 GMT"
+fn http_date(days: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (year, month, day) = dates::civil_from_days(days);
+    return format!("{}, {:02} {} {} 00:00:00 GMT", WEEKDAYS[days.rem_euclid(7) as usize], day, MONTHS[(month - 1) as usize], year);
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VersionStatus {
+    version: &'static str,
+    status: &'static str,
+    deprecated: Option<String>,
+    sunset: Option<String>,
+    requests: u64,
+}
+
+// Each API version with its status and the requests it has served since startup
+#[get("/versions")]
+pub fn get_versions(counters: &State<Arc<VersionCounters>>, dates: &State<V1Dates>) -> Json<Vec<VersionStatus>> {
+    let v1_status = match dates.deprecated {
+        Some(_) => "deprecated",
+        None => "supported",
+    };
+    let versions = [Version::Unversioned, Version::V1, Version::V2].iter().map(|v| VersionStatus {
+        version: v.name(),
+        status: match v {
+            Version::V2 => "current",
+            _ => v1_status,
+        },
+        deprecated: match v {
+            Version::V2 => None,
+            _ => dates.deprecated.clone(),
+        },
+        sunset: match v {
+            Version::V2 => None,
+            _ => dates.sunset.clone(),
+        },
+        requests: counters.get(*v),
+    }).collect();
+    Json(versions)
+}
@@ -0,0 +1,16 @@
+// The database layer and what it depends on, shared by the server in main.rs and bookshop-admin
+// Explicit returns are the house style throughout db/ and handlers/
+#![allow(clippy::needless_return)]
+
+pub mod address;
+pub mod catalog;
+pub mod dates;
+pub mod db;
+pub mod onix;
+pub mod redact;
+pub mod shipping;
+pub mod tax;
+pub mod text;
+#[cfg(test)]
+mod test_support;
+pub mod validation;
@@ -1,24 +1,127 @@
+// Explicit returns are the house style throughout db/ and handlers/
+#![allow(clippy::needless_return)]
+
 #[macro_use]
 extern crate rocket;
 extern crate serde;
 
-mod db;
+mod cors;
 mod handlers;
+// Rocket's route codegen re-exports the handler, see handlers/mod.rs
+#[allow(unused_imports)]
+mod metrics;
+mod rate_limit;
+mod request_id;
+mod security_headers;
+mod valid;
+use bookshop_rs::{address, catalog, dates, db, onix, redact, shipping, tax, validation};
 use log::info;
+use rocket::fairing::AdHoc;
+
+// Read again by the readiness check, see handlers/health.rs
+pub const LOG_CONFIG: &str = "log4rs.yml";
 
 #[launch]
 fn rocket() -> _ {
-    log4rs::init_file("log4rs.yml", Default::default()).expect("Should initialize");
+    log4rs::init_file(LOG_CONFIG, redact::deserializers()).expect("Should initialize");
     info!(target: "file", "Rocket is initialized");
-    rocket::build()
-        .mount("/books", routes![handlers::books::create_book])
-        .mount("/books", routes![handlers::books::get_price])
-        .mount("/customers", routes![handlers::customers::create_customer])
-        .mount("/customers", routes![handlers::customers::get_balance])
-        .mount("/customers", routes![handlers::customers::update_address])
-        .mount("/customers", routes![handlers::customers::update_balance])
-        .mount("/orders", routes![handlers::orders::create_order])
-        .mount("/orders", routes![handlers::orders::get_shipped])
-        .mount("/orders", routes![handlers::orders::ship_order])
-        .mount("/orders", routes![handlers::orders::get_status])
+    app()
+}
+
+// Everything but logging, so tests can build the same instance
+fn app() -> rocket::Rocket<rocket::Build> {
+    let rocket = rocket::build()
+        .attach(request_id::RequestIds)
+        .attach(rate_limit::RateLimiter::fairing())
+        .attach(redact::RedactionConfig::fairing())
+        .attach(handlers::idempotency::IdempotencyConfig::fairing())
+        .attach(validation::ValidationConfig::fairing())
+        .attach(handlers::versions::ApiVersions::fairing())
+        .attach(handlers::admin::AdminConfig::fairing())
+        .attach(shipping::ShippingRules::fairing())
+        .attach(tax::TaxRules::fairing())
+        .attach(cors::Cors::fairing())
+        .attach(security_headers::SecurityHeaders::fairing())
+        .attach(metrics::RequestMetrics)
+        // Before any request is handled, as connect doesn't migrate
+        .attach(AdHoc::on_ignite("Database", |rocket| async { db::health::initialize(); rocket }))
+        .register("/", catchers![valid::bad_request, valid::unprocessable, rate_limit::customer_limited])
+        .register(rate_limit::LIMITED_PATH, catchers![rate_limit::too_many_requests]);
+    // The original unversioned paths stay mounted so existing clients keep working until v1's sunset
+    let rocket = mount_v1(rocket, "");
+    let rocket = mount_v1(rocket, "/v1");
+    rocket
+        .mount("/v2/books", routes![handlers::v2::books::create_book])
+        .mount("/v2/books", routes![handlers::v2::books::get_book])
+        .mount("/v2/books", routes![handlers::v2::books::find_book])
+        .mount("/v2/books", routes![handlers::v2::books::set_stock])
+        .mount("/v2/books", routes![handlers::v2::books::get_prices])
+        .mount("/v2/books", routes![handlers::v2::books::schedule_price])
+        .mount("/v2/books", routes![handlers::v2::books::cancel_price])
+        .mount("/v2/books", routes![handlers::v2::books::get_classification])
+        .mount("/v2/books", routes![handlers::v2::books::classify_book])
+        .mount("/v2/books", routes![handlers::v2::books::import_books])
+        .mount("/v2/books", routes![handlers::v2::books::export_books])
+        .mount("/v2/books", routes![handlers::v2::books::ingest_onix])
+        .mount("/v2/catalog", routes![handlers::v2::taxonomy::list_catalog])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::list_categories])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::create_category])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::get_category])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::update_category])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::delete_category])
+        .mount("/v2/categories", routes![handlers::v2::taxonomy::get_books])
+        .mount("/v2/tags", routes![handlers::v2::taxonomy::list_tags])
+        .mount("/v2/works", routes![handlers::v2::works::get_work])
+        .mount("/v2/series", routes![handlers::v2::works::list_series])
+        .mount("/v2/series", routes![handlers::v2::works::get_series])
+        .mount("/v2/series", routes![handlers::v2::works::get_series_works])
+        .mount("/v2/authors", routes![handlers::v2::authors::list_authors])
+        .mount("/v2/authors", routes![handlers::v2::authors::get_author])
+        .mount("/v2/authors", routes![handlers::v2::authors::get_books])
+        .mount("/v2/customers", routes![handlers::v2::customers::create_customer])
+        .mount("/v2/customers", routes![handlers::v2::customers::get_customer])
+        .mount("/v2/customers", routes![handlers::v2::customers::set_balance])
+        .mount("/v2/customers", routes![handlers::v2::customers::get_addresses])
+        .mount("/v2/customers", routes![handlers::v2::customers::get_orders])
+        .mount("/v2/orders", routes![handlers::v2::orders::create_order])
+        .mount("/v2/orders", routes![handlers::v2::orders::quote_order])
+        .mount("/v2/orders", routes![handlers::v2::orders::get_shipping_options])
+        .mount("/v2/orders", routes![handlers::v2::orders::get_order])
+        .mount("/v2/orders", routes![handlers::v2::orders::ship_order])
+        .mount("/v2/promotions", routes![handlers::v2::promotions::list_promotions])
+        .mount("/v2/promotions", routes![handlers::v2::promotions::create_promotion])
+        .mount("/v2/promotions", routes![handlers::v2::promotions::get_promotion])
+        .mount("/v2/promotions", routes![handlers::v2::promotions::update_promotion])
+        .mount("/v2/promotions", routes![handlers::v2::promotions::delete_promotion])
+        .mount("/health", routes![handlers::health::live])
+        .mount("/health", routes![handlers::health::ready])
+        .mount("/admin", routes![handlers::admin::verify_audit])
+        .mount("/", routes![handlers::versions::get_versions])
+        .mount("/", routes![metrics::metrics])
+        .mount("/", routes![handlers::openapi::openapi_json])
+        .mount("/", routes![handlers::openapi::docs])
+}
+
+fn mount_v1(rocket: rocket::Rocket<rocket::Build>, base: &str) -> rocket::Rocket<rocket::Build> {
+    rocket
+        .mount(format!("{}/books", base), routes![handlers::books::create_book])
+        .mount(format!("{}/books", base), routes![handlers::books::get_price])
+        .mount(format!("{}/books", base), routes![handlers::books::get_prices])
+        .mount(format!("{}/books", base), routes![handlers::books::import_books])
+        .mount(format!("{}/books", base), routes![handlers::books::export_books])
+        .mount(format!("{}/books", base), routes![handlers::books::ingest_onix])
+        .mount(format!("{}/customers", base), routes![handlers::customers::create_customer])
+        .mount(format!("{}/customers", base), routes![handlers::customers::get_balance])
+        .mount(format!("{}/customers", base), routes![handlers::customers::update_address])
+        .mount(format!("{}/customers", base), routes![handlers::customers::update_balance])
+        .mount(format!("{}/customers", base), routes![handlers::customers::get_orders])
+        .mount(format!("{}/customers", base), routes![handlers::addresses::get_addresses])
+        .mount(format!("{}/customers", base), routes![handlers::addresses::create_address])
+        .mount(format!("{}/customers", base), routes![handlers::addresses::update_address])
+        .mount(format!("{}/customers", base), routes![handlers::addresses::set_default_address])
+        .mount(format!("{}/customers", base), routes![handlers::addresses::delete_address])
+        .mount(format!("{}/orders", base), routes![handlers::orders::create_order])
+        .mount(format!("{}/orders", base), routes![handlers::orders::get_shipped])
+        .mount(format!("{}/orders", base), routes![handlers::orders::ship_order])
+        .mount(format!("{}/orders", base), routes![handlers::orders::get_status])
 }
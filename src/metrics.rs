@@ -0,0 +1,112 @@
+use prometheus::{
+    exponential_buckets, Counter, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{Data, Request, Response};
+use std::sync::LazyLock;
+use std::time::Instant;
+use log::error;
+
+// Business events happen deep in handlers with no request at hand, so the metrics are global
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    orders_created: IntCounter,
+    revenue: Counter,
+    insufficient_funds: IntCounter,
+    validation_failures: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("bookshop".to_string()), None).expect("metric prefix should be valid");
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        ).expect("metric should be valid");
+        // 1ms to about 4s
+        let latency = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time from request to response by route")
+                .buckets(exponential_buckets(0.001, 2.0, 13).expect("buckets should be valid")),
+            &["method", "route"],
+        ).expect("metric should be valid");
+        let orders_created = IntCounter::new("orders_created_total", "Orders placed").expect("metric should be valid");
+        let revenue = Counter::new("revenue_dollars_total", "Sum of the prices paid for orders").expect("metric should be valid");
+        let insufficient_funds = IntCounter::new("insufficient_funds_total", "Orders rejected because the balance was below the price")
+            .expect("metric should be valid");
+        let validation_failures = IntCounterVec::new(
+            Opts::new("validation_failures_total", "Request body fields rejected by validation"),
+            &["field", "code"],
+        ).expect("metric should be valid");
+
+        for collector in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(latency.clone()),
+            Box::new(orders_created.clone()),
+            Box::new(revenue.clone()),
+            Box::new(insufficient_funds.clone()),
+            Box::new(validation_failures.clone()),
+        ] {
+            registry.register(collector).expect("metric names should be unique");
+        }
+        Metrics { registry, requests, latency, orders_created, revenue, insufficient_funds, validation_failures }
+    }
+}
+
+pub fn record_order(price: f64) {
+    METRICS.orders_created.inc();
+    METRICS.revenue.inc_by(price);
+}
+
+pub fn record_insufficient_funds() {
+    METRICS.insufficient_funds.inc();
+}
+
+pub fn record_validation_failure(field: &str, code: &str) {
+    METRICS.validation_failures.with_label_values(&[field, code]).inc();
+}
+
+// Counts and times every request by the route that handled it
+pub struct RequestMetrics;
+
+// When the request arrived, kept in the request's local cache
+struct Started(Instant);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info { name: "Request metrics", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let elapsed = req.local_cache(|| Started(Instant::now())).0.elapsed();
+        // The route's template, not the path, so ids don't each become a series
+        let route = match req.route() {
+            Some(r) => r.uri.as_str(),
+            None => "unmatched",
+        };
+        let method = req.method().as_str();
+        METRICS.requests.with_label_values(&[method, route, &res.status().code.to_string()]).inc();
+        METRICS.latency.with_label_values(&[method, route]).observe(elapsed.as_secs_f64());
+    }
+}
+
+// In the Prometheus text format
+#[get("/metrics")]
+pub fn metrics() -> (ContentType, String) {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer) {
+        error!(target: "file", "Unable to encode metrics: {}", e);
+    }
+    let content_type = ContentType::new("text", "plain").with_params([("version", "0.0.4"), ("charset", "utf-8")]);
+    (content_type, String::from_utf8(buffer).expect("metrics text should be UTF-8"))
+}
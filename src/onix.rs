@@ -0,0 +1,435 @@
+use regex::Regex;
+use roxmltree::{Document, Node, ParsingOptions};
+use serde::Serialize;
+use std::sync::LazyLock;
+use log::{info, warn};
+
+use crate::catalog::{Book, CreateBook};
+use crate::db::audit::Actor;
+use crate::db::books::{self, BookRecord, EditionFormat, Upserted};
+use crate::validation::{normalize_text, valid_isbn13, validate, Validate, ValidationConfig};
+
+// Prices are stored in dollars, other currencies are ignored
+const CURRENCY: &str = "USD";
+
+// Inline tags such as <b> sit within words and sentences, others separate them, see description
+static INLINE_TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?(a|b|i|em|strong|span|sub|sup)\b[^>]*>").unwrap());
+static TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+// A Product record that wasn't applied, and why
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedProduct {
+    pub record_reference: Option<String>,
+    pub isbn: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct OnixReport {
+    pub products: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub skipped: Vec<SkippedProduct>,
+}
+
+// Reads an ONIX 3.0 message with reference names and upserts each of its products by ISBN in one transaction,
+// see books::upsert_books_by_isbn. Titles, authors and prices are checked with the same rules as create_book.
+// Err if the message as a whole can't be read.
+pub fn ingest(actor: &Actor, xml: &str, config: &ValidationConfig) -> Result<OnixReport, String> {
+    let options = ParsingOptions { allow_dtd: true, ..ParsingOptions::default() };
+    let document = Document::parse_with_options(xml, options).map_err(|e| format!("Not well-formed XML: {}", e))?;
+    let message = document.root_element();
+    match message.tag_name().name() {
+        "ONIXMessage" => (),
+        "ONIXmessage" => return Err("ONIX short tags aren't supported, please send reference names".to_string()),
+        other => return Err(format!("Not an ONIX message, the root element is {}", other)),
+    };
+    let release = message.attribute("release").unwrap_or_default();
+    if !release.starts_with("3.") {
+        return Err(format!("Only ONIX 3.0 is supported, this message is release {}", release));
+    }
+    let default_currency = child(message, "Header").and_then(|header| text(header, "DefaultCurrencyCode"));
+
+    let mut report = OnixReport::default();
+    let mut accepted = Vec::new();
+    for product in children(message, "Product") {
+        report.products += 1;
+        let isbn = isbn(product);
+        match read_product(product, isbn.clone(), default_currency.as_deref(), config) {
+            Ok(metadata) => accepted.push(metadata),
+            Err(reason) => {
+                let record_reference = text(product, "RecordReference");
+                warn!(target: "file", "Skipped ONIX product {} with ISBN {}: {}",
+                      record_reference.as_deref().unwrap_or_default(), isbn.as_deref().unwrap_or_default(), reason);
+                report.skipped.push(SkippedProduct { record_reference, isbn, reason });
+            },
+        };
+    }
+    for (_, upserted) in books::upsert_books_by_isbn(actor, accepted) {
+        match upserted {
+            Upserted::Created => report.created += 1,
+            Upserted::Updated => report.updated += 1,
+            Upserted::Unchanged => report.unchanged += 1,
+        };
+    }
+    info!(target: "file", "ONIX message of {} products applied: {} created, {} updated, {} unchanged, {} skipped",
+          report.products, report.created, report.updated, report.unchanged, report.skipped.len());
+    return Ok(report);
+}
+
+fn read_product(product: Node, isbn: Option<String>, default_currency: Option<&str>, config: &ValidationConfig) -> Result<BookRecord, String> {
+    if text(product, "NotificationType").as_deref() == Some("05") {
+        return Err("Deletion notices aren't supported, the book was left as it is".to_string());
+    }
+    let isbn = isbn.ok_or("No ISBN-13")?;
+    if !valid_isbn13(&isbn) {
+        return Err(format!("Invalid ISBN-13 {}", isbn));
+    }
+    let detail = child(product, "DescriptiveDetail").ok_or("No DescriptiveDetail")?;
+    let (price, availability) = supply(product, default_currency)?;
+    let (series, series_position) = collection(detail).unzip();
+    let publishing = child(product, "PublishingDetail");
+    let mut book = Book {
+        id: None,
+        title: title(detail),
+        author: contributors(detail, "A01"),
+        editor: contributors(detail, "B01"),
+        translator: contributors(detail, "B06"),
+        illustrator: contributors(detail, "A12"),
+        price: Some(price),
+        isbn: Some(isbn),
+        availability: Some(availability.to_string()),
+        description: description(product),
+        series,
+        series_position: series_position.flatten(),
+        format: text(detail, "ProductForm").and_then(|form| edition_format(&form)),
+        publisher: publishing.and_then(publisher),
+        publication_date: publishing.and_then(publication_date),
+        stock: None,
+    };
+    book.normalize();
+    let errors = validate::<Book, CreateBook>(&book, config);
+    if !errors.is_empty() {
+        let messages = errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>();
+        return Err(messages.join(", "));
+    }
+    return Ok(book.to_record());
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &'a str) -> Option<Node<'a, 'i>> {
+    return children(node, name).next();
+}
+
+// ONIX reference names are matched without regard to the namespace
+fn children<'a, 'i: 'a>(node: Node<'a, 'i>, name: &'a str) -> impl Iterator<Item = Node<'a, 'i>> + 'a {
+    return node.children().filter(move |n| n.is_element() && n.tag_name().name() == name);
+}
+
+// The trimmed text of a child element, None if it's missing or empty
+fn text(node: Node, name: &str) -> Option<String> {
+    let value = child(node, name)?.text()?.trim().to_string();
+    return if value.is_empty() { None } else { Some(value) };
+}
+
+// ProductIDType 15 is an ISBN-13, 03 a GTIN-13 which is one when it starts with 978 or 979
+fn isbn(product: Node) -> Option<String> {
+    let identifiers = children(product, "ProductIdentifier")
+        .filter_map(|id| Some((text(id, "ProductIDType")?, text(id, "IDValue")?.replace('-', ""))))
+        .collect::<Vec<_>>();
+    let isbn = identifiers.iter().find(|(kind, _)| kind == "15");
+    let gtin = identifiers.iter().find(|(kind, value)| kind == "03" && (value.starts_with("978") || value.starts_with("979")));
+    return isbn.or(gtin).map(|(_, value)| value.clone());
+}
+
+// The distinctive title (TitleType 01) of the product as a whole (TitleElementLevel 01)
+fn title(detail: Node) -> Option<String> {
+    let titles = children(detail, "TitleDetail").collect::<Vec<_>>();
+    let title = titles.iter().find(|t| text(**t, "TitleType").as_deref() == Some("01")).or(titles.first())?;
+    let elements = children(*title, "TitleElement").collect::<Vec<_>>();
+    let element = elements.iter().find(|e| text(**e, "TitleElementLevel").as_deref() == Some("01")).or(elements.first())?;
+    return title_text(*element);
+}
+
+fn title_text(element: Node) -> Option<String> {
+    if let Some(title) = text(element, "TitleText") {
+        return Some(title);
+    }
+    let without_prefix = text(element, "TitleWithoutPrefix")?;
+    return Some(match text(element, "TitlePrefix") {
+        Some(prefix) => format!("{} {}", prefix, without_prefix),
+        None => without_prefix,
+    });
+}
+
+// The series (a Collection's title at TitleElementLevel 02), preferring the publisher's own (CollectionType 10),
+// and the product's number in it
+fn collection(detail: Node) -> Option<(String, Option<i64>)> {
+    let collections = children(detail, "Collection").collect::<Vec<_>>();
+    let collection = collections.iter().find(|c| text(**c, "CollectionType").as_deref() == Some("10")).or(collections.first())?;
+    let element = children(*collection, "TitleDetail")
+        .flat_map(|t| children(t, "TitleElement"))
+        .find(|e| text(*e, "TitleElementLevel").as_deref() == Some("02"))?;
+    let position = text(element, "PartNumber").and_then(|part| part.parse::<i64>().ok());
+    return Some((title_text(element)?, position));
+}
+
+// ONIX code list 150, by its first letter for digital (E) and audio (A) forms
+fn edition_format(form: &str) -> Option<EditionFormat> {
+    return match form {
+        "BB" => Some(EditionFormat::Hardcover),
+        "BC" => Some(EditionFormat::Paperback),
+        _ if form.starts_with('E') => Some(EditionFormat::Ebook),
+        _ if form.starts_with('A') => Some(EditionFormat::Audiobook),
+        _ => None,
+    };
+}
+
+// The publisher (PublishingRole 01), else the first one named
+fn publisher(publishing: Node) -> Option<String> {
+    let publishers = children(publishing, "Publisher").collect::<Vec<_>>();
+    let publisher = publishers.iter().find(|p| text(**p, "PublishingRole").as_deref() == Some("01")).or(publishers.first())?;
+    return text(*publisher, "PublisherName");
+}
+
+// The publication date (PublishingDateRole 01) when it's a whole date, YYYYMMDD in ONIX
+fn publication_date(publishing: Node) -> Option<String> {
+    let date = children(publishing, "PublishingDate")
+        .find(|d| text(*d, "PublishingDateRole").as_deref() == Some("01"))
+        .and_then(|d| text(d, "Date"))?;
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    return Some(format!("{}-{}-{}", &date[0..4], &date[4..6], &date[6..8]));
+}
+
+// Contributors with the ContributorRole joined in their order, such as A01 "By (author)", B01 "Edited by",
+// B06 "Translated by" or A12 "Illustrated by"
+fn contributors(detail: Node, role: &str) -> Option<String> {
+    let names = children(detail, "Contributor")
+        .filter(|c| children(*c, "ContributorRole").any(|r| r.text().map(str::trim) == Some(role)))
+        .filter_map(|c| {
+            let inverted = text(c, "KeyNames").map(|key| match text(c, "NamesBeforeKey") {
+                Some(before) => format!("{} {}", before, key),
+                None => key,
+            });
+            text(c, "PersonName").or(inverted).or_else(|| text(c, "CorporateName"))
+        })
+        .collect::<Vec<_>>();
+    return if names.is_empty() { None } else { Some(names.join(", ")) };
+}
+
+// The first price in dollars, with the availability of the supplier offering it
+fn supply(product: Node, default_currency: Option<&str>) -> Result<(f64, &'static str), String> {
+    let supplies = children(product, "ProductSupply").flat_map(|s| children(s, "SupplyDetail"));
+    for supply in supplies {
+        let price = children(supply, "Price")
+            .filter(|p| text(*p, "CurrencyCode").as_deref().or(default_currency) == Some(CURRENCY))
+            .find_map(|p| text(p, "PriceAmount"));
+        let price = match price {
+            Some(price) => price,
+            None => continue,
+        };
+        let price = price.parse::<f64>().map_err(|_| format!("Invalid PriceAmount {}", price))?;
+        let code = text(supply, "ProductAvailability").ok_or("No ProductAvailability")?;
+        return Ok((price, availability(&code).ok_or_else(|| format!("Unsupported ProductAvailability {}", code))?));
+    }
+    return Err(format!("No price in {}", CURRENCY));
+}
+
+// ONIX code list 65, grouped as stored in Books.availability
+fn availability(code: &str) -> Option<&'static str> {
+    return match code {
+        "11" => Some("temporarily_unavailable"),
+        "01" => Some("unavailable"),
+        _ if code.starts_with('1') || code == "09" => Some("not_yet_available"),
+        _ if code.starts_with('2') => Some("available"),
+        _ if code.starts_with('3') => Some("temporarily_unavailable"),
+        _ if code.starts_with('4') || code.starts_with('5') => Some("unavailable"),
+        _ => None,
+    };
+}
+
+// The description (TextType 03), or else the short description (02), as plain text
+fn description(product: Node) -> Option<String> {
+    let contents = child(product, "CollateralDetail")
+        .map(|c| children(c, "TextContent").collect::<Vec<_>>())
+        .unwrap_or_default();
+    let content = ["03", "02"]
+        .iter()
+        .find_map(|kind| contents.iter().find(|c| text(**c, "TextType").as_deref() == Some(kind)))?;
+    let mut markup = String::new();
+    write_markup(child(*content, "Text")?, &mut markup);
+    let description = normalize_text(&TAGS.replace_all(&INLINE_TAGS.replace_all(&markup, ""), " "));
+    return if description.is_empty() { None } else { Some(description) };
+}
+
+// XHTML is nested elements, written back as tags so they separate words as HTML's do.
+// HTML is escaped markup in the text.
+fn write_markup(node: Node, markup: &mut String) {
+    for child in node.children() {
+        if child.is_text() {
+            markup.push_str(child.text().unwrap_or_default());
+        } else if child.is_element() {
+            let name = child.tag_name().name();
+            markup.push_str(&format!("<{}>", name));
+            write_markup(child, markup);
+            markup.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ONIXMessage release="3.0" xmlns="http://ns.editeur.org/onix/3.0/reference">
+  <Header>
+    <Sender><SenderName>Example Press</SenderName></Sender>
+    <DefaultCurrencyCode>USD</DefaultCurrencyCode>
+  </Header>
+  <Product>
+    <RecordReference>isbn</RecordReference>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier><ProductIDType>15</ProductIDType><IDValue>978-0-306-40615-7</IDValue></ProductIdentifier>
+    <DescriptiveDetail>
+      <ProductForm>BC</ProductForm>
+      <Collection>
+        <CollectionType>10</CollectionType>
+        <TitleDetail>
+          <TitleType>01</TitleType>
+          <TitleElement><TitleElementLevel>02</TitleElementLevel><PartNumber>2</PartNumber><TitleText>Fields</TitleText></TitleElement>
+        </TitleDetail>
+      </Collection>
+      <TitleDetail>
+        <TitleType>01</TitleType>
+        <TitleElement><TitleElementLevel>01</TitleElementLevel><TitlePrefix>The</TitlePrefix><TitleWithoutPrefix>Measure of Things</TitleWithoutPrefix></TitleElement>
+      </TitleDetail>
+      <Contributor><ContributorRole>A01</ContributorRole><NamesBeforeKey>Ada</NamesBeforeKey><KeyNames>Byron</KeyNames></Contributor>
+      <Contributor><ContributorRole>B06</ContributorRole><PersonName>Jean Dupont</PersonName></Contributor>
+    </DescriptiveDetail>
+    <CollateralDetail>
+      <TextContent>
+        <TextType>03</TextType>
+        <Text textformat="02">&lt;p&gt;A &lt;b&gt;bold&lt;/b&gt;ly told story.&lt;/p&gt;&lt;p&gt;In two parts.&lt;/p&gt;</Text>
+      </TextContent>
+    </CollateralDetail>
+    <PublishingDetail>
+      <Publisher><PublishingRole>01</PublishingRole><PublisherName>Example Press</PublisherName></Publisher>
+      <PublishingDate><PublishingDateRole>01</PublishingDateRole><Date>20240305</Date></PublishingDate>
+    </PublishingDetail>
+    <ProductSupply>
+      <SupplyDetail>
+        <ProductAvailability>21</ProductAvailability>
+        <Price><PriceAmount>14.50</PriceAmount><CurrencyCode>EUR</CurrencyCode></Price>
+        <Price><PriceAmount>15.99</PriceAmount><CurrencyCode>USD</CurrencyCode></Price>
+      </SupplyDetail>
+    </ProductSupply>
+  </Product>
+  <Product>
+    <RecordReference>gtin</RecordReference>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier><ProductIDType>03</ProductIDType><IDValue>9781861972712</IDValue></ProductIdentifier>
+    <DescriptiveDetail>
+      <ProductForm>EA</ProductForm>
+      <TitleDetail><TitleType>01</TitleType><TitleElement><TitleElementLevel>01</TitleElementLevel><TitleText>Small Hours</TitleText></TitleElement></TitleDetail>
+      <Contributor><ContributorRole>A01</ContributorRole><PersonName>Mara Lind</PersonName></Contributor>
+    </DescriptiveDetail>
+    <CollateralDetail>
+      <TextContent>
+        <TextType>02</TextType>
+        <Text textformat="05"><p xmlns="http://www.w3.org/1999/xhtml">An <em>emph</em>atic</p><p xmlns="http://www.w3.org/1999/xhtml">tale.</p></Text>
+      </TextContent>
+    </CollateralDetail>
+    <ProductSupply>
+      <SupplyDetail>
+        <ProductAvailability>10</ProductAvailability>
+        <Price><PriceAmount>4.99</PriceAmount></Price>
+      </SupplyDetail>
+    </ProductSupply>
+  </Product>
+  <Product>
+    <RecordReference>not-a-book</RecordReference>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier><ProductIDType>03</ProductIDType><IDValue>4006381333931</IDValue></ProductIdentifier>
+  </Product>
+  <Product>
+    <RecordReference>pounds</RecordReference>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier><ProductIDType>15</ProductIDType><IDValue>9780306406157</IDValue></ProductIdentifier>
+    <DescriptiveDetail>
+      <TitleDetail><TitleType>01</TitleType><TitleElement><TitleElementLevel>01</TitleElementLevel><TitleText>Abroad</TitleText></TitleElement></TitleDetail>
+      <Contributor><ContributorRole>A01</ContributorRole><PersonName>Mara Lind</PersonName></Contributor>
+    </DescriptiveDetail>
+    <ProductSupply>
+      <SupplyDetail>
+        <ProductAvailability>20</ProductAvailability>
+        <Price><PriceAmount>9.99</PriceAmount><CurrencyCode>GBP</CurrencyCode></Price>
+      </SupplyDetail>
+    </ProductSupply>
+  </Product>
+  <Product>
+    <RecordReference>deleted</RecordReference>
+    <NotificationType>05</NotificationType>
+    <ProductIdentifier><ProductIDType>15</ProductIDType><IDValue>9780306406157</IDValue></ProductIdentifier>
+  </Product>
+</ONIXMessage>"#;
+
+    // The product of MESSAGE with the record reference, read as ingest does
+    fn read(reference: &str) -> Result<BookRecord, String> {
+        let document = Document::parse(MESSAGE).unwrap();
+        let message = document.root_element();
+        let default_currency = child(message, "Header").and_then(|header| text(header, "DefaultCurrencyCode"));
+        let product = children(message, "Product").find(|p| text(*p, "RecordReference").as_deref() == Some(reference)).unwrap();
+        return read_product(product, isbn(product), default_currency.as_deref(), &ValidationConfig::default());
+    }
+
+    #[test]
+    fn products_are_read_into_books() {
+        let book = read("isbn").unwrap();
+        assert_eq!(book.isbn.as_deref(), Some("9780306406157"));
+        assert_eq!(book.title, "The Measure of Things");
+        assert_eq!(book.author, "Ada Byron");
+        assert_eq!(book.translator.as_deref(), Some("Jean Dupont"));
+        assert_eq!((book.series.as_deref(), book.series_position), (Some("Fields"), Some(2)));
+        assert_eq!(book.format, Some(EditionFormat::Paperback));
+        assert_eq!(book.publisher.as_deref(), Some("Example Press"));
+        assert_eq!(book.publication_date.as_deref(), Some("2024-03-05"));
+        assert_eq!(book.availability.as_deref(), Some("available"));
+    }
+
+    #[test]
+    fn gtins_are_isbns_only_in_the_bookland_prefixes() {
+        assert_eq!(read("gtin").unwrap().isbn.as_deref(), Some("9781861972712"));
+        assert_eq!(read("not-a-book"), Err("No ISBN-13".to_string()));
+    }
+
+    #[test]
+    fn prices_are_taken_in_dollars_only() {
+        assert_eq!(read("isbn").unwrap().price, 15.99);
+        // Without a CurrencyCode the price is in the header's DefaultCurrencyCode
+        assert_eq!(read("gtin").unwrap().price, 4.99);
+        assert_eq!(read("pounds"), Err("No price in USD".to_string()));
+    }
+
+    #[test]
+    fn html_and_xhtml_descriptions_become_plain_text() {
+        assert_eq!(read("isbn").unwrap().description.as_deref(), Some("A boldly told story. In two parts."));
+        assert_eq!(read("gtin").unwrap().description.as_deref(), Some("An emphatic tale."));
+    }
+
+    #[test]
+    fn deletion_notices_are_skipped() {
+        assert_eq!(read("deleted"), Err("Deletion notices aren't supported, the book was left as it is".to_string()));
+    }
+
+    #[test]
+    fn only_onix_3_reference_names_are_read() {
+        let ingest = |xml: &str| ingest(&Actor::cli(), xml, &ValidationConfig::default()).unwrap_err();
+        assert_eq!(ingest("<ONIXmessage release=\"3.0\"><header/></ONIXmessage>"),
+                   "ONIX short tags aren't supported, please send reference names");
+        assert_eq!(ingest("<ONIXMessage release=\"2.1\"/>"), "Only ONIX 3.0 is supported, this message is release 2.1");
+        assert_eq!(ingest("<Catalog/>"), "Not an ONIX message, the root element is Catalog");
+    }
+}
@@ -0,0 +1,363 @@
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{Build, Data, Request, Rocket};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use log::{error, info, warn};
+
+use crate::request_id::customer_in_uri;
+
+// Limited requests are rerouted here, where no route matches, so too_many_requests answers them
+// without their handler running
+pub const LIMITED_PATH: &str = "/__rate_limited";
+
+// Buckets that have refilled are dropped once there are this many, and if that isn't enough the
+// least recently used too, down to BUCKETS_KEPT so the sweep isn't repeated on every request
+const MAX_BUCKETS: usize = 10_000;
+const BUCKETS_KEPT: usize = MAX_BUCKETS * 9 / 10;
+
+// Read from the `rate_limits` table in Rocket.toml. Keys are "default" or a method and path such as
+// "GET /customers/balance", with <...> matching any one segment. v1 routes are given by their unversioned path.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct RateLimitConfig {
+    #[serde(default)]
+    rate_limits: BTreeMap<String, Limit>,
+    // Proxies whose ip_header (X-Real-IP by default) names the client, from any other peer it's ignored
+    #[serde(default)]
+    trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct Limit {
+    // Requests allowed at once
+    burst: u32,
+    // Requests allowed per minute after that
+    per_minute: u32,
+}
+
+struct Rule {
+    name: String,
+    method: Option<Method>,
+    segments: Vec<String>,
+    limit: Limit,
+}
+
+impl Rule {
+    fn matches(&self, method: Method, path: &[&str]) -> bool {
+        if self.method.is_some_and(|m| m != method) {
+            return false;
+        }
+        if self.name == "default" {
+            return true;
+        }
+        return self.segments.len() == path.len()
+            && self.segments.iter().zip(path).all(|(rule, given)| rule.starts_with('<') || rule == given);
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // Takes a token, or gives the seconds until one is available
+    fn take(&mut self, limit: Limit, now: Instant) -> Result<(), u64> {
+        let per_second = limit.per_minute as f64 / 60.0;
+        let refilled = self.tokens + now.duration_since(self.updated).as_secs_f64() * per_second;
+        self.tokens = refilled.min(limit.burst as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        return Err(((1.0 - self.tokens) / per_second).ceil() as u64);
+    }
+
+    fn is_full(&self, limit: Limit, now: Instant) -> bool {
+        let per_second = limit.per_minute as f64 / 60.0;
+        return self.tokens + now.duration_since(self.updated).as_secs_f64() * per_second >= limit.burst as f64;
+    }
+}
+
+// Token buckets per route rule for each client IP, and for each customer a request acts for. There are
+// no logins yet, so that identity is the customer in a /customers/<cid>/... path, as in the audit log,
+// or else the customer_id of a request body, taken by the Valid guard once the body is read.
+// A request is limited when either of its buckets is empty.
+// Clones share their buckets, one is managed as state for the Valid guard.
+#[derive(Clone)]
+pub struct RateLimiter {
+    // Set from Rocket.toml on ignite, the first matching rule applies
+    rules: Arc<OnceLock<Vec<Rule>>>,
+    trusted_proxies: Arc<OnceLock<Vec<IpAddr>>>,
+    buckets: Arc<Mutex<HashMap<(usize, String), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn fairing() -> RateLimiter {
+        RateLimiter {
+            rules: Arc::new(OnceLock::new()),
+            trusted_proxies: Arc::new(OnceLock::new()),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn take(&self, rule: usize, limit: Limit, keys: &[String], now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().expect("rate limit buckets should not be poisoned");
+        if buckets.len() >= MAX_BUCKETS {
+            let rules = self.rules.get().map(Vec::as_slice).unwrap_or_default();
+            buckets.retain(|(rule, _), bucket| !bucket.is_full(rules[*rule].limit, now));
+            if buckets.len() > BUCKETS_KEPT {
+                let mut updated = buckets.values().map(|bucket| bucket.updated).collect::<Vec<_>>();
+                // Newest first, so everything from BUCKETS_KEPT on is dropped
+                let (_, newest_dropped, _) = updated.select_nth_unstable_by(BUCKETS_KEPT, |a, b| b.cmp(a));
+                let newest_dropped = *newest_dropped;
+                buckets.retain(|_, bucket| bucket.updated > newest_dropped);
+            }
+        }
+        let mut retry_after = None;
+        for key in keys {
+            let bucket = buckets
+                .entry((rule, key.clone()))
+                .or_insert_with(|| Bucket { tokens: limit.burst as f64, updated: now });
+            if let Err(seconds) = bucket.take(limit, now) {
+                retry_after = retry_after.max(Some(seconds));
+            }
+        }
+        return match retry_after {
+            Some(seconds) => Err(seconds),
+            None => Ok(()),
+        };
+    }
+
+    fn limited(&self, req: &Request<'_>, rule: &Rule, keys: &[String], retry_after: u64) {
+        warn!(target: "file", "Rate limit {} exceeded by {} for {} {}, retry after {}s",
+              rule.name, keys.join(" "), req.method(), req.uri(), retry_after);
+        req.local_cache(|| Limited(Some(retry_after)));
+    }
+}
+
+// The socket peer, or the IP the peer's ip_header gives if it's a trusted proxy. Headers from anyone
+// else are ignored, as clients could otherwise send a new IP with each request to get a new bucket.
+pub fn client_ip(req: &Request<'_>) -> String {
+    let peer = req.remote().map(|remote| remote.ip());
+    let trusted = req.rocket().state::<RateLimiter>().and_then(|limiter| limiter.trusted_proxies.get());
+    let ip = match peer {
+        Some(peer) if trusted.is_some_and(|trusted| trusted.contains(&peer)) => req.client_ip(),
+        _ => peer,
+    };
+    return ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+}
+
+// Kept in the request's local cache, the seconds to wait if it was limited
+struct Limited(Option<u64>);
+
+// Kept in the request's local cache, the rule that applied to it
+struct Matched(Option<usize>);
+
+// Takes a token from the customer's bucket for a request whose body acts for them, when the path didn't
+// already name a customer. Gives the seconds to wait if it's empty, to be answered by the 429 catcher.
+pub fn limit_customer(req: &Request<'_>, cid: i64) -> Result<(), u64> {
+    let (limiter, index) = match (req.rocket().state::<RateLimiter>(), req.local_cache(|| Matched(None)).0) {
+        (Some(limiter), Some(index)) => (limiter, index),
+        _ => return Ok(()),
+    };
+    if customer_in_uri(req).is_some() {
+        return Ok(());
+    }
+    let rule = &limiter.rules.get().expect("rules are set on ignite")[index];
+    let keys = [format!("customer:{}", cid)];
+    if let Err(retry_after) = limiter.take(index, rule.limit, &keys, Instant::now()) {
+        limiter.limited(req, rule, &keys, retry_after);
+        return Err(retry_after);
+    }
+    return Ok(());
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info { name: "Rate limits", kind: Kind::Ignite | Kind::Request }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = match rocket.figment().extract::<RateLimitConfig>() {
+            Ok(config) => config,
+            Err(e) => {
+                error!(target: "file", "Invalid rate_limits in Rocket.toml: {}", e);
+                return Err(rocket);
+            },
+        };
+        let mut rules = Vec::new();
+        for (name, limit) in config.rate_limits {
+            match parse_rule(&name, limit) {
+                Some(rule) => rules.push(rule),
+                None => {
+                    error!(target: "file", "Invalid rate_limits entry in Rocket.toml, expected \"default\" or a method and path \
+                                           with a burst and per_minute above 0: {}", name);
+                    return Err(rocket);
+                },
+            };
+            info!(target: "file", "Rate limit {} set to {} at once and {} per minute", name, limit.burst, limit.per_minute);
+        }
+        // The most specific rule applies: fewest <...> segments first, the default last
+        rules.sort_by_key(|rule| (rule.name == "default", rule.segments.iter().filter(|s| s.starts_with('<')).count()));
+        let _ = self.rules.set(rules);
+        let _ = self.trusted_proxies.set(config.trusted_proxies);
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        let path = req.uri().path().segments().skip_while(|s| *s == "v1").collect::<Vec<_>>();
+        let rules = self.rules.get().map(Vec::as_slice).unwrap_or_default();
+        let (index, rule) = match rules.iter().enumerate().find(|(_, rule)| rule.matches(req.method(), &path)) {
+            Some(found) => found,
+            None => return,
+        };
+        req.local_cache(|| Matched(Some(index)));
+
+        let mut keys = vec![format!("ip:{}", client_ip(req))];
+        if let Some(cid) = customer_in_uri(req) {
+            keys.push(format!("customer:{}", cid));
+        }
+        if let Err(retry_after) = self.take(index, rule.limit, &keys, Instant::now()) {
+            self.limited(req, rule, &keys, retry_after);
+            req.set_uri(Origin::parse(LIMITED_PATH).expect("LIMITED_PATH should be a valid URI"));
+        }
+    }
+}
+
+fn parse_rule(name: &str, limit: Limit) -> Option<Rule> {
+    if limit.burst == 0 || limit.per_minute == 0 {
+        return None;
+    }
+    if name == "default" {
+        return Some(Rule { name: name.to_string(), method: None, segments: Vec::new(), limit });
+    }
+    let (method, path) = name.split_once(' ')?;
+    let method = method.parse::<Method>().ok()?;
+    if !path.starts_with('/') {
+        return None;
+    }
+    let segments = path.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect();
+    return Some(Rule { name: name.to_string(), method: Some(method), segments, limit });
+}
+
+// 429 with the seconds to wait, or 404 for requests to LIMITED_PATH that weren't limited
+pub struct TooManyRequests(Option<u64>);
+
+impl<'r> Responder<'r, 'static> for TooManyRequests {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let retry_after = match self.0 {
+            Some(seconds) => seconds,
+            None => return Response::build().status(Status::NotFound).ok(),
+        };
+        let body = format!("Too many requests, please retry after {} seconds", retry_after);
+        Response::build()
+            .status(Status::TooManyRequests)
+            .header(Header::new("Retry-After", retry_after.to_string()))
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+#[catch(404)]
+pub fn too_many_requests(req: &Request) -> TooManyRequests {
+    return TooManyRequests(req.local_cache(|| Limited(None)).0);
+}
+
+// Requests the Valid guard limited by the customer in their body
+#[catch(429)]
+pub fn customer_limited(req: &Request) -> TooManyRequests {
+    return TooManyRequests(Some(req.local_cache(|| Limited(None)).0.unwrap_or(1)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const LIMIT: Limit = Limit { burst: 2, per_minute: 60 };
+
+    fn limiter(limit: Limit) -> RateLimiter {
+        let limiter = RateLimiter::fairing();
+        let _ = limiter.rules.set(vec![parse_rule("default", limit).unwrap()]);
+        return limiter;
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        return keys.iter().map(|key| key.to_string()).collect();
+    }
+
+    #[test]
+    fn buckets_refill_up_to_the_burst() {
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+        let mut bucket = Bucket { tokens: 2.0, updated: start };
+        assert_eq!(bucket.take(LIMIT, at(0)), Ok(()));
+        assert_eq!(bucket.take(LIMIT, at(0)), Ok(()));
+        assert_eq!(bucket.take(LIMIT, at(0)), Err(1));
+        assert_eq!(bucket.take(LIMIT, at(500)), Err(1));
+        assert_eq!(bucket.take(LIMIT, at(1000)), Ok(()));
+        // An hour refills the burst and no more
+        assert_eq!(bucket.take(LIMIT, at(3_601_000)), Ok(()));
+        assert_eq!(bucket.take(LIMIT, at(3_601_000)), Ok(()));
+        assert_eq!(bucket.take(LIMIT, at(3_601_000)), Err(1));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        let start = Instant::now();
+        let limit = Limit { burst: 1, per_minute: 7 };
+        let mut bucket = Bucket { tokens: 0.0, updated: start };
+        // A token every 60 / 7 = 8.57 seconds
+        assert_eq!(bucket.take(limit, start), Err(9));
+        assert_eq!(bucket.take(limit, start + Duration::from_secs(8)), Err(1));
+        assert_eq!(bucket.take(limit, start + Duration::from_secs(9)), Ok(()));
+    }
+
+    #[test]
+    fn requests_are_limited_when_either_bucket_is_empty() {
+        let now = Instant::now();
+        let limit = Limit { burst: 1, per_minute: 6 };
+        let limiter = limiter(limit);
+        assert_eq!(limiter.take(0, limit, &keys(&["ip:a", "customer:1"]), now), Ok(()));
+        // Another IP acting for the same customer waits for the customer's bucket
+        assert_eq!(limiter.take(0, limit, &keys(&["ip:b", "customer:1"]), now), Err(10));
+        assert_eq!(limiter.take(0, limit, &keys(&["ip:c", "customer:2"]), now), Ok(()));
+    }
+
+    #[test]
+    fn full_buckets_are_swept_first() {
+        let start = Instant::now();
+        let limiter = limiter(LIMIT);
+        for i in 0..MAX_BUCKETS {
+            limiter.take(0, LIMIT, &keys(&[&format!("ip:{}", i)]), start).unwrap();
+        }
+        // Two seconds later every bucket has refilled, so all of them go
+        limiter.take(0, LIMIT, &keys(&["ip:new"]), start + Duration::from_secs(2)).unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.keys().map(|(_, key)| key.as_str()).collect::<Vec<_>>(), vec!["ip:new"]);
+    }
+
+    #[test]
+    fn least_recently_used_buckets_are_swept_when_none_are_full() {
+        let start = Instant::now();
+        let limit = Limit { burst: 1, per_minute: 1 };
+        let limiter = limiter(limit);
+        for i in 0..MAX_BUCKETS {
+            limiter.take(0, limit, &keys(&[&format!("ip:{}", i)]), start + Duration::from_millis(i as u64)).unwrap();
+        }
+        limiter.take(0, limit, &keys(&["ip:new"]), start + Duration::from_secs(11)).unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), BUCKETS_KEPT + 1);
+        assert!(!buckets.contains_key(&(0, format!("ip:{}", MAX_BUCKETS - BUCKETS_KEPT - 1))));
+        assert!(buckets.contains_key(&(0, format!("ip:{}", MAX_BUCKETS - BUCKETS_KEPT))));
+    }
+}
@@ -0,0 +1,141 @@
+use rocket::fairing::{AdHoc, Fairing};
+use serde::Deserialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::{warn, Record};
+use log4rs::config::Deserializers;
+use log4rs::encode::{self, Encode, EncoderConfig};
+
+// Set once from Rocket.toml, read by RedactingEncoder as each line is written
+static LOG_PII: AtomicBool = AtomicBool::new(false);
+
+// Put around a name or address by Pii, so only the encoder decides how it is written
+const MASK_START: char = '\u{1}';
+const MASK_END: char = '\u{2}';
+
+// Read from the `log_pii` key in Rocket.toml
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RedactionConfig {
+    #[serde(default)]
+    log_pii: bool,
+}
+
+impl RedactionConfig {
+    pub fn fairing() -> impl Fairing {
+        AdHoc::on_ignite("Log redaction", |rocket| async move {
+            let config = rocket.figment().extract::<RedactionConfig>().unwrap_or_default();
+            LOG_PII.store(config.log_pii, Ordering::Relaxed);
+            if config.log_pii {
+                warn!(target: "file", "log_pii is set, names and addresses are logged unmasked");
+            }
+            rocket
+        })
+    }
+}
+
+// Request fields holding names and addresses, matched without any "address." prefix
+const PII_FIELDS: &[&str] = &["name", "shipping_address", "line1", "line2", "city", "postal_code"];
+
+// A name or address as written to the log: "Ann Lee" becomes "A*** L***" unless log_pii is set
+pub struct Pii<T> {
+    value: T,
+    mask: bool,
+}
+
+pub fn pii<T: fmt::Display>(value: T) -> Pii<T> {
+    return Pii { value, mask: true };
+}
+
+// Masks the value only if the field holds a name or address, so "Invalid title" lines stay readable
+pub fn field_value<T: fmt::Display>(field: &str, value: T) -> Pii<T> {
+    let name = field.rsplit('.').next().unwrap_or(field);
+    return Pii { value, mask: PII_FIELDS.contains(&name) };
+}
+
+impl<T: fmt::Display> fmt::Display for Pii<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Marks in the value itself would let it unmask its own end
+        let value = self.value.to_string().replace([MASK_START, MASK_END], "");
+        if !self.mask {
+            return write!(f, "{}", value);
+        }
+        write!(f, "{}{}{}", MASK_START, value, MASK_END)
+    }
+}
+
+// Masks every marked value in a log message, or only drops the marks if log_pii is set
+pub fn redact(message: &str) -> String {
+    let unmasked = LOG_PII.load(Ordering::Relaxed);
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find(MASK_START) {
+        redacted.push_str(&rest[..start]);
+        let marked = &rest[start + MASK_START.len_utf8()..];
+        let end = marked.find(MASK_END).unwrap_or(marked.len());
+        if unmasked {
+            redacted.push_str(&marked[..end]);
+        } else {
+            redacted.push_str(&mask(&marked[..end]));
+        }
+        rest = marked.get(end + MASK_END.len_utf8()..).unwrap_or("");
+    }
+    redacted.push_str(rest);
+    return redacted;
+}
+
+// The first character of each word is kept so lines about different people can be told apart
+fn mask(value: &str) -> String {
+    return value.split_whitespace()
+        .map(|word| word.chars().next().map(|c| format!("{}***", c)).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+// Redacts each line before the wrapped encoder writes it, for every appender it's configured on
+#[derive(Debug)]
+pub struct RedactingEncoder(Box<dyn Encode>);
+
+impl RedactingEncoder {
+    pub fn new(encoder: Box<dyn Encode>) -> Self {
+        return RedactingEncoder(encoder);
+    }
+}
+
+impl Encode for RedactingEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        let message = redact(&record.args().to_string());
+        return self.0.encode(w, &Record::builder()
+            .args(format_args!("{}", message))
+            .level(record.level())
+            .target(record.target())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line())
+            .build());
+    }
+}
+
+// `kind: redacted` in log4rs.yml, around the encoder given under `encoder`
+#[derive(Deserialize)]
+pub struct RedactingEncoderConfig {
+    encoder: EncoderConfig,
+}
+
+struct RedactingEncoderDeserializer;
+
+impl log4rs::config::Deserialize for RedactingEncoderDeserializer {
+    type Trait = dyn Encode;
+    type Config = RedactingEncoderConfig;
+
+    fn deserialize(&self, config: RedactingEncoderConfig, deserializers: &Deserializers) -> anyhow::Result<Box<dyn Encode>> {
+        let encoder = deserializers.deserialize(&config.encoder.kind, config.encoder.config)?;
+        return Ok(Box::new(RedactingEncoder(encoder)));
+    }
+}
+
+// log4rs' own kinds and `redacted`, for log4rs::init_file
+pub fn deserializers() -> Deserializers {
+    let mut deserializers = Deserializers::default();
+    deserializers.insert("redacted", RedactingEncoderDeserializer);
+    return deserializers;
+}
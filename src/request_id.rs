@@ -0,0 +1,134 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{self, FromRequest};
+use rocket::{Data, Request, Response};
+use std::collections::hash_map::RandomState;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use log::{info, warn};
+
+use crate::db::audit::Actor;
+
+const HEADER: &str = "X-Request-Id";
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+// Tags every log line written while handling a request with its X-Request-Id, through the log4rs MDC.
+// The route and customer id are added once the body has been read (see Valid), and every request
+// ends with one line carrying all of them and the duration.
+pub struct RequestIds;
+
+// Kept in the request's local cache
+struct RequestContext {
+    id: String,
+    started: Instant,
+}
+
+// The customer a request acts for, from its body or its <cid> path segment
+pub struct CustomerId(pub Option<i64>);
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info { name: "Request ids", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        // A caller's id is kept so one request can be followed across services
+        let given = req.headers().get_one(HEADER).map(str::to_string);
+        let id = match &given {
+            Some(id) if is_valid(id) => id.clone(),
+            _ => new_request_id(),
+        };
+        log_mdc::clear();
+        log_mdc::insert("request_id", &id);
+        // Until routing tells us otherwise, /customers/<cid>/... acts for that customer, see restore
+        if let Some(cid) = customer_in_uri(req) {
+            log_mdc::insert("customer_id", cid.to_string());
+        }
+        if let Some(invalid) = given.filter(|given| *given != id) {
+            warn!(target: "file", "Invalid {} header replaced: {}", HEADER, invalid);
+        }
+        req.local_cache(|| RequestContext { id, started: Instant::now() });
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let context = req.local_cache(|| RequestContext { id: new_request_id(), started: Instant::now() });
+        res.set_header(Header::new(HEADER, context.id.clone()));
+
+        // The handler may have run on another thread, so the MDC is filled in again for the closing line
+        restore(req);
+        let duration_ms = context.started.elapsed().as_secs_f64() * 1000.0;
+        log_mdc::insert("duration_ms", format!("{:.3}", duration_ms));
+        log_mdc::insert("status", res.status().code.to_string());
+        info!(target: "file", "{} {} answered {} in {:.3}ms", req.method(), req.uri(), res.status().code, duration_ms);
+        log_mdc::clear();
+    }
+}
+
+// Sets the MDC for the request being handled on this thread, called again after anything that may have
+// moved the request to another worker thread, such as reading the body
+pub fn restore(req: &Request<'_>) {
+    let context = req.local_cache(|| RequestContext { id: new_request_id(), started: Instant::now() });
+    log_mdc::clear();
+    log_mdc::insert("request_id", &context.id);
+    if let Some(route) = req.route() {
+        log_mdc::insert("route", route.uri.as_str());
+    }
+    if let CustomerId(Some(cid)) = req.local_cache(|| CustomerId(customer_in_path(req))) {
+        log_mdc::insert("customer_id", cid.to_string());
+    }
+}
+
+// Who audit events of the request are recorded as, the customer its path names if any, and its request id.
+// Request guards run before the body is read, so handlers whose body names the customer use Actor::for_customer.
+pub struct RequestActor(Actor);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestActor {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let context = req.local_cache(|| RequestContext { id: new_request_id(), started: Instant::now() });
+        request::Outcome::Success(RequestActor(Actor::request(customer_in_path(req), &context.id)))
+    }
+}
+
+impl Deref for RequestActor {
+    type Target = Actor;
+
+    fn deref(&self) -> &Actor {
+        &self.0
+    }
+}
+
+fn customer_in_path(req: &Request<'_>) -> Option<i64> {
+    let route = req.route()?;
+    let position = route.uri.path().split('/').filter(|s| !s.is_empty()).position(|s| s == "<cid>")?;
+    // Counted from the root, routed_segment would count from the mount point
+    return req.uri().path().segments().get(position).and_then(|s| s.parse().ok());
+}
+
+pub fn customer_in_uri(req: &Request<'_>) -> Option<i64> {
+    let mut segments = req.uri().path().segments().skip_while(|s| *s == "v1" || *s == "v2");
+    return match (segments.next(), segments.next()) {
+        (Some("customers"), Some(cid)) => cid.parse().ok(),
+        _ => None,
+    };
+}
+
+// Ids are echoed into logs and headers, so only short ids of URL-safe characters are kept
+fn is_valid(id: &str) -> bool {
+    return !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+}
+
+fn new_request_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(NEXT_REQUEST.fetch_add(1, Ordering::Relaxed));
+    let high = hasher.finish();
+    hasher.write_u64(high);
+    return format!("{:016x}{:016x}", high, hasher.finish());
+}
@@ -0,0 +1,75 @@
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::shield::{Hsts, Shield};
+use rocket::time::Duration;
+use rocket::{Build, Request, Response, Rocket};
+use serde::Deserialize;
+use std::sync::OnceLock;
+use log::{error, info};
+
+// RapiDoc on /docs is loaded from unpkg and styles itself inline
+const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' https://unpkg.com; style-src 'self' 'unsafe-inline'; \
+                           img-src 'self' data:; connect-src 'self'; frame-ancestors 'none'";
+
+// Read from the `security_headers` table of the profile in Rocket.toml
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+struct SecurityHeadersConfig {
+    // Seconds browsers should only use HTTPS for, 0 sends no Strict-Transport-Security
+    hsts_max_age: i64,
+    // Sent with HTML pages
+    content_security_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> SecurityHeadersConfig {
+        SecurityHeadersConfig { hsts_max_age: 0, content_security_policy: DEFAULT_CSP.to_string() }
+    }
+}
+
+// Replaces Rocket's default Shield with one adding HSTS when configured, which keeps X-Content-Type-Options: nosniff,
+// X-Frame-Options and Permissions-Policy, and adds a Content-Security-Policy to HTML responses
+pub struct SecurityHeaders {
+    // Set from Rocket.toml on ignite
+    csp: OnceLock<String>,
+}
+
+impl SecurityHeaders {
+    pub fn fairing() -> SecurityHeaders {
+        SecurityHeaders { csp: OnceLock::new() }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info { name: "Security headers", kind: Kind::Ignite | Kind::Response }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let config = match rocket.figment().extract_inner::<SecurityHeadersConfig>("security_headers") {
+            Ok(config) => config,
+            Err(e) if e.missing() => SecurityHeadersConfig::default(),
+            Err(e) => {
+                error!(target: "file", "Invalid security_headers in Rocket.toml: {}", e);
+                return Err(rocket);
+            },
+        };
+        let mut shield = Shield::default();
+        if config.hsts_max_age > 0 {
+            info!(target: "file", "Strict-Transport-Security max-age set to {}", config.hsts_max_age);
+            shield = shield.enable(Hsts::Enable(Duration::seconds(config.hsts_max_age)));
+        }
+        let _ = self.csp.set(config.content_security_policy);
+        Ok(rocket.attach(shield))
+    }
+
+    async fn on_response<'r>(&self, _req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.content_type() != Some(ContentType::HTML) {
+            return;
+        }
+        if let Some(csp) = self.csp.get().filter(|csp| !csp.is_empty()) {
+            res.set_header(Header::new("Content-Security-Policy", csp.clone()));
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use log::{error, info, warn};
+
+use crate::dates;
+use crate::db::addresses::AddressFields;
+use crate::db::promotions::OrderPricing;
+
+const DEFAULT_RULES_PATH: &str = "shipping.toml";
+// Zones listing this take every country no other zone lists, and addresses without a country
+const ANY_COUNTRY: &str = "*";
+
+// Read from the `shipping_rules` key in Rocket.toml, the path of the rules file
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ShippingConfig {
+    shipping_rules: Option<String>,
+}
+
+// What a method costs for up to items copies
+#[derive(Deserialize, Debug, Clone)]
+struct Tier {
+    items: i64,
+    cost: f64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Method {
+    name: String,
+    // Business days from ordering to delivery, the fewest and the most
+    days: (u32, u32),
+    // Orders of at least this much after discounts ship free
+    free_over: Option<f64>,
+    // Cost tables by zone, the method only shipping to the zones listed
+    zones: BTreeMap<String, Vec<Tier>>,
+}
+
+// The rules file. Zones are lists of two letter country codes, and methods are by the key orders give as shipping_method.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ShippingRules {
+    default_method: Option<String>,
+    #[serde(default)]
+    zones: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    methods: BTreeMap<String, Method>,
+}
+
+// How an order ships, what it costs and when it should arrive, dates being of form YYYY-MM-DD
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ShippingQuote {
+    pub method: String,
+    pub name: String,
+    pub cost: f64,
+    pub earliest_delivery: String,
+    pub latest_delivery: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShippingRejected {
+    Unknown(String),
+    // The method and the destination's country
+    Unavailable(String, String),
+    // No method was given and there's no default_method
+    NoMethod,
+}
+
+impl fmt::Display for ShippingRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShippingRejected::Unknown(method) => write!(f, "No shipping method {}", method),
+            ShippingRejected::Unavailable(method, country) if country.is_empty() => {
+                write!(f, "Shipping method {} doesn't ship to this address", method)
+            },
+            ShippingRejected::Unavailable(method, country) => write!(f, "Shipping method {} doesn't ship to {}", method, country),
+            ShippingRejected::NoMethod => write!(f, "Please give a shipping_method"),
+        }
+    }
+}
+
+impl ShippingRules {
+    // Loaded once at launch, which fails if the file is invalid. Without a file orders ship free.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Shipping rules", |rocket| async move {
+            let path = rocket.figment().extract::<ShippingConfig>().unwrap_or_default().shipping_rules
+                .unwrap_or_else(|| DEFAULT_RULES_PATH.to_string());
+            return match ShippingRules::load(Path::new(&path)) {
+                Ok(rules) => Ok(rocket.manage(rules)),
+                Err(e) => {
+                    error!(target: "file", "Invalid shipping rules in {}: {}", path, e);
+                    Err(rocket)
+                },
+            };
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<ShippingRules, String> {
+        if !path.exists() {
+            warn!(target: "file", "No shipping rules at {}, orders ship free", path.display());
+            return Ok(ShippingRules::default());
+        }
+        let rules = ShippingRules::from_figment(Figment::from(Toml::file(path)))?;
+        info!(target: "file", "Loaded {} shipping methods to {} zones from {}", rules.methods.len(), rules.zones.len(), path.display());
+        return Ok(rules);
+    }
+
+    // Checks the rules, upper-casing country codes and sorting each cost table by items
+    fn from_figment(figment: Figment) -> Result<ShippingRules, String> {
+        let mut rules = figment.extract::<ShippingRules>().map_err(|e| e.to_string())?;
+        let mut zoned = BTreeMap::new();
+        for (zone, countries) in &mut rules.zones {
+            for country in countries.iter_mut() {
+                *country = country.to_uppercase();
+                if country != ANY_COUNTRY && (country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic())) {
+                    return Err(format!("{} in zone {} isn't a two letter country code or {}", country, zone, ANY_COUNTRY));
+                }
+                if let Some(other) = zoned.insert(country.clone(), zone.clone()) {
+                    return Err(format!("{} is in both zones {} and {}", country, other, zone));
+                }
+            }
+        }
+        for (key, method) in &mut rules.methods {
+            if method.days.0 > method.days.1 {
+                return Err(format!("The days of {} must be the fewest then the most", key));
+            }
+            for (zone, tiers) in &mut method.zones {
+                if !rules.zones.contains_key(zone) {
+                    return Err(format!("{} ships to zone {}, which isn't in zones", key, zone));
+                }
+                if tiers.is_empty() || tiers.iter().any(|tier| tier.items < 1 || !tier.cost.is_finite() || tier.cost < 0.0) {
+                    return Err(format!("The costs of {} to {} must have items of 1 or more and costs of 0 or more", key, zone));
+                }
+                tiers.sort_by_key(|tier| tier.items);
+            }
+        }
+        if let Some(default) = rules.default_method.as_ref().filter(|m| !rules.methods.contains_key(*m)) {
+            return Err(format!("default_method {} isn't in methods", default));
+        }
+        return Ok(rules);
+    }
+
+    fn zone(&self, country: &str) -> Option<&str> {
+        let country = country.to_uppercase();
+        let listing = |code: &str| self.zones.iter().find(|(_, countries)| countries.iter().any(|c| c == code));
+        return listing(&country).or_else(|| listing(ANY_COUNTRY)).map(|(zone, _)| zone.as_str());
+    }
+
+    // What shipping quantity copies worth goods_total after discounts by the method costs, if it ships to the destination
+    pub fn quote(&self, key: &str, destination: &AddressFields, quantity: i64, goods_total: f64) -> Result<ShippingQuote, ShippingRejected> {
+        let method = self.methods.get(key).ok_or_else(|| ShippingRejected::Unknown(key.to_string()))?;
+        let tiers = self.zone(&destination.country)
+            .and_then(|zone| method.zones.get(zone))
+            .ok_or_else(|| ShippingRejected::Unavailable(key.to_string(), destination.country.clone()))?;
+        // The last tier covers any more copies
+        let tier = tiers.iter().find(|tier| tier.items >= quantity).or(tiers.last()).expect("load rejects empty cost tables");
+        let free = method.free_over.is_some_and(|free_over| goods_total >= free_over);
+        let today = dates::today();
+        return Ok(ShippingQuote {
+            method: key.to_string(),
+            name: method.name.clone(),
+            cost: if free { 0.0 } else { tier.cost },
+            earliest_delivery: dates::format_date(today + calendar_days(today, method.days.0)),
+            latest_delivery: dates::format_date(today + calendar_days(today, method.days.1)),
+        });
+    }
+
+    // Every method that ships the order to the destination, cheapest first
+    pub fn options(&self, destination: &AddressFields, quantity: i64, goods_total: f64) -> Vec<ShippingQuote> {
+        let mut quotes = self.methods.keys()
+            .filter_map(|key| self.quote(key, destination, quantity, goods_total).ok())
+            .collect::<Vec<_>>();
+        quotes.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+        return quotes;
+    }
+
+    // Adds shipping by the method, or the default_method if none is given, to the pricing and its total.
+    // Without any methods nothing is added.
+    pub fn apply(&self, pricing: &mut OrderPricing, method: Option<&str>, destination: &AddressFields) -> Result<(), ShippingRejected> {
+        if self.methods.is_empty() {
+            return Ok(());
+        }
+        let key = method.or(self.default_method.as_deref()).ok_or(ShippingRejected::NoMethod)?;
+        let quote = self.quote(key, destination, pricing.quantity, pricing.total)?;
+        pricing.total = ((pricing.total + quote.cost) * 100.0).round() / 100.0;
+        pricing.shipping = Some(quote);
+        return Ok(());
+    }
+}
+
+// Days from the day until business_days weekdays have passed
+fn calendar_days(day: i64, business_days: u32) -> i64 {
+    let mut days = 0;
+    let mut left = business_days;
+    while left > 0 {
+        days += 1;
+        // 2 and 3 are Saturday and Sunday, see dates
+        if !matches!((day + days).rem_euclid(7), 2 | 3) {
+            left -= 1;
+        }
+    }
+    return days;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{destination, pricing};
+
+    // Checked as load does, so country codes are upper-cased and cost tables sorted
+    fn load(toml: &str) -> Result<ShippingRules, String> {
+        return ShippingRules::from_figment(Figment::from(Toml::string(toml)));
+    }
+
+    fn rules(toml: &str) -> ShippingRules {
+        return load(toml).unwrap();
+    }
+
+    const RULES: &str = r#"
+        default_method = "standard"
+
+        [zones]
+        domestic = ["us"]
+        north_america = ["CA", "MX"]
+
+        [methods.standard]
+        name = "Standard"
+        days = [3, 5]
+        free_over = 35
+        [methods.standard.zones]
+        domestic = [{ items = 10, cost = 8.99 }, { items = 1, cost = 3.99 }, { items = 3, cost = 5.99 }]
+        north_america = [{ items = 1, cost = 7.99 }]
+
+        [methods.express]
+        name = "Express"
+        days = [1, 1]
+        [methods.express.zones]
+        domestic = [{ items = 1, cost = 14.99 }]
+    "#;
+
+    #[test]
+    fn tiers_are_picked_by_the_fewest_items_covering_the_quantity() {
+        let rules = rules(RULES);
+        let cost = |quantity: i64| rules.quote("standard", &destination("US", None), quantity, 10.0).unwrap().cost;
+        assert_eq!(cost(1), 3.99);
+        assert_eq!(cost(2), 5.99);
+        assert_eq!(cost(3), 5.99);
+        assert_eq!(cost(10), 8.99);
+        // The last tier covers any more copies
+        assert_eq!(cost(25), 8.99);
+    }
+
+    #[test]
+    fn orders_of_free_over_ship_free() {
+        let rules = rules(RULES);
+        assert_eq!(rules.quote("standard", &destination("US", None), 1, 35.0).unwrap().cost, 0.0);
+        assert_eq!(rules.quote("standard", &destination("US", None), 1, 34.99).unwrap().cost, 3.99);
+        assert_eq!(rules.quote("express", &destination("US", None), 1, 100.0).unwrap().cost, 14.99);
+    }
+
+    #[test]
+    fn methods_only_ship_to_their_zones() {
+        let rules = rules(RULES);
+        assert_eq!(rules.quote("standard", &destination("mx", None), 1, 10.0).unwrap().cost, 7.99);
+        assert_eq!(rules.quote("express", &destination("CA", None), 1, 10.0),
+                   Err(ShippingRejected::Unavailable("express".to_string(), "CA".to_string())));
+        assert_eq!(rules.quote("standard", &destination("", None), 1, 10.0),
+                   Err(ShippingRejected::Unavailable("standard".to_string(), String::new())));
+        assert_eq!(rules.quote("overnight", &destination("US", None), 1, 10.0), Err(ShippingRejected::Unknown("overnight".to_string())));
+        let methods = rules.options(&destination("US", None), 1, 10.0).into_iter().map(|q| q.method).collect::<Vec<_>>();
+        assert_eq!(methods, vec!["standard", "express"]);
+    }
+
+    #[test]
+    fn any_country_takes_countries_no_zone_lists() {
+        let rules = rules(&RULES.replace("north_america = [\"CA\", \"MX\"]", "north_america = [\"CA\", \"MX\"]\nrest = [\"*\"]")
+            .replace("north_america = [{ items = 1, cost = 7.99 }]", "north_america = [{ items = 1, cost = 7.99 }]\nrest = [{ items = 1, cost = 12.99 }]"));
+        assert_eq!(rules.quote("standard", &destination("FR", None), 1, 10.0).unwrap().cost, 12.99);
+        assert_eq!(rules.quote("standard", &destination("", None), 1, 10.0).unwrap().cost, 12.99);
+        assert_eq!(rules.quote("standard", &destination("CA", None), 1, 10.0).unwrap().cost, 7.99);
+    }
+
+    #[test]
+    fn apply_adds_the_default_method() {
+        let with_default = rules(RULES);
+        let mut order = pricing(2, 20.0);
+        with_default.apply(&mut order, None, &destination("US", None)).unwrap();
+        assert_eq!(order.shipping.as_ref().map(|q| q.method.as_str()), Some("standard"));
+        assert_eq!(order.total, 25.99);
+
+        let without_default = rules(&RULES.replace("default_method = \"standard\"", ""));
+        assert_eq!(without_default.apply(&mut pricing(1, 10.0), None, &destination("US", None)), Err(ShippingRejected::NoMethod));
+        let mut order = pricing(1, 10.0);
+        ShippingRules::default().apply(&mut order, None, &destination("US", None)).unwrap();
+        assert_eq!((order.shipping, order.total), (None, 10.0));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let error = load(&RULES.replace("[\"CA\", \"MX\"]", "[\"CA\", \"US\"]")).unwrap_err();
+        assert_eq!(error, "US is in both zones domestic and north_america");
+        let error = load(&RULES.replace("days = [3, 5]", "days = [5, 3]")).unwrap_err();
+        assert_eq!(error, "The days of standard must be the fewest then the most");
+        let error = load(&RULES.replace("default_method = \"standard\"", "default_method = \"slow\"")).unwrap_err();
+        assert_eq!(error, "default_method slow isn't in methods");
+    }
+
+    #[test]
+    fn business_days_skip_weekends() {
+        // Day 0, 1970-01-01, was a Thursday
+        assert_eq!(calendar_days(0, 0), 0);
+        assert_eq!(calendar_days(0, 1), 1);
+        assert_eq!(calendar_days(0, 2), 4);
+        assert_eq!(calendar_days(1, 1), 3);
+        assert_eq!(calendar_days(2, 1), 2);
+        assert_eq!(calendar_days(0, 5), 7);
+        // 2026-10-19 is a Monday
+        assert_eq!(calendar_days(20_745, 5), 7);
+    }
+}
@@ -0,0 +1,257 @@
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use log::{error, info, warn};
+
+use crate::db::addresses::AddressFields;
+use crate::db::books::EditionFormat;
+use crate::db::promotions::OrderPricing;
+
+const DEFAULT_RULES_PATH: &str = "tax.toml";
+
+// Read from the `tax_rules` key in Rocket.toml, the path of the rules file
+#[derive(Deserialize, Debug, Clone, Default)]
+struct TaxConfig {
+    tax_rules: Option<String>,
+}
+
+// Whether book prices already include tax, or it's added to them at checkout
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PricingMode {
+    #[default]
+    Exclusive,
+    Inclusive,
+}
+
+// Percentages. rate applies to shipping, books to books in place of rate, and formats to editions of that format in place of books.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct Rates {
+    rate: Option<f64>,
+    books: Option<f64>,
+    #[serde(default)]
+    formats: HashMap<EditionFormat, f64>,
+}
+
+impl Rates {
+    fn for_book(&self, format: Option<EditionFormat>) -> Option<f64> {
+        return format.and_then(|f| self.formats.get(&f).copied()).or(self.books).or(self.rate);
+    }
+
+    fn all(&self) -> impl Iterator<Item = f64> + '_ {
+        return self.rate.into_iter().chain(self.books).chain(self.formats.values().copied());
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct CountryRates {
+    #[serde(flatten)]
+    rates: Rates,
+    // By state or province code, each replacing the country's rates where it sets them
+    #[serde(default)]
+    regions: BTreeMap<String, Rates>,
+}
+
+// The rules file, by two letter country code. Destinations with no rate aren't taxed.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TaxRules {
+    #[serde(default)]
+    pricing: PricingMode,
+    #[serde(default)]
+    countries: BTreeMap<String, CountryRates>,
+}
+
+// The tax on one line of an order
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TaxLine {
+    // "book" for the book's copies or "shipping"
+    pub line: String,
+    // The country code, with the region code after a hyphen when its rate applied, such as US-NY
+    pub jurisdiction: String,
+    pub rate: f64,
+    // What the rate was applied to, the copies after discounts or the shipping cost
+    pub taxable: f64,
+    pub amount: f64,
+    // Whether the amount was part of the price rather than added to it
+    pub included: bool,
+}
+
+impl TaxRules {
+    // Loaded once at launch, which fails if the file is invalid. Without a file no tax is charged.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Tax rules", |rocket| async move {
+            let path = rocket.figment().extract::<TaxConfig>().unwrap_or_default().tax_rules
+                .unwrap_or_else(|| DEFAULT_RULES_PATH.to_string());
+            return match TaxRules::load(Path::new(&path)) {
+                Ok(rules) => Ok(rocket.manage(rules)),
+                Err(e) => {
+                    error!(target: "file", "Invalid tax rules in {}: {}", path, e);
+                    Err(rocket)
+                },
+            };
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<TaxRules, String> {
+        if !path.exists() {
+            warn!(target: "file", "No tax rules at {}, orders are not taxed", path.display());
+            return Ok(TaxRules::default());
+        }
+        let rules = TaxRules::from_figment(Figment::from(Toml::file(path)))?;
+        info!(target: "file", "Loaded tax rules for {} countries from {}, prices {} tax", rules.countries.len(), path.display(),
+              match rules.pricing { PricingMode::Exclusive => "exclude", PricingMode::Inclusive => "include" });
+        return Ok(rules);
+    }
+
+    // Checks the country codes and rates, upper-casing the codes
+    fn from_figment(figment: Figment) -> Result<TaxRules, String> {
+        let rules = figment.extract::<TaxRules>().map_err(|e| e.to_string())?;
+        let mut countries = BTreeMap::new();
+        for (country, mut rates) in rules.countries {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(format!("{} isn't a two letter country code", country));
+            }
+            rates.regions = rates.regions.into_iter().map(|(region, rates)| (region.to_uppercase(), rates)).collect();
+            let all = rates.rates.all().chain(rates.regions.values().flat_map(Rates::all)).collect::<Vec<_>>();
+            if let Some(rate) = all.into_iter().find(|rate| !(0.0..=100.0).contains(rate)) {
+                return Err(format!("The rates of {} must be percentages from 0 to 100, not {}", country, rate));
+            }
+            countries.insert(country.to_uppercase(), rates);
+        }
+        return Ok(TaxRules { pricing: rules.pricing, countries });
+    }
+
+    // The jurisdiction and rate the destination's region has for what rates picks, or else its country
+    fn rate(&self, destination: &AddressFields, rates: impl Fn(&Rates) -> Option<f64>) -> Option<(String, f64)> {
+        let country_code = destination.country.to_uppercase();
+        let country = self.countries.get(&country_code)?;
+        let region_code = destination.region.as_deref().unwrap_or_default().to_uppercase();
+        if let Some(rate) = country.regions.get(&region_code).and_then(&rates) {
+            return Some((format!("{}-{}", country_code, region_code), rate));
+        }
+        return rates(&country.rates).map(|rate| (country_code, rate));
+    }
+
+    // Adds the tax on the book and on any shipping cost to the pricing, and to its total unless prices include it.
+    // Destinations without a rate, such as free text addresses with no country, get no tax line.
+    pub fn apply(&self, pricing: &mut OrderPricing, destination: &AddressFields, format: Option<EditionFormat>) {
+        let shipping = pricing.shipping.as_ref().map(|quote| quote.cost).unwrap_or(0.0);
+        let mut lines = vec![("book", cents(pricing.total - shipping), self.rate(destination, |rates| rates.for_book(format)))];
+        if shipping > 0.0 {
+            lines.push(("shipping", shipping, self.rate(destination, |rates| rates.rate)));
+        }
+        let included = self.pricing == PricingMode::Inclusive;
+        for (line, taxable, found) in lines {
+            let Some((jurisdiction, rate)) = found else { continue };
+            let amount = match included {
+                true => cents(taxable * rate / (100.0 + rate)),
+                false => cents(taxable * rate / 100.0),
+            };
+            if !included {
+                pricing.total = cents(pricing.total + amount);
+            }
+            pricing.taxes.push(TaxLine { line: line.to_string(), jurisdiction, rate, taxable, amount, included });
+        }
+    }
+}
+
+// Amounts are kept to the cent
+fn cents(amount: f64) -> f64 {
+    return (amount * 100.0).round() / 100.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{destination, pricing, shipped};
+
+    // Checked as load does, so country and region codes are upper-cased
+    fn load(toml: &str) -> Result<TaxRules, String> {
+        return TaxRules::from_figment(Figment::from(Toml::string(toml)));
+    }
+
+    fn rules(toml: &str) -> TaxRules {
+        return load(toml).unwrap();
+    }
+
+    fn lines(pricing: &OrderPricing) -> Vec<(&str, &str, f64, f64, f64)> {
+        return pricing.taxes.iter().map(|t| (t.line.as_str(), t.jurisdiction.as_str(), t.rate, t.taxable, t.amount)).collect();
+    }
+
+    const RULES: &str = r#"
+        [countries.de]
+        rate = 19
+        books = 7
+        formats = { ebook = 19 }
+
+        [countries.CA]
+        rate = 5
+
+        [countries.CA.regions.on]
+        rate = 13
+
+        [countries.US.regions.NY]
+        rate = 4
+    "#;
+
+    #[test]
+    fn exclusive_tax_is_added_to_the_total() {
+        let rules = rules(RULES);
+        let mut order = shipped(pricing(1, 20.0), 5.0);
+        rules.apply(&mut order, &destination("DE", None), Some(EditionFormat::Hardcover));
+        assert_eq!(lines(&order), vec![("book", "DE", 7.0, 20.0, 1.4), ("shipping", "DE", 19.0, 5.0, 0.95)]);
+        assert!(order.taxes.iter().all(|t| !t.included));
+        assert_eq!(order.total, 27.35);
+    }
+
+    #[test]
+    fn inclusive_tax_is_taken_out_of_the_price() {
+        let rules = rules(&format!("pricing = \"inclusive\"\n{}", RULES));
+        let mut order = pricing(1, 21.4);
+        rules.apply(&mut order, &destination("DE", None), None);
+        assert_eq!(lines(&order), vec![("book", "DE", 7.0, 21.4, 1.4)]);
+        assert!(order.taxes[0].included);
+        assert_eq!(order.total, 21.4);
+    }
+
+    #[test]
+    fn formats_replace_the_book_rate() {
+        let rules = rules(RULES);
+        let mut order = pricing(1, 10.0);
+        rules.apply(&mut order, &destination("de", None), Some(EditionFormat::Ebook));
+        assert_eq!(lines(&order), vec![("book", "DE", 19.0, 10.0, 1.9)]);
+    }
+
+    #[test]
+    fn regions_fall_back_to_their_country() {
+        let rules = rules(RULES);
+        let rate = |country: &str, region: Option<&str>| rules.rate(&destination(country, region), |rates| rates.for_book(None));
+        assert_eq!(rate("CA", Some("on")), Some(("CA-ON".to_string(), 13.0)));
+        assert_eq!(rate("CA", Some("QC")), Some(("CA".to_string(), 5.0)));
+        assert_eq!(rate("CA", None), Some(("CA".to_string(), 5.0)));
+        assert_eq!(rate("us", Some("NY")), Some(("US-NY".to_string(), 4.0)));
+        // Regions without a rate of their own in a country without one aren't taxed
+        assert_eq!(rate("US", Some("TX")), None);
+        assert_eq!(rate("FR", None), None);
+    }
+
+    #[test]
+    fn destinations_without_a_rate_get_no_tax_line() {
+        let rules = rules(RULES);
+        let mut order = shipped(pricing(1, 10.0), 5.0);
+        rules.apply(&mut order, &destination("", None), None);
+        assert!(order.taxes.is_empty());
+        assert_eq!(order.total, 15.0);
+    }
+
+    #[test]
+    fn rates_must_be_percentages() {
+        let error = load("[countries.GB]\nrate = 120\n").unwrap_err();
+        assert_eq!(error, "The rates of GB must be percentages from 0 to 100, not 120");
+        let error = load("[countries.GBR]\nrate = 20\n").unwrap_err();
+        assert_eq!(error, "GBR isn't a two letter country code");
+    }
+}
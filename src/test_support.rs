@@ -0,0 +1,48 @@
+// Builders the unit tests of several modules share
+
+use crate::db::addresses::AddressFields;
+use crate::db::promotions::OrderPricing;
+use crate::shipping::ShippingQuote;
+
+pub fn address(line1: &str, city: &str, region: Option<&str>, postal_code: Option<&str>, country: &str) -> AddressFields {
+    return AddressFields {
+        line1: line1.to_string(),
+        line2: None,
+        city: city.to_string(),
+        region: region.map(str::to_string),
+        postal_code: postal_code.map(str::to_string),
+        country: country.to_string(),
+    };
+}
+
+// An address in the country and region, for rules that go by nothing else
+pub fn destination(country: &str, region: Option<&str>) -> AddressFields {
+    return address("1 Main St", "Springfield", region, None, country);
+}
+
+// Copies of book 1 coming to total, before shipping and tax
+pub fn pricing(quantity: i64, total: f64) -> OrderPricing {
+    return OrderPricing {
+        book_id: 1,
+        quantity,
+        unit_price: total / quantity as f64,
+        subtotal: total,
+        discounts: Vec::new(),
+        shipping: None,
+        taxes: Vec::new(),
+        total,
+    };
+}
+
+// The pricing shipped by the standard method at cost, added to its total
+pub fn shipped(mut pricing: OrderPricing, cost: f64) -> OrderPricing {
+    pricing.shipping = Some(ShippingQuote {
+        method: "standard".to_string(),
+        name: "Standard".to_string(),
+        cost,
+        earliest_delivery: "2026-10-21".to_string(),
+        latest_delivery: "2026-10-23".to_string(),
+    });
+    pricing.total += cost;
+    return pricing;
+}
@@ -0,0 +1,26 @@
+use caseless::Caseless;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{is_potential_mixed_script_confusable_char, RestrictionLevel, RestrictionLevelDetection};
+
+// The form titles, authors and names are stored in, so "Dune" typed as NFC or NFD is the same text
+pub fn nfc(input: &str) -> String {
+    return input.nfc().collect();
+}
+
+// Lookup key for titles, authors and names, compatibility characters like "ﬁ" are
+// decomposed and case is fully folded, so "DUNE" and "dune", or "STRASSE" and "straße", find the same book
+pub fn canonical_key(input: &str) -> String {
+    return input.nfkc().default_case_fold().nfkc().collect();
+}
+
+// Words mixing scripts in a way that lets one pass for another, such as a Cyrillic "е" in "Dune".
+// Latin mixed with Han, Hiragana, Katakana or Hangul is normal in Chinese, Japanese and Korean text
+// and isn't reported.
+pub fn mixed_script_confusables(input: &str) -> Vec<&str> {
+    return input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .filter(|word| !word.check_restriction_level(RestrictionLevel::HighlyRestrictive))
+        .filter(|word| word.chars().any(is_potential_mixed_script_confusable_char))
+        .collect();
+}
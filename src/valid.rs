@@ -0,0 +1,100 @@
+use rocket::data::{self, Data, FromData};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::Json;
+use serde::Deserialize;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use log::error;
+
+use crate::metrics;
+use crate::rate_limit;
+use crate::request_id::{self, CustomerId};
+use crate::validation::{validate, FieldError, Rules, Validate, ValidationConfig, ValidationErrors};
+
+// A JSON request body that passed the rules R, invalid bodies are rejected with 422 before the handler runs
+pub struct Valid<T, R> {
+    body: T,
+    rules: PhantomData<R>,
+}
+
+impl<T, R> Deref for Valid<T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.body
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T, R> FromData<'r> for Valid<T, R>
+where
+    T: Validate + Deserialize<'r> + Send + 'static,
+    R: Rules<T> + Send + 'static,
+{
+    type Error = ValidationErrors;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let mut body = match Json::<T>::from_data(req, data).await {
+            data::Outcome::Success(json) => json.into_inner(),
+            data::Outcome::Forward(data) => return data::Outcome::Forward(data),
+            data::Outcome::Failure((status, e)) => {
+                request_id::restore(req);
+                error!(target: "file", "Unreadable request body in {}: {}", R::FUNCTION, e);
+                let errors = ValidationErrors { errors: vec![FieldError::new("body", "malformed", &e.to_string())] };
+                metrics::record_validation_failure("body", "malformed");
+                req.local_cache(|| errors.clone());
+                return data::Outcome::Failure((status, errors));
+            }
+        };
+        if let Some(cid) = body.customer_id() {
+            req.local_cache(|| CustomerId(Some(cid)));
+        }
+        request_id::restore(req);
+        if let Some(cid) = body.customer_id() {
+            if rate_limit::limit_customer(req, cid).is_err() {
+                return data::Outcome::Failure((Status::TooManyRequests, ValidationErrors::default()));
+            }
+        }
+        body.normalize();
+
+        let config = req.rocket().state::<ValidationConfig>().cloned().unwrap_or_default();
+        let errors = validate::<T, R>(&body, &config);
+        if !errors.is_empty() {
+            for err in &errors {
+                metrics::record_validation_failure(&err.field, err.code);
+            }
+            let errors = ValidationErrors { errors };
+            // Kept for the 422 catcher, which has no other way to see why the guard failed
+            req.local_cache(|| errors.clone());
+            return data::Outcome::Failure((Status::UnprocessableEntity, errors));
+        }
+        data::Outcome::Success(Valid { body, rules: PhantomData })
+    }
+}
+
+// Kept in the request's local cache by other guards that fail with 400 or 422, the message to answer with
+pub struct GuardMessage(pub Option<String>);
+
+// Bodies Valid rejected are answered with every field error as JSON. Other failures keep the message their
+// guard left, or else get one for the status, as the catcher can't see why they failed.
+fn rejected(req: &Request, status: Status) -> Result<Json<ValidationErrors>, String> {
+    let errors = req.local_cache(ValidationErrors::default).clone();
+    if !errors.errors.is_empty() {
+        return Ok(Json(errors));
+    }
+    return match &req.local_cache(|| GuardMessage(None)).0 {
+        Some(message) => Err(message.clone()),
+        None => Err(format!("{}: the request could not be {}", status, if status == Status::BadRequest { "read" } else { "processed" })),
+    };
+}
+
+#[catch(400)]
+pub fn bad_request(req: &Request) -> Result<Json<ValidationErrors>, String> {
+    return rejected(req, Status::BadRequest);
+}
+
+#[catch(422)]
+pub fn unprocessable(req: &Request) -> Result<Json<ValidationErrors>, String> {
+    return rejected(req, Status::UnprocessableEntity);
+}
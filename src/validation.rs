@@ -0,0 +1,309 @@
+use rocket::fairing::{AdHoc, Fairing};
+use serde::{Deserialize, Serialize};
+use regex::Regex;
+use log::{error, warn};
+
+use crate::address::normalize_free_text;
+use crate::db::books::AVAILABILITY;
+use crate::redact::field_value;
+use crate::text;
+
+// Read from the `reject_confusables` key in Rocket.toml
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ValidationConfig {
+    // Mixed-script confusables are always logged, and rejected only when this is set
+    #[serde(default)]
+    reject_confusables: bool,
+}
+
+impl ValidationConfig {
+    pub fn fairing() -> impl Fairing {
+        AdHoc::config::<ValidationConfig>()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &'static str, message: &str) -> FieldError {
+        FieldError { field: field.to_string(), code, message: message.to_string() }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+pub enum Value<'a> {
+    Text(&'a Option<String>),
+    Amount(Option<f64>),
+    Id(Option<i64>),
+    Count(Option<i64>),
+    // Nested structs check themselves, see Validate::check
+    Nested(Option<&'a dyn Validate>),
+}
+
+impl Value<'_> {
+    fn is_present(&self) -> bool {
+        match self {
+            Value::Text(v) => v.is_some(),
+            Value::Amount(v) => v.is_some(),
+            Value::Id(v) => v.is_some(),
+            Value::Count(v) => v.is_some(),
+            Value::Nested(v) => v.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    Required,
+    // Required unless the named field is given instead
+    RequiredWithout(&'static str),
+    // Letters, numbers, spaces, periods and commas, no weird ones like 💜 or < or /
+    Alphanumeric,
+    // No words mixing scripts to look like another word, see text::mixed_script_confusables
+    SingleScript,
+    // A free text street address, see address::normalize_free_text
+    StreetAddress,
+    // A price or balance of form X.YY: 0 < X.YY <= 9999.99
+    Amount,
+    PositiveId,
+    // A number of things, 0 or more
+    Count,
+    // A date of form YYYY-MM-DD
+    Date,
+    // A UTC time of form YYYY-MM-DD HH:MM:SS, or a date for its start
+    Timestamp,
+    // An ISBN-13 without hyphens, with its check digit
+    Isbn,
+    // One of books::AVAILABILITY
+    Availability,
+}
+
+// The fields of a request body, each looked up by name from a set of Rules
+pub trait Validate {
+    // None for a name the body has no field of
+    fn value(&self, field: &str) -> Option<Value<'_>>;
+
+    // Normalizes the text fields before any rule runs, see normalize_text
+    fn normalize(&mut self) {}
+
+    // The customer the request acts for, added to its log lines, see request_id
+    fn customer_id(&self) -> Option<i64> {
+        None
+    }
+
+    // Checks that span several fields, run once every field has passed its rules
+    fn check(&self, _prefix: &str, _function: &str) -> Vec<FieldError> {
+        Vec::new()
+    }
+}
+
+// The rules one endpoint applies to a request body
+pub trait Rules<T: Validate> {
+    const FUNCTION: &'static str;
+    const FIELDS: &'static [(&'static str, &'static [Rule])];
+}
+
+pub fn validate<T: Validate, R: Rules<T>>(body: &T, config: &ValidationConfig) -> Vec<FieldError> {
+    return validate_fields(body, R::FIELDS, "", R::FUNCTION, config);
+}
+
+fn validate_fields<T: Validate + ?Sized>(body: &T, fields: &[(&str, &[Rule])], prefix: &str, function: &str,
+                                         config: &ValidationConfig) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    for (field, rules) in fields {
+        let name = format!("{}{}", prefix, field);
+        let Some(value) = body.value(field) else {
+            error!(target: "file", "Rule for unknown field {} in {}", name, function);
+            errors.push(FieldError::new(&name, "unknown_field", "This field cannot be checked"));
+            continue;
+        };
+        if !value.is_present() {
+            for rule in rules.iter() {
+                match rule {
+                    Rule::Required => errors.push(missing(&name, function, "Please provide a value")),
+                    Rule::RequiredWithout(other) if !body.value(other).is_some_and(|v| v.is_present()) => errors.push(
+                        missing(&name, function, &format!("Please provide {} or {}{}", name, prefix, other))),
+                    _ => (),
+                };
+            }
+            continue;
+        }
+        for rule in rules.iter() {
+            if let Some(err) = check_rule(*rule, &value, &name, function, config) {
+                errors.push(err);
+                // Later rules on the same field would only repeat the problem
+                break;
+            }
+        }
+        if let Value::Nested(Some(nested)) = value {
+            errors.extend(nested.check(&format!("{}.", name), function));
+        }
+    }
+    if errors.is_empty() {
+        errors.extend(body.check(prefix, function));
+    }
+    return errors;
+}
+
+fn missing(field: &str, function: &str, message: &str) -> FieldError {
+    error!(target: "file", "No {} given in {}", field, function);
+    return FieldError::new(field, "required", message);
+}
+
+fn check_rule(rule: Rule, value: &Value, field: &str, function: &str, config: &ValidationConfig) -> Option<FieldError> {
+    let result = match (rule, value) {
+        (Rule::Alphanumeric, Value::Text(Some(text))) => validate_alphanumeric_input(text, field, function),
+        (Rule::SingleScript, Value::Text(Some(text))) => validate_single_script(text, field, function, config.reject_confusables),
+        (Rule::StreetAddress, Value::Text(Some(text))) => normalize_free_text(text, field, function).map(|_| ()),
+        (Rule::Amount, Value::Amount(Some(amount))) => validate_amount(*amount, field, function),
+        (Rule::PositiveId, Value::Id(Some(id))) => validate_positive_id(*id, field, function),
+        (Rule::Count, Value::Count(Some(count))) => validate_count(*count, field, function),
+        (Rule::Date, Value::Text(Some(date))) => validate_date(date, field, function),
+        (Rule::Timestamp, Value::Text(Some(time))) => validate_timestamp(time, field, function),
+        (Rule::Isbn, Value::Text(Some(isbn))) => validate_isbn(isbn, field, function),
+        (Rule::Availability, Value::Text(Some(availability))) => validate_availability(availability, field, function),
+        _ => Ok(()),
+    };
+    return result.err();
+}
+
+// Allows only alphabetic and numeric input for these fields, no weird ones like 💜 or < or /
+pub fn validate_alphanumeric_input(input: &str, field: &str, function: &str) -> Result<(), FieldError> {
+    if input.is_empty() || input.chars().all(char::is_whitespace) {
+        error!(target: "file", "Empty input given in {}, field: {}", function, field);
+        return Err(FieldError::new(field, "empty", "Please do not input only empty space"));
+    }
+    let valid = input.chars().all(|x| x.is_alphanumeric() || x.is_whitespace() || x == '.' || x == ','); // Gets only 'word' characters and spaces
+
+    if !valid {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, field_value(field, input));
+        return Err(FieldError::new(field, "invalid_characters", "Please use only alphabet and numeric values"));
+    }
+    return Ok(());
+}
+
+pub fn validate_single_script(input: &str, field: &str, function: &str, reject: bool) -> Result<(), FieldError> {
+    let confusables = text::mixed_script_confusables(input);
+    if confusables.is_empty() {
+        return Ok(());
+    }
+    warn!(target: "file", "Mixed-script confusable {} in {}: {} ({})", field, function, field_value(field, input), field_value(field, confusables.join(", ")));
+    if reject {
+        return Err(FieldError::new(field, "mixed_script",
+            &format!("Please do not mix alphabets within a word: {}", confusables.join(", "))));
+    }
+    return Ok(());
+}
+
+// Prices and balances, decimals no greater than 10000
+pub fn validate_amount(amount: f64, field: &str, function: &str) -> Result<(), FieldError> {
+    if amount <= 0.00 {
+        error!(target: "file", "{} of {:.2} given in {}", field, amount, function);
+        return Err(FieldError::new(field, "not_positive", &format!("Please give a positive value (>0) for {}", field)));
+    }
+    // Adding .'s to integer amounts for regex
+    let mut amount_string = amount.to_string();
+    if !amount_string.contains('.') {
+        amount_string.push('.');
+    }
+
+    let re = Regex::new(r"^\d{1,4}\.\d{0,2}$").unwrap();
+    if !re.is_match(&amount_string) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, amount);
+        return Err(FieldError::new(field, "invalid_amount",
+            &format!("Please input a valid {} of form X.YY: 0 <= X <= 9999, 0 <= Y <= 9", field)));
+    }
+    return Ok(());
+}
+
+pub fn validate_positive_id(id: i64, field: &str, function: &str) -> Result<(), FieldError> {
+    if id <= 0 {
+        error!(target: "file", "Non-positive {} given in {}: {}", field, function, id);
+        return Err(FieldError::new(field, "not_positive", "Id numbers must be positive"));
+    }
+    return Ok(());
+}
+
+pub fn validate_count(count: i64, field: &str, function: &str) -> Result<(), FieldError> {
+    if count < 0 {
+        error!(target: "file", "Negative {} given in {}: {}", field, function, count);
+        return Err(FieldError::new(field, "negative", &format!("Please give 0 or more for {}", field)));
+    }
+    return Ok(());
+}
+
+pub fn validate_date(date: &str, field: &str, function: &str) -> Result<(), FieldError> {
+    let re = Regex::new(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])$").unwrap();
+    if !re.is_match(date) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, date);
+        return Err(FieldError::new(field, "invalid_date", &format!("Please input a valid {} of form YYYY-MM-DD", field)));
+    }
+    return Ok(());
+}
+
+pub fn valid_isbn13(isbn: &str) -> bool {
+    if isbn.len() != 13 || !isbn.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let sum: u32 = isbn.bytes().enumerate().map(|(i, b)| (b - b'0') as u32 * if i % 2 == 0 { 1 } else { 3 }).sum();
+    return sum.is_multiple_of(10);
+}
+
+pub fn validate_isbn(isbn: &str, field: &str, function: &str) -> Result<(), FieldError> {
+    if !valid_isbn13(isbn) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, isbn);
+        return Err(FieldError::new(field, "invalid_isbn", &format!("Please input a valid ISBN-13 for {}", field)));
+    }
+    return Ok(());
+}
+
+pub fn validate_availability(availability: &str, field: &str, function: &str) -> Result<(), FieldError> {
+    if !AVAILABILITY.contains(&availability) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, availability);
+        return Err(FieldError::new(field, "invalid_availability", &format!("Please give one of {} for {}", AVAILABILITY.join(", "), field)));
+    }
+    return Ok(());
+}
+
+// Seconds may be left out, and a T may stand for the space as in ISO 8601
+pub fn validate_timestamp(time: &str, field: &str, function: &str) -> Result<(), FieldError> {
+    let re = Regex::new(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])([ T]([01]\d|2[0-3]):[0-5]\d(:[0-5]\d)?)?$").unwrap();
+    if !re.is_match(time) {
+        error!(target: "file", "Invalid {} in {}: {}", field, function, time);
+        return Err(FieldError::new(field, "invalid_time", &format!("Please input a valid {} of form YYYY-MM-DD HH:MM:SS in UTC", field)));
+    }
+    return Ok(());
+}
+
+pub fn fix_whitespace(input: &str) -> String {
+    // Remove spaces at beginning and end of string
+    let temp_string = input.trim().to_string();
+    // Remove extra spaces within
+    let ex_sp_re = Regex::new(r"\s+").unwrap();
+    return ex_sp_re.replace_all(temp_string.as_str(), " ").to_string();
+}
+
+// Text is stored in NFC with whitespace collapsed
+pub fn normalize_text(input: &str) -> String {
+    return fix_whitespace(&text::nfc(input));
+}
+
+pub fn normalize_text_in(value: &mut Option<String>) {
+    if let Some(v) = value {
+        *v = normalize_text(v);
+    }
+}
+
+// Fields marked Required are always present in a body that passed validation
+pub fn required<T: Clone>(value: &Option<T>) -> T {
+    return value.clone().expect("required fields are checked by validate");
+}